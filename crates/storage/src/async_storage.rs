@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use crate::{ColumnMetadata, Result, Storage, StorageError, TableMetadata};
+
+/// An async front end to a `Storage` for use inside a tokio runtime.
+///
+/// The file I/O is still blocking: every call runs the matching `Storage`
+/// method on tokio's blocking thread pool with `spawn_blocking` and awaits
+/// it, so the page format, locking and parsing are the synchronous ones.
+/// Closures and arguments are moved to that thread, hence the `Send +
+/// 'static` bounds and owned paths.
+#[derive(Clone)]
+pub struct AsyncStorage {
+    storage: Arc<Storage>,
+}
+
+impl AsyncStorage {
+    pub fn new(storage: Storage) -> Self {
+        Self::from_arc(Arc::new(storage))
+    }
+
+    /// Shares a Storage that synchronous code keeps using too.
+    pub fn from_arc(storage: Arc<Storage>) -> Self {
+        AsyncStorage { storage }
+    }
+
+    pub fn storage(&self) -> &Arc<Storage> {
+        &self.storage
+    }
+
+    /// Runs `operation` on the blocking thread pool. A panic in it is resumed
+    /// in the awaiting task.
+    async fn run<F, T>(&self, operation: F) -> Result<T>
+    where
+        F: FnOnce(&Storage) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let storage = Arc::clone(&self.storage);
+        match tokio::task::spawn_blocking(move || operation(&storage)).await {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => Err(StorageError::Io(std::io::Error::other(err))),
+        }
+    }
+
+    pub async fn create_postgres_file(&self, file_path: impl Into<String>) -> Result<()> {
+        let file_path = file_path.into();
+        self.run(move |storage| storage.create_postgres_file(&file_path))
+            .await
+    }
+
+    pub async fn read_metadata<F, T>(
+        &self,
+        file_path: impl Into<String>,
+        parse_entry: F,
+    ) -> Result<Vec<T>>
+    where
+        F: Fn(&[u8], usize) -> Result<(T, usize)> + Send + 'static,
+        T: Send + 'static,
+    {
+        let file_path = file_path.into();
+        self.run(move |storage| storage.read_metadata(&file_path, parse_entry))
+            .await
+    }
+
+    pub async fn write_metadata<F>(
+        &self,
+        file_path: impl Into<String>,
+        entries: Vec<Vec<u8>>,
+        calculate_size: F,
+    ) -> Result<Vec<(u32, u16)>>
+    where
+        F: Fn(&[u8]) -> usize + Send + 'static,
+    {
+        let file_path = file_path.into();
+        self.run(move |storage| storage.write_metadata(&file_path, entries, calculate_size))
+            .await
+    }
+
+    pub async fn rewrite_metadata<F>(
+        &self,
+        file_path: impl Into<String>,
+        entries: Vec<Vec<u8>>,
+        calculate_size: F,
+    ) -> Result<Vec<(u32, u16)>>
+    where
+        F: Fn(&[u8]) -> usize + Send + 'static,
+    {
+        let file_path = file_path.into();
+        self.run(move |storage| storage.rewrite_metadata(&file_path, entries, calculate_size))
+            .await
+    }
+
+    pub async fn read_postgres_class(&self) -> Result<Vec<TableMetadata>> {
+        self.run(|storage| storage.read_postgres_class()).await
+    }
+
+    pub async fn write_postgres_class(&self, tables_metadata: Vec<TableMetadata>) -> Result<()> {
+        self.run(move |storage| storage.write_postgres_class(&tables_metadata))
+            .await
+    }
+
+    pub async fn replace_postgres_class(&self, tables_metadata: Vec<TableMetadata>) -> Result<()> {
+        self.run(move |storage| storage.replace_postgres_class(&tables_metadata))
+            .await
+    }
+
+    pub async fn update_postgres_class(
+        &self,
+        table_id: u32,
+        new_metadata: TableMetadata,
+    ) -> Result<()> {
+        self.run(move |storage| storage.update_postgres_class(table_id, &new_metadata))
+            .await
+    }
+
+    pub async fn delete_postgres_class(&self, table_id: u32) -> Result<()> {
+        self.run(move |storage| storage.delete_postgres_class(table_id))
+            .await
+    }
+
+    pub async fn read_postgres_attribute(&self) -> Result<Vec<ColumnMetadata>> {
+        self.run(|storage| storage.read_postgres_attribute()).await
+    }
+
+    pub async fn write_postgres_attribute(
+        &self,
+        columns_metadata: Vec<ColumnMetadata>,
+    ) -> Result<()> {
+        self.run(move |storage| storage.write_postgres_attribute(&columns_metadata))
+            .await
+    }
+
+    pub async fn replace_postgres_attribute(
+        &self,
+        columns_metadata: Vec<ColumnMetadata>,
+    ) -> Result<()> {
+        self.run(move |storage| storage.replace_postgres_attribute(&columns_metadata))
+            .await
+    }
+
+    pub async fn delete_postgres_attribute(&self, table_id: u32, column_id: u32) -> Result<()> {
+        self.run(move |storage| storage.delete_postgres_attribute(table_id, column_id))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_async_postgres_class_and_attribute_round_trip() {
+        let base_dir = crate::tests::temp_base_dir("async_catalog");
+        let storage = AsyncStorage::new(Storage::new(&base_dir));
+
+        let tables_metadata: Vec<TableMetadata> = ["accounts", "users", "orders"]
+            .iter()
+            .zip(1..)
+            .map(|(name, table_id)| TableMetadata {
+                table_id,
+                table_name: name.to_string(),
+            })
+            .collect();
+        let columns_metadata = vec![
+            ColumnMetadata {
+                column_id: 1,
+                table_id: 2,
+                column_name: "user_id".to_string(),
+                data_type: "INTEGER".to_string(),
+                is_nullable: false,
+                is_primary_key: false,
+                default: None,
+            },
+            ColumnMetadata {
+                column_id: 2,
+                table_id: 2,
+                column_name: "email".to_string(),
+                data_type: "VARCHAR".to_string(),
+                is_nullable: true,
+                is_primary_key: false,
+                default: None,
+            },
+        ];
+
+        storage
+            .write_postgres_class(tables_metadata.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.read_postgres_class().await.unwrap(),
+            tables_metadata
+        );
+
+        storage
+            .write_postgres_attribute(columns_metadata.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.read_postgres_attribute().await.unwrap(),
+            columns_metadata
+        );
+
+        // The sync API sees the same files.
+        assert_eq!(
+            storage.storage().read_postgres_class().unwrap(),
+            tables_metadata
+        );
+        let table_file_path = storage.storage().pg_class_path().to_string();
+        let sizes = storage
+            .read_metadata(table_file_path, |page, pointer| {
+                Ok((page.len() - pointer, page.len()))
+            })
+            .await
+            .unwrap();
+        assert_eq!(sizes, [4 + 2 + 8, 4 + 2 + 5, 4 + 2 + 6]);
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+
+use crate::page::{
+    item_ids, page_checksum, read_special, read_u16, write_item_id, write_special, ITEM_ID_SIZE,
+    PAGE_HEADER_SIZE,
+};
+use crate::ItemId;
+use crate::{DataFile, PageHeader, PageInit, Result, Storage, StorageError, TupleId};
+
+/// Each node keeps its level (0 for leaves) and the page number of its right
+/// sibling in the special space.
+const BTREE_SPECIAL_SIZE: u16 = 8;
+const NO_SIBLING: u32 = u32::MAX;
+
+/// The root always lives on page 0, so a split root is moved to a new page
+/// rather than replaced.
+const ROOT_PAGE: u32 = 0;
+
+/// A leaf item is the key, the heap page number and the slot; an internal item
+/// is the key and the child page number.
+const LEAF_ITEM_SIZE: usize = 10;
+const INTERNAL_ITEM_SIZE: usize = 8;
+
+/// A B-tree index file mapping u32 keys to tuples of a heap file. Keys may
+/// repeat; `search` returns every tuple stored under a key.
+///
+/// Nodes are ordinary slotted pages whose slot directory is kept in key order.
+/// The first key of an internal node is never compared, so its child holds
+/// every key below the second key. Pages go through the Storage's buffer pool
+/// and WAL like heap pages.
+pub struct Index<'a> {
+    storage: &'a Storage,
+    file_path: String,
+}
+
+/// A node decoded into its items: keys with the tuple they point at in a
+/// leaf, or with the child page number in an internal node.
+struct Node {
+    level: u16,
+    right_sibling: u32,
+    items: Vec<(u32, u64)>,
+}
+
+impl Node {
+    /// Items that fit on a page of `page_size` bytes.
+    fn capacity(&self, page_size: usize) -> usize {
+        let item_size = if self.level == 0 {
+            LEAF_ITEM_SIZE
+        } else {
+            INTERNAL_ITEM_SIZE
+        };
+        (page_size - BTREE_SPECIAL_SIZE as usize - PAGE_HEADER_SIZE) / (item_size + ITEM_ID_SIZE)
+    }
+
+    fn decode(page: &[u8], header: &PageHeader) -> Node {
+        let special = read_special(page);
+        let level = read_u16(special, 0);
+        let right_sibling = u32::from_le_bytes(special[4..8].try_into().unwrap());
+        let items = item_ids(page, header)
+            .into_iter()
+            .map(|item_id| {
+                let pointer = item_id.offset as usize;
+                let key = u32::from_le_bytes(page[pointer..pointer + 4].try_into().unwrap());
+                let value = if level == 0 {
+                    tuple_value(TupleId {
+                        page_no: u32::from_le_bytes(
+                            page[pointer + 4..pointer + 8].try_into().unwrap(),
+                        ),
+                        slot: read_u16(page, pointer + 8),
+                    })
+                } else {
+                    u32::from_le_bytes(page[pointer + 4..pointer + 8].try_into().unwrap()) as u64
+                };
+                (key, value)
+            })
+            .collect();
+        Node {
+            level,
+            right_sibling,
+            items,
+        }
+    }
+
+    /// Builds a fresh, checksummed page of `page_size` bytes holding the node.
+    /// The caller has checked that the items fit.
+    fn encode(&self, page_size: usize) -> Box<[u8]> {
+        let mut page = PageInit {
+            special_size: BTREE_SPECIAL_SIZE,
+            ..PageInit::default()
+        }
+        .build(page_size);
+        let mut header = PageHeader::decode(&page);
+        let mut special = [0u8; BTREE_SPECIAL_SIZE as usize];
+        special[..2].copy_from_slice(&self.level.to_le_bytes());
+        special[4..].copy_from_slice(&self.right_sibling.to_le_bytes());
+        write_special(&mut page, &special).unwrap();
+
+        for (slot, (key, value)) in (0..).zip(&self.items) {
+            let mut item = key.to_le_bytes().to_vec();
+            if self.level == 0 {
+                let tuple_id = value_tuple(*value);
+                item.extend_from_slice(&tuple_id.page_no.to_le_bytes());
+                item.extend_from_slice(&tuple_id.slot.to_le_bytes());
+            } else {
+                item.extend_from_slice(&(*value as u32).to_le_bytes());
+            }
+            header.higher -= item.len() as u16;
+            page[header.higher as usize..header.higher as usize + item.len()]
+                .copy_from_slice(&item);
+            write_item_id(
+                &mut page,
+                slot,
+                ItemId::normal(header.higher, item.len() as u16),
+            );
+            header.lower += ITEM_ID_SIZE as u16;
+        }
+        header.write_to(&mut page);
+        header.checksum = page_checksum(&page);
+        header.write_to(&mut page);
+        page
+    }
+
+    /// Index of the child to descend into for `key`: the last one whose key
+    /// is below it, or with `inclusive` the last one whose key is not above
+    /// it. The first child takes keys below every other key.
+    fn child_index(&self, key: u32, inclusive: bool) -> usize {
+        self.items[1..]
+            .iter()
+            .take_while(|(child_key, _)| {
+                if inclusive {
+                    *child_key <= key
+                } else {
+                    *child_key < key
+                }
+            })
+            .count()
+    }
+}
+
+fn tuple_value(tuple_id: TupleId) -> u64 {
+    (tuple_id.page_no as u64) << 16 | tuple_id.slot as u64
+}
+
+fn value_tuple(value: u64) -> TupleId {
+    TupleId {
+        page_no: (value >> 16) as u32,
+        slot: value as u16,
+    }
+}
+
+impl<'a> Index<'a> {
+    /// Creates the index file with an empty root leaf. Fails with
+    /// `AlreadyExists` if the file is already there.
+    pub fn create(storage: &'a Storage, file_path: &str) -> Result<Index<'a>> {
+        let root = Node {
+            level: 0,
+            right_sibling: NO_SIBLING,
+            items: Vec::new(),
+        };
+        storage.install_new_file(file_path, &root.encode(storage.page_size()))?;
+        storage.buffer_pool().discard_file(file_path);
+        Ok(Index {
+            storage,
+            file_path: file_path.to_string(),
+        })
+    }
+
+    /// Opens an existing index file, checking that its root is readable.
+    pub fn open(storage: &'a Storage, file_path: &str) -> Result<Index<'a>> {
+        let index = Index {
+            storage,
+            file_path: file_path.to_string(),
+        };
+        index.read_node(&mut storage.open_data_file(file_path)?, ROOT_PAGE)?;
+        Ok(index)
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    /// Adds `tuple_id` under `key`, splitting full nodes on the way back up.
+    pub fn insert(&self, key: u32, tuple_id: TupleId) -> Result<()> {
+        let mut file = self.storage.open_writable_data_file(&self.file_path)?;
+        let mut page_count = self.storage.page_count(&file)?;
+        let page_size = self.storage.page_size();
+        let mut pages = BTreeMap::new();
+
+        let mut path = Vec::new();
+        let mut page_no = ROOT_PAGE;
+        let mut node = self.read_node(&mut file, page_no)?;
+        while node.level > 0 {
+            let child_index = node.child_index(key, true);
+            let child_page_no = node.items[child_index].1 as u32;
+            path.push((page_no, node, child_index));
+            page_no = child_page_no;
+            node = self.read_node(&mut file, page_no)?;
+        }
+        let position = node.items.partition_point(|(item_key, _)| *item_key <= key);
+        node.items.insert(position, (key, tuple_value(tuple_id)));
+
+        loop {
+            if node.items.len() <= node.capacity(page_size) {
+                pages.insert(page_no, node.encode(page_size));
+                break;
+            }
+            let (split_key, right_page_no) = self.split(&mut node, &mut page_count, &mut pages);
+            match path.pop() {
+                Some((parent_page_no, mut parent, child_index)) => {
+                    pages.insert(page_no, node.encode(page_size));
+                    parent
+                        .items
+                        .insert(child_index + 1, (split_key, right_page_no as u64));
+                    page_no = parent_page_no;
+                    node = parent;
+                }
+                None => {
+                    // Move the left half off the root page and make the root
+                    // an internal node over both halves.
+                    let left_page_no = page_count;
+                    pages.insert(left_page_no, node.encode(page_size));
+                    let root = Node {
+                        level: node.level + 1,
+                        right_sibling: NO_SIBLING,
+                        items: vec![
+                            (node.items[0].0, left_page_no as u64),
+                            (split_key, right_page_no as u64),
+                        ],
+                    };
+                    pages.insert(ROOT_PAGE, root.encode(page_size));
+                    break;
+                }
+            }
+        }
+
+        // New pages sit past the end of the file, so writing in page order
+        // never leaves a hole.
+        for (page_no, mut page) in pages {
+            self.storage.write_page(&mut file, page_no, &mut page)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the upper half of `node` to a new page linked in as its right
+    /// sibling, and returns the first key of the new page with its number. The
+    /// caller writes `node` itself, which for the root is moved to a page of
+    /// its own.
+    fn split(
+        &self,
+        node: &mut Node,
+        page_count: &mut u32,
+        pages: &mut BTreeMap<u32, Box<[u8]>>,
+    ) -> (u32, u32) {
+        let right_page_no = *page_count;
+        *page_count += 1;
+        let right = Node {
+            level: node.level,
+            right_sibling: node.right_sibling,
+            items: node.items.split_off(node.items.len() / 2),
+        };
+        node.right_sibling = right_page_no;
+        pages.insert(right_page_no, right.encode(self.storage.page_size()));
+        (right.items[0].0, right_page_no)
+    }
+
+    /// Every tuple stored under `key`, in insertion order for equal keys.
+    pub fn search(&self, key: u32) -> Result<Vec<TupleId>> {
+        let mut file = self.storage.open_data_file(&self.file_path)?;
+        let mut node = self.read_node(&mut file, ROOT_PAGE)?;
+        // Equal keys may straddle a split, so start at the leftmost leaf that
+        // can hold the key and follow the siblings from there.
+        while node.level > 0 {
+            let child_page_no = node.items[node.child_index(key, false)].1 as u32;
+            node = self.read_node(&mut file, child_page_no)?;
+        }
+
+        let mut tuple_ids = Vec::new();
+        loop {
+            tuple_ids.extend(
+                node.items
+                    .iter()
+                    .filter(|(item_key, _)| *item_key == key)
+                    .map(|(_, value)| value_tuple(*value)),
+            );
+            let past_key = node.items.last().is_some_and(|(last, _)| *last > key);
+            if past_key || node.right_sibling == NO_SIBLING {
+                return Ok(tuple_ids);
+            }
+            node = self.read_node(&mut file, node.right_sibling)?;
+        }
+    }
+
+    fn read_node(&self, file: &mut DataFile, page_no: u32) -> Result<Node> {
+        let (page, header) = self.storage.read_page(file, page_no)?;
+        if header.special_space as usize != page.len() - BTREE_SPECIAL_SIZE as usize {
+            return Err(StorageError::CorruptPage {
+                page_no,
+                slot: None,
+                reason: format!(
+                    "special space {} does not hold B-tree node metadata",
+                    header.special_space
+                ),
+            });
+        }
+        Ok(Node::decode(&page, &header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_path;
+    use crate::PAGE_SIZE;
+
+    fn tuple(n: u32) -> TupleId {
+        TupleId {
+            page_no: n / 100,
+            slot: (n % 100) as u16,
+        }
+    }
+
+    #[test]
+    fn test_index_splits_leaves() {
+        let file_path = temp_path("btree_leaves");
+        let _ = std::fs::remove_file(&file_path);
+        let storage = Storage::new(std::env::temp_dir());
+        let index = Index::create(&storage, &file_path).unwrap();
+
+        // Keys arrive out of order, and key 700 repeats often enough to
+        // straddle a leaf split.
+        for n in 0..2000 {
+            let key = n * 7919 % 2000;
+            index.insert(key, tuple(key)).unwrap();
+        }
+        for n in 0..800 {
+            index.insert(700, tuple(10_000 + n)).unwrap();
+        }
+
+        let data = std::fs::read(&file_path).unwrap();
+        assert!(data.len() / PAGE_SIZE > 4);
+        let root = &data[..PAGE_SIZE];
+        assert_eq!(
+            Node::decode(root, &PageHeader::parse(root).unwrap()).level,
+            1
+        );
+
+        for key in [0, 1, 339, 340, 341, 1000, 1998, 1999] {
+            assert_eq!(index.search(key).unwrap(), vec![tuple(key)]);
+        }
+        let duplicates = index.search(700).unwrap();
+        assert_eq!(duplicates.len(), 801);
+        assert_eq!(duplicates[0], tuple(700));
+        assert_eq!(duplicates[800], tuple(10_799));
+        assert!(index.search(2000).unwrap().is_empty());
+
+        drop(index);
+        let index = Index::open(&storage, &file_path).unwrap();
+        assert_eq!(index.search(1234).unwrap(), vec![tuple(1234)]);
+        std::fs::remove_file(&file_path).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+/// Identifies a cached page by the path of its file and its page number.
+pub type PageKey = (String, u32);
+
+struct Frame {
+    page: Box<[u8]>,
+    dirty: bool,
+    last_used: u64,
+}
+
+/// A fixed number of cached pages with least-recently-used eviction.
+///
+/// The pool only tracks pages; `Storage` does the I/O. When the pool is full,
+/// `victim` names the page to evict so the caller can write it back if it is
+/// dirty before calling `remove`.
+pub struct BufferPool {
+    capacity: usize,
+    frames: HashMap<PageKey, Frame>,
+    clock: u64,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            frames: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns a copy of the cached page and marks it most recently used.
+    pub fn get(&mut self, file_path: &str, page_no: u32) -> Option<Box<[u8]>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.frames
+            .get_mut(&(file_path.to_string(), page_no))
+            .map(|frame| {
+                frame.last_used = clock;
+                frame.page.clone()
+            })
+    }
+
+    /// Whether the page is cached, without marking it used.
+    pub fn contains(&self, file_path: &str, page_no: u32) -> bool {
+        self.frames.contains_key(&(file_path.to_string(), page_no))
+    }
+
+    /// The page that has to go before `file_path`/`page_no` can be cached, if
+    /// the pool is full and does not already hold it.
+    pub fn victim(&self, file_path: &str, page_no: u32) -> Option<PageKey> {
+        if self.frames.len() < self.capacity
+            || self.frames.contains_key(&(file_path.to_string(), page_no))
+        {
+            return None;
+        }
+        self.frames
+            .iter()
+            .min_by_key(|(_, frame)| frame.last_used)
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Caches the page, replacing any cached copy. The pool must have room for
+    /// it; see `victim`.
+    pub fn put(&mut self, file_path: &str, page_no: u32, page: &[u8], dirty: bool) {
+        self.clock += 1;
+        let key = (file_path.to_string(), page_no);
+        debug_assert!(self.frames.contains_key(&key) || self.frames.len() < self.capacity);
+        self.frames.insert(
+            key,
+            Frame {
+                page: page.into(),
+                dirty,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Contents of the page if it is cached and dirty.
+    pub fn dirty_page(&self, key: &PageKey) -> Option<Box<[u8]>> {
+        self.frames
+            .get(key)
+            .filter(|frame| frame.dirty)
+            .map(|frame| frame.page.clone())
+    }
+
+    pub fn remove(&mut self, key: &PageKey) {
+        self.frames.remove(key);
+    }
+
+    /// Dirty pages of `file_path`, or of every file if `None`, in page order.
+    pub fn dirty_pages(&self, file_path: Option<&str>) -> Vec<(PageKey, Box<[u8]>)> {
+        let mut pages: Vec<(PageKey, Box<[u8]>)> = self
+            .frames
+            .iter()
+            .filter(|(key, frame)| frame.dirty && file_path.is_none_or(|path| key.0 == path))
+            .map(|(key, frame)| (key.clone(), frame.page.clone()))
+            .collect();
+        pages.sort_by(|a, b| a.0.cmp(&b.0));
+        pages
+    }
+
+    /// Marks the page clean once its contents are on disk.
+    pub fn mark_clean(&mut self, key: &PageKey) {
+        if let Some(frame) = self.frames.get_mut(key) {
+            frame.dirty = false;
+        }
+    }
+
+    /// Number of pages of `file_path` implied by the cached pages, which may
+    /// include pages not yet written to the file.
+    pub fn page_count(&self, file_path: &str) -> u32 {
+        self.frames
+            .keys()
+            .filter(|(path, _)| path == file_path)
+            .map(|(_, page_no)| page_no + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Drops every cached page of `file_path`, dirty or not.
+    pub fn discard_file(&mut self, file_path: &str) {
+        self.frames.retain(|(path, _), _| path != file_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PAGE_SIZE;
+
+    fn page(byte: u8) -> Box<[u8]> {
+        vec![byte; PAGE_SIZE].into_boxed_slice()
+    }
+
+    #[test]
+    fn test_buffer_pool_evicts_least_recently_used() {
+        let mut pool = BufferPool::new(2);
+        pool.put("a", 0, &page(1), false);
+        pool.put("a", 1, &page(2), true);
+        assert_eq!(pool.victim("a", 1), None);
+        assert_eq!(pool.get("a", 0), Some(page(1)));
+
+        let victim = pool.victim("b", 0).unwrap();
+        assert_eq!(victim, ("a".to_string(), 1));
+        assert_eq!(pool.dirty_page(&victim), Some(page(2)));
+        pool.remove(&victim);
+        pool.put("b", 0, &page(3), false);
+
+        assert_eq!(pool.victim("a", 1), Some(("a".to_string(), 0)));
+        assert_eq!(pool.dirty_page(&("a".to_string(), 0)), None);
+        pool.remove(&("a".to_string(), 0));
+        assert_eq!(pool.get("a", 1), None);
+        assert_eq!(pool.get("b", 0), Some(page(3)));
+    }
+
+    #[test]
+    fn test_buffer_pool_tracks_dirty_pages() {
+        let mut pool = BufferPool::new(4);
+        pool.put("b", 0, &page(1), true);
+        pool.put("a", 2, &page(2), true);
+        pool.put("a", 0, &page(3), false);
+        pool.put("a", 1, &page(4), true);
+
+        let keys: Vec<PageKey> = pool
+            .dirty_pages(None)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("a".to_string(), 1),
+                ("a".to_string(), 2),
+                ("b".to_string(), 0)
+            ]
+        );
+        assert_eq!(pool.page_count("a"), 3);
+
+        pool.mark_clean(&("a".to_string(), 1));
+        assert_eq!(pool.dirty_pages(Some("a")).len(), 1);
+        pool.discard_file("a");
+        assert_eq!(pool.page_count("a"), 0);
+        assert_eq!(pool.dirty_pages(None)[0].1, page(1));
+    }
+}
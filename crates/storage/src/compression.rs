@@ -0,0 +1,425 @@
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::page::PAGE_FLAG_COMPRESSED;
+use crate::{
+    stamp_checksum, write_page_to_disk, PageHeader, Result, StorageError, PAGE_HEADER_SIZE,
+};
+
+/// The unit file systems allocate space in, and so the least a compressed
+/// page has to save to be worth storing compressed.
+const FILE_SYSTEM_BLOCK: usize = 4096;
+
+const MIN_MATCH: usize = 4;
+/// The last bytes of a block are always literals, and the last match starts
+/// at least `MATCH_FIND_LIMIT` bytes before the end, as the format requires.
+const LAST_LITERALS: usize = 5;
+const MATCH_FIND_LIMIT: usize = 12;
+const HASH_LOG: u32 = 12;
+
+/// Compresses `input` as one LZ4 block, finding matches through a table of
+/// the last position each 4-byte sequence was seen at.
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut position = 0;
+    let match_limit = input.len().saturating_sub(MATCH_FIND_LIMIT);
+    while position < match_limit {
+        let sequence = read_u32(input, position);
+        let slot = (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize;
+        // Positions are stored plus one, so 0 marks an empty entry.
+        let candidate = table[slot].checked_sub(1);
+        table[slot] = position + 1;
+        let Some(candidate) = candidate.filter(|candidate| {
+            position - candidate <= u16::MAX as usize && read_u32(input, *candidate) == sequence
+        }) else {
+            position += 1;
+            continue;
+        };
+        let end = input.len() - LAST_LITERALS;
+        let mut length = MIN_MATCH;
+        while position + length < end && input[candidate + length] == input[position + length] {
+            length += 1;
+        }
+        write_sequence(
+            &mut output,
+            &input[anchor..position],
+            Some((position - candidate, length)),
+        );
+        position += length;
+        anchor = position;
+    }
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+/// Writes a token, the literals and, unless this is the last sequence, the
+/// match's offset and length.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], found: Option<(usize, usize)>) {
+    let match_code = found.map_or(0, |(_, length)| length - MIN_MATCH);
+    output.push(((literals.len().min(15) as u8) << 4) | match_code.min(15) as u8);
+    if literals.len() >= 15 {
+        write_length(output, literals.len() - 15);
+    }
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = found {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_code >= 15 {
+            write_length(output, match_code - 15);
+        }
+    }
+}
+
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        output.push(255);
+        length -= 255;
+    }
+    output.push(length as u8);
+}
+
+/// Decompresses an LZ4 block that must come to exactly `size` bytes, or
+/// returns `None` if it is malformed.
+pub(crate) fn decompress(input: &[u8], size: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(size);
+    let mut position = 0;
+    loop {
+        let token = *input.get(position)?;
+        position += 1;
+        let mut literal_count = (token >> 4) as usize;
+        if literal_count == 15 {
+            literal_count += read_length(input, &mut position)?;
+        }
+        let literals = input.get(position..position.checked_add(literal_count)?)?;
+        if output.len() + literals.len() > size {
+            return None;
+        }
+        output.extend_from_slice(literals);
+        position += literal_count;
+        if position == input.len() {
+            break;
+        }
+        let offset = u16::from_le_bytes(input.get(position..position + 2)?.try_into().ok()?);
+        position += 2;
+        let mut length = (token & 0x0f) as usize;
+        if length == 15 {
+            length += read_length(input, &mut position)?;
+        }
+        length += MIN_MATCH;
+        let offset = offset as usize;
+        if offset == 0 || offset > output.len() || output.len() + length > size {
+            return None;
+        }
+        // The match may overlap the bytes it produces, so it is copied a
+        // byte at a time.
+        let start = output.len() - offset;
+        for index in start..start + length {
+            output.push(output[index]);
+        }
+    }
+    (output.len() == size).then_some(output)
+}
+
+fn read_length(input: &[u8], position: &mut usize) -> Option<usize> {
+    let mut length = 0usize;
+    loop {
+        let byte = *input.get(*position)?;
+        *position += 1;
+        length = length.checked_add(byte as usize)?;
+        if byte != 255 {
+            return Some(length);
+        }
+    }
+}
+
+/// The slot contents for the page, whose checksum is already stamped, or
+/// `None` if compressing it does not free a file system block. The slot holds
+/// the page header with `PAGE_FLAG_COMPRESSED` set, the u32 length of the
+/// rest of the page compressed as an LZ4 block, and those bytes, so page
+/// numbers still map to the same offsets.
+fn compress_page(page: &[u8]) -> Option<Vec<u8>> {
+    let body = compress(&page[PAGE_HEADER_SIZE..]);
+    let stored_size = PAGE_HEADER_SIZE + 4 + body.len();
+    if stored_size.next_multiple_of(FILE_SYSTEM_BLOCK) >= page.len() {
+        return None;
+    }
+    let mut stored = Vec::with_capacity(stored_size);
+    stored.extend_from_slice(&page[..PAGE_HEADER_SIZE]);
+    let mut header = PageHeader::decode(page);
+    header.flags |= PAGE_FLAG_COMPRESSED as u16;
+    header.write_to(&mut stored);
+    stored.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    stored.extend_from_slice(&body);
+    Some(stored)
+}
+
+/// Stamps the page checksum and writes the page compressed to its slot, or
+/// as it is if it does not compress well enough to save space. The file
+/// system blocks the compressed page leaves unused are released as a hole
+/// where the file system supports that, which is where the space is saved.
+pub(crate) fn write_compressed_page(file: &mut File, page_no: u32, page: &mut [u8]) -> Result<()> {
+    stamp_checksum(page)?;
+    let Some(stored) = compress_page(page) else {
+        return write_page_to_disk(file, page_no, page);
+    };
+    let start = page_no as u64 * page.len() as u64;
+    let end = start + page.len() as u64;
+    file.seek(SeekFrom::Start(start))?;
+    file.write_all(&stored)?;
+    // A new last page still takes its whole slot, so the page count read off
+    // the file's length stays right.
+    if file.metadata()?.len() < end {
+        file.set_len(end)?;
+    }
+    let hole_start = start + stored.len().next_multiple_of(FILE_SYSTEM_BLOCK) as u64;
+    release_blocks(file, hole_start, end - hole_start);
+    Ok(())
+}
+
+/// Undoes `write_compressed_page` on a slot read from the file, leaving the
+/// page as it was written. Fails with `CorruptPage` if the compressed bytes
+/// do not decompress to the rest of a page.
+pub(crate) fn expand_page(slot: &mut [u8], page_no: u32) -> Result<()> {
+    let corrupt = |reason: &str| StorageError::CorruptPage {
+        page_no,
+        slot: None,
+        reason: reason.to_string(),
+    };
+    let body_start = PAGE_HEADER_SIZE + 4;
+    let length =
+        u32::from_le_bytes(slot[PAGE_HEADER_SIZE..body_start].try_into().unwrap()) as usize;
+    let body = slot
+        .get(body_start..body_start.saturating_add(length))
+        .ok_or_else(|| corrupt("compressed page runs past its slot"))?;
+    let rest = decompress(body, slot.len() - PAGE_HEADER_SIZE)
+        .ok_or_else(|| corrupt("compressed page does not decompress"))?;
+    slot[PAGE_HEADER_SIZE..].copy_from_slice(&rest);
+    let mut header = PageHeader::decode(slot);
+    header.flags &= !(PAGE_FLAG_COMPRESSED as u16);
+    header.write_to(slot);
+    Ok(())
+}
+
+/// Punches a hole over the bytes so the file system can free their blocks.
+/// Failing to is not an error, since the bytes are never read. Only 64-bit
+/// Linux is supported, where the offsets `fallocate` takes are 64 bits.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn release_blocks(file: &File, offset: u64, length: u64) {
+    use std::os::fd::AsRawFd;
+    use std::os::raw::c_int;
+
+    const FALLOC_FL_KEEP_SIZE: c_int = 0x01;
+    const FALLOC_FL_PUNCH_HOLE: c_int = 0x02;
+    extern "C" {
+        fn fallocate(fd: c_int, mode: c_int, offset: i64, len: i64) -> c_int;
+    }
+    if length == 0 {
+        return;
+    }
+    // SAFETY: fallocate only reads its integer arguments, and the descriptor
+    // stays open for the call.
+    unsafe {
+        fallocate(
+            file.as_raw_fd(),
+            FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE,
+            offset as i64,
+            length as i64,
+        );
+    }
+}
+
+/// Other platforms keep the bytes past a compressed page.
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+fn release_blocks(_file: &File, _offset: u64, _length: u64) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColumnDefinition, DataType, ScanOptions, Storage, PAGE_SIZE};
+
+    #[test]
+    fn test_lz4_round_trip() {
+        let mut noise = Vec::new();
+        let mut state = 0x2545_f491u32;
+        for _ in 0..5000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            noise.push(state as u8);
+        }
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcdabcdabcd".to_vec(),
+            vec![7; 10_000],
+            b"the same phrase, the same phrase, the same phrase!".repeat(40),
+            noise.clone(),
+            [&noise[..300], &vec![0; 700], &noise[..300]].concat(),
+        ];
+        for input in &inputs {
+            let compressed = compress(input);
+            assert_eq!(
+                decompress(&compressed, input.len()).as_deref(),
+                Some(&input[..])
+            );
+        }
+        assert!(compress(&inputs[3]).len() < 100);
+        assert!(compress(&noise).len() > noise.len());
+
+        // Truncated or mangled blocks are rejected rather than trusted.
+        let compressed = compress(&inputs[4]);
+        let size = inputs[4].len();
+        assert_eq!(decompress(&compressed[..compressed.len() - 1], size), None);
+        assert_eq!(decompress(&compressed, size - 1), None);
+        assert_eq!(decompress(&[0x0f, 1, 0], 19), None);
+    }
+
+    #[test]
+    fn test_compressed_pages_round_trip_and_save_space() {
+        let plain_dir = crate::tests::temp_base_dir("compression_plain");
+        let packed_dir = crate::tests::temp_base_dir("compression_packed");
+        let plain = Storage::new(&plain_dir);
+        let packed = Storage::new(&packed_dir)
+            .with_page_compression()
+            .with_buffer_pool_capacity(8);
+        let columns = [
+            ColumnDefinition {
+                name: "id".to_string(),
+                data_type: "INTEGER".to_string(),
+                is_nullable: false,
+                is_primary_key: false,
+                default: None,
+            },
+            ColumnDefinition {
+                name: "note".to_string(),
+                data_type: "VARCHAR".to_string(),
+                is_nullable: true,
+                is_primary_key: false,
+                default: None,
+            },
+        ];
+        let row = |id: i32| {
+            vec![
+                Some(DataType::Integer32(id)),
+                Some(DataType::String(
+                    format!("reading {} of sensor north-{}", id, id % 4).repeat(12),
+                )),
+            ]
+        };
+        let mut table_ids = Vec::new();
+        for storage in [&plain, &packed] {
+            let table_id = storage.create_table("readings", &columns).unwrap();
+            for id in 0..2500 {
+                storage.insert_row(table_id, &row(id)).unwrap();
+            }
+            storage.flush_all().unwrap();
+            table_ids.push(table_id);
+        }
+        let plain_path = plain.heap_path(table_ids[0]);
+        let packed_path = packed.heap_path(table_ids[1]);
+        let plain_file = std::fs::metadata(&plain_path).unwrap();
+        let packed_file = std::fs::metadata(&packed_path).unwrap();
+        // Page numbers keep their offsets, so the length is the same.
+        assert_eq!(plain_file.len(), packed_file.len());
+        assert!(plain_file.len() / PAGE_SIZE as u64 >= 100);
+        // Only 64-bit Linux releases the unused blocks.
+        #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(
+                packed_file.blocks() * 2 <= plain_file.blocks(),
+                "{} blocks compressed, {} plain",
+                packed_file.blocks(),
+                plain_file.blocks()
+            );
+        }
+        let stored = std::fs::read(&packed_path).unwrap();
+        assert!(PageHeader::decode(&stored).is_compressed());
+
+        // Reads expand the pages, with or without compression on the reader.
+        let rows = |storage: &Storage, table_id| -> Vec<Vec<Option<DataType>>> {
+            storage
+                .scan(table_id, ScanOptions::new())
+                .unwrap()
+                .map(std::result::Result::unwrap)
+                .collect()
+        };
+        let expected = rows(&plain, table_ids[0]);
+        assert_eq!(rows(&packed, table_ids[1]), expected);
+        let reader = Storage::new(&packed_dir).with_read_ahead(4);
+        assert_eq!(rows(&reader, table_ids[1]), expected);
+        assert!(reader.verify_file(&packed_path).unwrap().is_ok());
+        drop(reader);
+
+        // Updated pages are compressed again when written back.
+        let heap_path = packed.heap_path(table_ids[1]);
+        let tuple_id = crate::TupleId {
+            page_no: 3,
+            slot: 0,
+        };
+        packed.delete_tuple(&heap_path, tuple_id).unwrap();
+        packed.flush_all().unwrap();
+        let reader = Storage::new(&packed_dir);
+        assert_eq!(rows(&reader, table_ids[1]).len(), expected.len() - 1);
+        let page = &std::fs::read(&packed_path).unwrap()[3 * PAGE_SIZE..];
+        assert!(PageHeader::decode(page).is_compressed());
+        drop(reader);
+
+        // Pages that do not shrink, like the overflow pages of a string of
+        // noise, are stored as they are.
+        let mut state = 0x2545_f491u32;
+        let noise: String = (0..12_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                char::from_u32(0x4e00 + state % 0x5000).unwrap()
+            })
+            .collect();
+        let noisy = packed.create_table("noisy", &columns).unwrap();
+        let noisy_row = [Some(DataType::Integer32(1)), Some(DataType::String(noise))];
+        packed.insert_row(noisy, &noisy_row).unwrap();
+        packed.flush_all().unwrap();
+        let stored = std::fs::read(packed.heap_path(noisy)).unwrap();
+        let stats = packed.analyze(noisy).unwrap();
+        assert!(stats.overflow_page_count >= 4);
+        let raw_pages = stored
+            .chunks(PAGE_SIZE)
+            .filter(|page| !PageHeader::decode(page).is_compressed())
+            .count();
+        // The last overflow page is mostly empty, so it shrinks with the
+        // row's page.
+        assert_eq!(raw_pages as u32, stats.overflow_page_count - 1);
+        assert_eq!(rows(&Storage::new(&packed_dir), noisy), [noisy_row]);
+
+        // A damaged compressed page is reported, not expanded into garbage.
+        let mut damaged = std::fs::read(&packed_path).unwrap();
+        damaged[PAGE_HEADER_SIZE + 4] ^= 0xff;
+        damaged[PAGE_HEADER_SIZE + 5] ^= 0xff;
+        std::fs::write(&packed_path, &damaged).unwrap();
+        let reader = Storage::new(&packed_dir);
+        assert!(matches!(
+            reader
+                .scan(table_ids[1], ScanOptions::new())
+                .unwrap()
+                .next(),
+            Some(Err(StorageError::CorruptPage { page_no: 0, .. }))
+        ));
+        let report = reader.verify_file(&packed_path).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].page_no, Some(0));
+        let info = reader.dump_page(&packed_path, 0).unwrap();
+        assert!(info.header.is_compressed());
+        assert_eq!(info.anomalies[0], report.problems[0].reason);
+        drop(reader);
+        drop(packed);
+        for dir in [&plain_dir, &packed_dir] {
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
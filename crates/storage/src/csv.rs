@@ -0,0 +1,449 @@
+use std::borrow::Cow;
+use std::io::BufRead;
+
+use crate::{check_row, with_defaults, ColumnMetadata, DataType, Result, StorageError};
+
+/// How `Storage::copy_from_csv` and `copy_to_csv` read and write CSV. The
+/// default is comma-separated records without a header line, and a load that
+/// fails at the first bad record.
+#[derive(Debug, Clone, Copy)]
+pub struct CsvOptions {
+    pub(crate) delimiter: u8,
+    pub(crate) header: bool,
+    pub(crate) skip_bad_lines: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            header: false,
+            skip_bad_lines: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Separates fields with `delimiter` instead of a comma. A double quote
+    /// or a line break would not read back, so those fail with
+    /// `InvalidCsvDelimiter`.
+    pub fn with_delimiter(mut self, delimiter: u8) -> Result<Self> {
+        if matches!(delimiter, b'"' | b'\n' | b'\r') {
+            return Err(StorageError::InvalidCsvDelimiter(delimiter));
+        }
+        self.delimiter = delimiter;
+        Ok(self)
+    }
+
+    /// The first record names the columns: an import skips it, an export
+    /// writes it.
+    pub fn with_header(mut self) -> Self {
+        self.header = true;
+        self
+    }
+
+    /// An import leaves out the records that do not load and lists their
+    /// lines in `CsvImport::skipped_lines`, rather than failing.
+    pub fn with_bad_lines_skipped(mut self) -> Self {
+        self.skip_bad_lines = true;
+        self
+    }
+}
+
+/// What `Storage::copy_from_csv` loaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CsvImport {
+    pub rows_loaded: usize,
+    /// The line each skipped record started on, counting from 1.
+    pub skipped_lines: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Field {
+    bytes: Vec<u8>,
+    quoted: bool,
+}
+
+pub(crate) struct Record {
+    fields: Vec<Field>,
+    /// Lines the record took, more than one if a quoted field spans lines.
+    pub(crate) line_count: usize,
+    problem: Option<String>,
+}
+
+/// Reads the next record, or `None` at the end of the input. Fields may be
+/// quoted with double quotes, doubled inside to stand for one, and a quoted
+/// field may hold delimiters and line breaks. A malformed record is still
+/// read to its end, with its problem noted for `parse_row`.
+pub(crate) fn read_record(reader: &mut impl BufRead, delimiter: u8) -> Result<Option<Record>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut record = Record {
+        fields: Vec::new(),
+        line_count: 1,
+        problem: None,
+    };
+    let mut field = Field::default();
+    let mut in_quotes = false;
+    let mut after_quote = false;
+    let mut index = 0;
+    loop {
+        let Some(&byte) = line.get(index) else {
+            if !in_quotes {
+                break;
+            }
+            // The quoted field goes on on the next line.
+            line.clear();
+            index = 0;
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                record
+                    .problem
+                    .get_or_insert_with(|| "unterminated quoted field".to_string());
+                break;
+            }
+            record.line_count += 1;
+            continue;
+        };
+        index += 1;
+        if in_quotes {
+            if byte != b'"' {
+                field.bytes.push(byte);
+            } else if line.get(index) == Some(&b'"') {
+                field.bytes.push(b'"');
+                index += 1;
+            } else {
+                in_quotes = false;
+                after_quote = true;
+            }
+            continue;
+        }
+        match byte {
+            b'\n' => break,
+            b'\r' if line.get(index) == Some(&b'\n') => {}
+            _ if byte == delimiter => {
+                record.fields.push(std::mem::take(&mut field));
+                after_quote = false;
+            }
+            _ if after_quote => {
+                record.problem.get_or_insert_with(|| {
+                    "unexpected character after a closing quote".to_string()
+                });
+            }
+            b'"' if field.bytes.is_empty() && !field.quoted => {
+                in_quotes = true;
+                field.quoted = true;
+            }
+            b'"' => {
+                record
+                    .problem
+                    .get_or_insert_with(|| "quote inside an unquoted field".to_string());
+            }
+            _ => field.bytes.push(byte),
+        }
+    }
+    record.fields.push(field);
+    Ok(Some(record))
+}
+
+/// The row a record starting on `line` stands for, with the columns'
+/// defaults filled in and checked like a row for `insert_row`. Every problem
+/// fails with `InvalidCsv`.
+pub(crate) fn parse_row(
+    columns: &[ColumnMetadata],
+    record: Record,
+    line: usize,
+) -> Result<Vec<Option<DataType>>> {
+    let invalid = |column_name: Option<&str>, reason: String| StorageError::InvalidCsv {
+        line,
+        column_name: column_name.map(str::to_string),
+        reason,
+    };
+    if let Some(problem) = record.problem {
+        return Err(invalid(None, problem));
+    }
+    if record.fields.len() != columns.len() {
+        return Err(invalid(
+            None,
+            format!(
+                "expected {} fields, found {}",
+                columns.len(),
+                record.fields.len()
+            ),
+        ));
+    }
+    let values = columns
+        .iter()
+        .zip(record.fields)
+        .map(|(column, field)| {
+            parse_field(&column.data_type, field)
+                .map_err(|reason| invalid(Some(&column.column_name), reason))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let row = with_defaults(columns, &values)?;
+    check_row(columns, &row).map_err(|err| match err {
+        StorageError::NullViolation { column_name } => invalid(
+            Some(&column_name),
+            "NULL in a column that does not allow it".to_string(),
+        ),
+        StorageError::ValueTooLong {
+            ref column_name, ..
+        } => invalid(Some(column_name), err.to_string()),
+        err => invalid(None, err.to_string()),
+    })?;
+    Ok(row)
+}
+
+/// An unquoted field that is empty or `\N` is NULL.
+fn parse_field(data_type: &str, field: Field) -> std::result::Result<Option<DataType>, String> {
+    if !field.quoted && (field.bytes.is_empty() || field.bytes == b"\\N") {
+        return Ok(None);
+    }
+    let text = String::from_utf8(field.bytes).map_err(|_| "invalid UTF-8".to_string())?;
+    let invalid =
+        |err: &dyn std::fmt::Display| format!("invalid {} {:?}: {}", data_type, text, err);
+    let value = match DataType::from_type_name(data_type).map_err(|err| err.to_string())? {
+        DataType::String(_) => DataType::String(text),
+        DataType::Integer32(_) => DataType::Integer32(text.parse().map_err(|err| invalid(&err))?),
+        DataType::Float32(_) => DataType::Float32(text.parse().map_err(|err| invalid(&err))?),
+        DataType::Integer64(_) => DataType::Integer64(text.parse().map_err(|err| invalid(&err))?),
+        DataType::Float64(_) => DataType::Float64(text.parse().map_err(|err| invalid(&err))?),
+        DataType::Boolean(_) => match text.to_ascii_lowercase().as_str() {
+            "true" | "t" | "1" => DataType::Boolean(true),
+            "false" | "f" | "0" => DataType::Boolean(false),
+            _ => return Err(invalid(&"expected true or false")),
+        },
+    };
+    Ok(Some(value))
+}
+
+/// Formats the fields as a record, ending in a line feed. NULL is an empty
+/// field, which an empty string is told apart from by being quoted.
+pub(crate) fn format_record(fields: &[Option<Cow<'_, str>>], delimiter: u8) -> String {
+    let mut record = String::new();
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            record.push(delimiter as char);
+        }
+        let Some(text) = field else {
+            continue;
+        };
+        let needs_quotes = text.is_empty()
+            || *text == "\\N"
+            || text
+                .bytes()
+                .any(|byte| matches!(byte, b'"' | b'\n' | b'\r') || byte == delimiter);
+        if needs_quotes {
+            record.push('"');
+            record.push_str(&text.replace('"', "\"\""));
+            record.push('"');
+        } else {
+            record.push_str(text);
+        }
+    }
+    record.push('\n');
+    record
+}
+
+pub(crate) fn format_value(value: &DataType) -> Cow<'_, str> {
+    match value {
+        DataType::String(value) => Cow::Borrowed(value),
+        DataType::Integer32(value) => Cow::Owned(value.to_string()),
+        DataType::Float32(value) => Cow::Owned(value.to_string()),
+        DataType::Integer64(value) => Cow::Owned(value.to_string()),
+        DataType::Float64(value) => Cow::Owned(value.to_string()),
+        DataType::Boolean(value) => Cow::Owned(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::column;
+    use crate::{Storage, Wal};
+
+    #[test]
+    fn test_csv_round_trip() {
+        let base_dir = crate::tests::temp_base_dir("csv_round_trip");
+        let wal_path = crate::tests::temp_path("csv_round_trip_wal");
+        let _ = std::fs::remove_file(&wal_path);
+        let storage = Storage::new(&base_dir).with_wal(&wal_path).unwrap();
+        let columns = [
+            column("id", "INTEGER", false),
+            column("name", "VARCHAR", true),
+            column("score", "REAL", true),
+            column("total", "BIGINT", true),
+            column("ratio", "DOUBLE PRECISION", true),
+            column("done", "BOOLEAN", true),
+        ];
+        let source = storage.create_table("source", &columns).unwrap();
+        let names = [
+            "plain",
+            "a, b",
+            "line one\nline two\r\n",
+            "say \"hi\"",
+            "héllo wörld ✓ 日本語",
+            "",
+            "\\N",
+        ];
+        let mut rows = Vec::new();
+        for (id, name) in names.iter().enumerate() {
+            rows.push(vec![
+                Some(DataType::Integer32(id as i32)),
+                Some(DataType::String(name.to_string())),
+                Some(DataType::Float32(id as f32 / 3.0)),
+                Some(DataType::Integer64(-(id as i64) << 40)),
+                Some(DataType::Float64(0.1 * id as f64)),
+                Some(DataType::Boolean(id % 2 == 0)),
+            ]);
+        }
+        rows.push(vec![
+            Some(DataType::Integer32(100)),
+            None,
+            None,
+            None,
+            None,
+            None,
+        ]);
+        rows.push(vec![
+            Some(DataType::Integer32(101)),
+            Some(DataType::String("long ".repeat(3000))),
+            None,
+            None,
+            None,
+            None,
+        ]);
+        for row in &rows {
+            storage.insert_row(source, row).unwrap();
+        }
+
+        let mut csv = Vec::new();
+        let options = CsvOptions::new().with_header();
+        assert_eq!(storage.copy_to_csv(source, &mut csv, options).unwrap(), 9);
+        let text = String::from_utf8(csv.clone()).unwrap();
+        assert!(text.starts_with(
+            "id,name,score,total,ratio,done\n\
+             0,plain,0,0,0,true\n\
+             1,\"a, b\",0.33333334,-1099511627776,0.1,false\n\
+             2,\"line one\nline two\r\n\",0.6666667,-2199023255552,0.2,true\n\
+             3,\"say \"\"hi\"\"\",1,-3298534883328,0.30000000000000004,false\n"
+        ));
+        assert!(text.contains("\n5,\"\",1.6666666,"));
+        assert!(text.contains("\n6,\"\\N\",2,"));
+        assert!(text.contains("\n100,,,,,\n"));
+
+        let copy = storage.create_table("copy", &columns).unwrap();
+        let import = storage.copy_from_csv(copy, &csv[..], options).unwrap();
+        assert_eq!(import.rows_loaded, 9);
+        assert!(import.skipped_lines.is_empty());
+        let copied = storage.read_tuples(&storage.heap_path(copy)).unwrap();
+        assert_eq!(copied.len(), rows.len());
+        assert!(rows.iter().all(|row| copied.contains(row)));
+
+        // The rows went in a page at a time, each page written once.
+        let heap_path = storage.heap_path(copy);
+        let page_writes = Wal::open(&wal_path)
+            .unwrap()
+            .records()
+            .unwrap()
+            .filter(|record| record.as_ref().unwrap().1.file_path == heap_path)
+            .count();
+        assert_eq!(
+            page_writes as u32,
+            storage.analyze(copy).unwrap().page_count
+        );
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_bad_lines() {
+        let base_dir = crate::tests::temp_base_dir("csv_bad_lines");
+        let storage = Storage::new(&base_dir);
+        let table_id = storage
+            .create_table(
+                "items",
+                &[
+                    column("id", "INTEGER", false),
+                    column("label", "VARCHAR", true),
+                ],
+            )
+            .unwrap();
+        let input = "1,\"two\nlines\"\nx,bad id\n,no id\n1,repeated key\n4,\"open\" quote\n5\n6;semicolon\n7,fine\n";
+        let heap_path = storage.heap_path(table_id);
+
+        // By default the first bad record fails the load and nothing is stored.
+        match storage.copy_from_csv(table_id, input.as_bytes(), CsvOptions::new()) {
+            Err(StorageError::InvalidCsv {
+                line,
+                column_name,
+                reason,
+            }) => {
+                assert_eq!(line, 3);
+                assert_eq!(column_name.as_deref(), Some("id"));
+                assert!(reason.starts_with("invalid INTEGER \"x\""), "{}", reason);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(
+            storage.read_tuples(&heap_path).unwrap(),
+            Vec::<Vec<_>>::new()
+        );
+
+        let import = storage
+            .copy_from_csv(
+                table_id,
+                input.as_bytes(),
+                CsvOptions::new().with_bad_lines_skipped(),
+            )
+            .unwrap();
+        assert_eq!(import.rows_loaded, 2);
+        assert_eq!(import.skipped_lines, [3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            storage.read_tuples(&heap_path).unwrap(),
+            [
+                vec![
+                    Some(DataType::Integer32(1)),
+                    Some(DataType::String("two\nlines".to_string()))
+                ],
+                vec![
+                    Some(DataType::Integer32(7)),
+                    Some(DataType::String("fine".to_string()))
+                ],
+            ]
+        );
+
+        // Keys already stored count too, and another delimiter can be used.
+        let import = storage
+            .copy_from_csv(
+                table_id,
+                "7;again\n8;\"semi;colon\"\n".as_bytes(),
+                CsvOptions::new()
+                    .with_delimiter(b';')
+                    .unwrap()
+                    .with_bad_lines_skipped(),
+            )
+            .unwrap();
+        assert_eq!((import.rows_loaded, import.skipped_lines), (1, vec![1]));
+        for delimiter in [b'"', b'\n', b'\r'] {
+            assert!(matches!(
+                CsvOptions::new().with_delimiter(delimiter),
+                Err(StorageError::InvalidCsvDelimiter(found)) if found == delimiter
+            ));
+        }
+        assert!(matches!(
+            storage.copy_from_csv(table_id, "9,\"unterminated\n".as_bytes(), CsvOptions::new()),
+            Err(StorageError::InvalidCsv {
+                line: 1,
+                column_name: None,
+                ..
+            })
+        ));
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{Result, StorageError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataType {
+    String(String),
+    Integer32(i32),
+    Float32(f32),
+    Integer64(i64),
+    Float64(f64),
+    Boolean(bool),
+}
+
+const TAG_STRING: u8 = 0;
+const TAG_INTEGER32: u8 = 1;
+const TAG_FLOAT32: u8 = 2;
+const TAG_INTEGER64: u8 = 3;
+const TAG_FLOAT64: u8 = 4;
+const TAG_BOOLEAN: u8 = 5;
+
+/// Type names in tag order, as stored in `ColumnMetadata::data_type`.
+const TYPE_NAMES: [&str; 6] = [
+    "VARCHAR",
+    "INTEGER",
+    "REAL",
+    "BIGINT",
+    "DOUBLE PRECISION",
+    "BOOLEAN",
+];
+
+impl DataType {
+    /// The catalog name of the value's type.
+    pub fn type_name(&self) -> &'static str {
+        TYPE_NAMES[self.tag() as usize]
+    }
+
+    /// Returns the zero value of the named type, so callers can match on the
+    /// variant. Names are compared ignoring ASCII case.
+    pub fn from_type_name(type_name: &str) -> Result<DataType> {
+        let tag = TYPE_NAMES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(type_name))
+            .ok_or_else(|| StorageError::UnknownDataType(type_name.to_string()))?;
+        Ok(match tag as u8 {
+            TAG_STRING => DataType::String(String::new()),
+            TAG_INTEGER32 => DataType::Integer32(0),
+            TAG_FLOAT32 => DataType::Float32(0.0),
+            TAG_INTEGER64 => DataType::Integer64(0),
+            TAG_FLOAT64 => DataType::Float64(0.0),
+            _ => DataType::Boolean(false),
+        })
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            DataType::String(_) => TAG_STRING,
+            DataType::Integer32(_) => TAG_INTEGER32,
+            DataType::Float32(_) => TAG_FLOAT32,
+            DataType::Integer64(_) => TAG_INTEGER64,
+            DataType::Float64(_) => TAG_FLOAT64,
+            DataType::Boolean(_) => TAG_BOOLEAN,
+        }
+    }
+
+    /// Appends the value's payload. Numbers are little-endian, booleans a
+    /// single 0 or 1 byte, and strings are prefixed with their u16 byte length.
+    pub fn serialize(&self, buf: &mut Vec<u8>) {
+        match self {
+            DataType::String(value) => {
+                buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+                buf.extend_from_slice(value.as_bytes());
+            }
+            DataType::Integer32(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            DataType::Float32(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            DataType::Integer64(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            DataType::Float64(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            DataType::Boolean(value) => buf.push(*value as u8),
+        }
+    }
+
+    /// Decodes a value of the named type from the start of `bytes`, returning
+    /// it with the number of bytes it occupied.
+    pub fn deserialize(bytes: &[u8], type_name: &str) -> Result<(DataType, usize)> {
+        let value = match DataType::from_type_name(type_name)? {
+            DataType::String(_) => {
+                let length = u16::from_le_bytes(take(bytes, 0, type_name)?) as usize;
+                let payload = bytes
+                    .get(2..2 + length)
+                    .ok_or_else(|| truncated(type_name))?;
+                DataType::String(String::from_utf8(payload.to_vec())?)
+            }
+            DataType::Integer32(_) => {
+                DataType::Integer32(i32::from_le_bytes(take(bytes, 0, type_name)?))
+            }
+            DataType::Float32(_) => {
+                DataType::Float32(f32::from_le_bytes(take(bytes, 0, type_name)?))
+            }
+            DataType::Integer64(_) => {
+                DataType::Integer64(i64::from_le_bytes(take(bytes, 0, type_name)?))
+            }
+            DataType::Float64(_) => {
+                DataType::Float64(f64::from_le_bytes(take(bytes, 0, type_name)?))
+            }
+            DataType::Boolean(_) => match take::<1>(bytes, 0, type_name)? {
+                [0] => DataType::Boolean(false),
+                [1] => DataType::Boolean(true),
+                [other] => {
+                    return Err(StorageError::CorruptValue {
+                        reason: format!("boolean byte {} is neither 0 nor 1", other),
+                    })
+                }
+            },
+        };
+        let size = match &value {
+            DataType::String(value) => 2 + value.len(),
+            DataType::Integer32(_) | DataType::Float32(_) => 4,
+            DataType::Integer64(_) | DataType::Float64(_) => 8,
+            DataType::Boolean(_) => 1,
+        };
+        Ok((value, size))
+    }
+
+    /// Appends the value as a one-byte type tag followed by its payload.
+    pub(crate) fn encode_tagged(&self, data: &mut Vec<u8>) {
+        data.push(self.tag());
+        self.serialize(data);
+    }
+
+    /// Decodes a tagged value at `offset`, returning it with the offset just
+    /// past it.
+    pub(crate) fn decode_tagged(page: &[u8], offset: usize) -> Result<(DataType, usize)> {
+        let [tag] = take::<1>(page, offset, "field tag")?;
+        let type_name = TYPE_NAMES
+            .get(tag as usize)
+            .ok_or_else(|| StorageError::CorruptValue {
+                reason: format!("unknown field tag {}", tag),
+            })?;
+        let (value, size) = DataType::deserialize(&page[offset + 1..], type_name)?;
+        Ok((value, offset + 1 + size))
+    }
+
+    /// Like `decode_tagged`, but only returns the offset past the value.
+    /// Nothing is allocated and a string's bytes are not checked for UTF-8.
+    pub(crate) fn skip_tagged(page: &[u8], offset: usize) -> Result<usize> {
+        let [tag] = take::<1>(page, offset, "field tag")?;
+        let size = match tag {
+            TAG_STRING => 2 + u16::from_le_bytes(take(page, offset + 1, "VARCHAR")?) as usize,
+            TAG_INTEGER32 | TAG_FLOAT32 => 4,
+            TAG_INTEGER64 | TAG_FLOAT64 => 8,
+            TAG_BOOLEAN => 1,
+            _ => {
+                return Err(StorageError::CorruptValue {
+                    reason: format!("unknown field tag {}", tag),
+                })
+            }
+        };
+        let end = offset + 1 + size;
+        if end > page.len() {
+            return Err(truncated(TYPE_NAMES[tag as usize]));
+        }
+        Ok(end)
+    }
+}
+
+pub(crate) fn take<const N: usize>(bytes: &[u8], offset: usize, what: &str) -> Result<[u8; N]> {
+    bytes
+        .get(offset..offset + N)
+        .map(|slice| slice.try_into().unwrap())
+        .ok_or_else(|| truncated(what))
+}
+
+pub(crate) fn truncated(what: &str) -> StorageError {
+    StorageError::CorruptValue {
+        reason: format!("truncated {} value", what),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_type_round_trip() {
+        let values = [
+            DataType::String(String::new()),
+            DataType::String("héllo".to_string()),
+            DataType::Integer32(i32::MIN),
+            DataType::Integer32(-1),
+            DataType::Integer32(i32::MAX),
+            DataType::Float32(-0.0),
+            DataType::Float32(f32::MIN_POSITIVE),
+            DataType::Float32(f32::INFINITY),
+            DataType::Integer64(i64::MIN),
+            DataType::Integer64(-42),
+            DataType::Integer64(i64::MAX),
+            DataType::Float64(f64::NEG_INFINITY),
+            DataType::Float64(f64::EPSILON),
+            DataType::Float64(-1.5e300),
+            DataType::Boolean(false),
+            DataType::Boolean(true),
+        ];
+        for value in values {
+            let mut buf = vec![];
+            value.serialize(&mut buf);
+            buf.push(0xFF);
+            let (decoded, size) = DataType::deserialize(&buf, value.type_name()).unwrap();
+            assert_eq!(decoded, value);
+            assert_eq!(size, buf.len() - 1);
+
+            let mut tagged = vec![0xAA];
+            value.encode_tagged(&mut tagged);
+            assert_eq!(DataType::skip_tagged(&tagged, 1).unwrap(), tagged.len());
+            assert!(DataType::skip_tagged(&tagged[..tagged.len() - 1], 1).is_err());
+            assert_eq!(
+                DataType::decode_tagged(&tagged, 1).unwrap(),
+                (value, tagged.len())
+            );
+        }
+    }
+
+    #[test]
+    fn test_data_type_names() {
+        for name in TYPE_NAMES {
+            assert_eq!(DataType::from_type_name(name).unwrap().type_name(), name);
+        }
+        assert_eq!(
+            DataType::from_type_name("bigint").unwrap(),
+            DataType::Integer64(0)
+        );
+        assert!(matches!(
+            DataType::from_type_name("DECIMAL"),
+            Err(StorageError::UnknownDataType(name)) if name == "DECIMAL"
+        ));
+    }
+
+    #[test]
+    fn test_data_type_rejects_malformed_bytes() {
+        assert!(matches!(
+            DataType::deserialize(&[1, 2, 3], "BIGINT"),
+            Err(StorageError::CorruptValue { .. })
+        ));
+        assert!(matches!(
+            DataType::deserialize(&[5, 0, b'a'], "VARCHAR"),
+            Err(StorageError::CorruptValue { .. })
+        ));
+        assert!(matches!(
+            DataType::deserialize(&[1, 0, 0xFF], "VARCHAR"),
+            Err(StorageError::InvalidUtf8(_))
+        ));
+        assert!(matches!(
+            DataType::deserialize(&[2], "BOOLEAN"),
+            Err(StorageError::CorruptValue { .. })
+        ));
+        assert!(matches!(
+            DataType::decode_tagged(&[9, 0, 0, 0, 0], 0),
+            Err(StorageError::CorruptValue { .. })
+        ));
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+
+use crate::page::{page_checksum, read_item_id, ITEM_ID_SIZE, PAGE_HEADER_SIZE};
+use crate::{ItemId, ItemStatus, PageHeader};
+
+/// Bytes of an entry shown in the report before it is cut short.
+const HEX_PREVIEW_BYTES: usize = 16;
+
+/// What `Storage::dump_page` makes of a page: its header, slots and whatever
+/// looks wrong with them. Nothing is validated before decoding, so a page
+/// that does not read still gets a report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageInfo {
+    pub page_no: u32,
+    pub header: PageHeader,
+    /// The checksum the page's bytes have, to compare with the stored one.
+    pub computed_checksum: u16,
+    pub free_space: usize,
+    pub slots: Vec<SlotInfo>,
+    /// Problems found on the page, described in words.
+    pub anomalies: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotInfo {
+    pub slot: u16,
+    pub item_id: ItemId,
+    /// The entry's bytes, for a slot with storage that lies within the page.
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl PageInfo {
+    pub(crate) fn new(page: &[u8], page_no: u32) -> PageInfo {
+        let header = PageHeader::decode(page);
+        let mut anomalies = Vec::new();
+        match header.check_format() {
+            Err(err) => anomalies.push(err.to_string()),
+            Ok(()) if header.page_size as usize != page.len() => anomalies.push(format!(
+                "header gives a page size of {}, but the page was read as {} bytes",
+                header.page_size,
+                page.len()
+            )),
+            Ok(()) => {}
+        }
+        let computed_checksum = page_checksum(page);
+        if header.has_checksum() && header.checksum != computed_checksum {
+            anomalies.push(format!(
+                "checksum mismatch: stored {:#06x}, computed {:#06x}",
+                header.checksum, computed_checksum
+            ));
+        }
+        let invariants = header.check_invariants();
+        if let Err(reason) = &invariants {
+            anomalies.push(reason.clone());
+        }
+
+        let max_slots = (page.len() - PAGE_HEADER_SIZE) / ITEM_ID_SIZE;
+        let slot_count = header.slot_count().min(max_slots);
+        let mut slots = Vec::with_capacity(slot_count);
+        for slot in 0..slot_count as u16 {
+            let item_id = read_item_id(page, slot);
+            let start = item_id.offset as usize;
+            let end = start + item_id.length as usize;
+            let in_page = end <= page.len();
+            let problem = match &invariants {
+                Ok(()) => header.check_item_id(&item_id).err(),
+                Err(_) if item_id.has_storage() && !in_page => Some(format!(
+                    "entry of {} bytes at offset {} runs past the end of the page",
+                    item_id.length, start
+                )),
+                Err(_) => None,
+            };
+            if let Some(reason) = problem {
+                anomalies.push(format!("slot {}: {}", slot, reason));
+            }
+            slots.push(SlotInfo {
+                slot,
+                item_id,
+                bytes: (item_id.has_storage() && in_page).then(|| page[start..end].to_vec()),
+            });
+        }
+
+        let mut stored: Vec<&SlotInfo> = slots
+            .iter()
+            .filter(|slot| slot.bytes.as_ref().is_some_and(|bytes| !bytes.is_empty()))
+            .collect();
+        stored.sort_by_key(|slot| slot.item_id.offset);
+        for pair in stored.windows(2) {
+            let (first, second) = (&pair[0].item_id, &pair[1].item_id);
+            if first.offset + first.length > second.offset {
+                anomalies.push(format!(
+                    "slots {} and {} overlap",
+                    pair[0].slot, pair[1].slot
+                ));
+            }
+        }
+
+        PageInfo {
+            page_no,
+            header,
+            computed_checksum,
+            free_space: header.free_space(),
+            slots,
+            anomalies,
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+impl fmt::Display for PageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.header;
+        writeln!(f, "page {}", self.page_no)?;
+        writeln!(f, "  lsn:           {}", header.lsn)?;
+        writeln!(
+            f,
+            "  checksum:      {:#06x} (computed {:#06x})",
+            header.checksum, self.computed_checksum
+        )?;
+        let mut flag_names = Vec::new();
+        if header.has_checksum() {
+            flag_names.push("has checksum");
+        }
+        if header.is_overflow() {
+            flag_names.push("overflow");
+        }
+        write!(
+            f,
+            "  flags:         {:#06x} (version {}",
+            header.flags,
+            header.version()
+        )?;
+        for name in flag_names {
+            write!(f, ", {}", name)?;
+        }
+        writeln!(f, ")")?;
+        writeln!(f, "  lower:         {}", header.lower)?;
+        writeln!(f, "  higher:        {}", header.higher)?;
+        writeln!(f, "  special space: {}", header.special_space)?;
+        writeln!(f, "  magic:         {:#010x}", header.magic)?;
+        writeln!(f, "  page size:     {}", header.page_size)?;
+        writeln!(f, "  free space:    {}", self.free_space)?;
+        writeln!(f, "  slots:         {}", self.slots.len())?;
+        for slot in &self.slots {
+            let item_id = &slot.item_id;
+            let status = match item_id.status {
+                ItemStatus::Unused => "unused",
+                ItemStatus::Normal => "normal",
+                ItemStatus::Redirect => "redirect",
+                ItemStatus::Dead => "dead",
+            };
+            write!(
+                f,
+                "  [{}] {} offset {} length {}",
+                slot.slot, status, item_id.offset, item_id.length
+            )?;
+            if let Some(bytes) = &slot.bytes {
+                write!(f, ":")?;
+                for byte in bytes.iter().take(HEX_PREVIEW_BYTES) {
+                    write!(f, " {:02x}", byte)?;
+                }
+                if bytes.len() > HEX_PREVIEW_BYTES {
+                    write!(f, " ...")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        for anomaly in &self.anomalies {
+            writeln!(f, "  anomaly: {}", anomaly)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::write_item_id;
+    use crate::{Storage, TupleId, PAGE_SIZE};
+
+    #[test]
+    fn test_dump_page_report() {
+        let file_path = crate::tests::temp_path("dump_page");
+        let _ = std::fs::remove_file(&file_path);
+        let storage = Storage::new(std::env::temp_dir());
+        storage.create_postgres_file(&file_path).unwrap();
+        storage
+            .write_metadata(
+                &file_path,
+                vec![b"first".to_vec(), (0..20).collect(), b"third".to_vec()],
+                |entry| entry.len(),
+            )
+            .unwrap();
+        storage
+            .delete_entry(
+                &file_path,
+                TupleId {
+                    page_no: 0,
+                    slot: 0,
+                },
+            )
+            .unwrap();
+
+        let info = storage.dump_page(&file_path, 0).unwrap();
+        assert!(info.anomalies.is_empty());
+        assert_eq!(info.slot_count(), 3);
+        assert_eq!(info.slots[1].bytes, Some((0..20).collect()));
+        assert_eq!(
+            info.to_string(),
+            "\
+page 0
+  lsn:           0
+  checksum:      0x2a40 (computed 0x2a40)
+  flags:         0x0401 (version 4, has checksum)
+  lower:         36
+  higher:        8162
+  special space: 8192
+  magic:         0x44525352
+  page size:     8192
+  free space:    8126
+  slots:         3
+  [0] dead offset 8187 length 5: 66 69 72 73 74
+  [1] normal offset 8167 length 20: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f ...
+  [2] normal offset 8162 length 5: 74 68 69 72 64
+"
+        );
+        std::fs::remove_file(&file_path).unwrap();
+        let _ = std::fs::remove_file(crate::FreeSpaceMap::path_for(&file_path));
+    }
+
+    #[test]
+    fn test_dump_page_reports_anomalies() {
+        let mut page = crate::PageInit::default().build(PAGE_SIZE);
+        write_item_id(&mut page, 0, ItemId::normal(8180, 12));
+        write_item_id(&mut page, 1, ItemId::normal(8186, 6));
+        write_item_id(&mut page, 2, ItemId::normal(8190, 100));
+        let mut header = PageHeader::decode(&page);
+        header.lower = 36;
+        header.higher = 8180;
+        header.magic = 0x1234_5678;
+        header.write_to(&mut page);
+
+        let info = PageInfo::new(&page, 7);
+        assert_eq!(info.slots[2].bytes, None);
+        assert_eq!(
+            info.anomalies,
+            [
+                "Not a storage file: page magic 0x12345678, expected 0x44525352".to_string(),
+                format!(
+                    "checksum mismatch: stored {:#06x}, computed {:#06x}",
+                    info.header.checksum, info.computed_checksum
+                ),
+                "slot 2: entry of 100 bytes at offset 8190 runs past special space 8192"
+                    .to_string(),
+                "slots 0 and 1 overlap".to_string(),
+            ]
+        );
+        assert!(info
+            .to_string()
+            .ends_with("  anomaly: slots 0 and 1 overlap\n"));
+
+        // Bytes that are not a page at all still get a report.
+        let garbage = [0xFF; PAGE_SIZE];
+        let info = PageInfo::new(&garbage, 0);
+        assert_eq!(
+            info.slot_count(),
+            (PAGE_SIZE - PAGE_HEADER_SIZE) / ITEM_ID_SIZE
+        );
+        assert!(info.anomalies[0].starts_with("Not a storage file"));
+        assert!(info.slots.iter().all(|slot| slot.bytes.is_none()));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::DataType;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    /// The page cannot hold `needed` more bytes; only `available` are free.
+    /// The entry may still fit on another page.
+    PageFull {
+        needed: usize,
+        available: usize,
+    },
+    /// The entry is larger than an empty page can hold, so no page will fit it.
+    EntryTooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// `size` bytes do not fit in the `available` bytes of a page's special
+    /// space, or a special space of `size` bytes leaves no room for entries.
+    SpecialSpaceOverflow {
+        size: usize,
+        available: usize,
+    },
+    NotFound(String),
+    DuplicateTableName(String),
+    /// The table already has a column with this name.
+    DuplicateColumnName(String),
+    /// Entry `index` of a batch written to a catalog file has the same `key`
+    /// as an entry already there or an earlier one in the batch.
+    DuplicateCatalogEntry {
+        index: usize,
+        key: String,
+    },
+    InvalidUtf8(std::string::FromUtf8Error),
+    UnknownDataType(String),
+    ArityMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// A row with more fields than `MAX_TUPLE_FIELDS`, which its tuple's
+    /// field count cannot hold.
+    TooManyFields {
+        count: usize,
+        max: usize,
+    },
+    NullViolation {
+        column_name: String,
+    },
+    /// The transaction id is reserved and cannot be used by a transaction.
+    InvalidXid(u32),
+    /// A record of CSV input that does not load, by the line it starts on
+    /// and the column at fault, if the problem lies with one.
+    InvalidCsv {
+        line: usize,
+        column_name: Option<String>,
+        reason: String,
+    },
+    /// A delimiter `CsvOptions::with_delimiter` does not accept.
+    InvalidCsvDelimiter(u8),
+    /// PostgreSQL binary COPY data that does not load, by the tuple at fault
+    /// counting from 1, or `None` for the header and trailer, and the column,
+    /// if the problem lies with one.
+    InvalidPgCopy {
+        tuple: Option<usize>,
+        column_name: Option<String>,
+        reason: String,
+    },
+    /// A stored row already has these values in the primary key columns.
+    UniqueViolation {
+        column_names: Vec<String>,
+        values: Vec<DataType>,
+    },
+    /// The value is not of the column's declared type.
+    TypeMismatch {
+        column_name: String,
+        expected: &'static str,
+        actual: &'static str,
+    },
+    /// The value is longer than the column can store.
+    ValueTooLong {
+        column_name: String,
+        length: usize,
+        max: usize,
+    },
+    /// Stored bytes do not decode as a value of their type.
+    CorruptValue {
+        reason: String,
+    },
+    ParserPanicked {
+        page: u32,
+        slot: u16,
+        message: String,
+    },
+    ChecksumMismatch {
+        page_no: u32,
+        stored: u16,
+        computed: u16,
+    },
+    InvalidPageHeader {
+        reason: String,
+    },
+    /// The page, or with `slot` set the entry in that slot, does not decode.
+    CorruptPage {
+        page_no: u32,
+        slot: Option<u16>,
+        reason: String,
+    },
+    CorruptWalRecord {
+        lsn: u64,
+        reason: String,
+    },
+    /// The control file holding the id counters does not decode.
+    CorruptControlFile {
+        reason: String,
+    },
+    /// The page does not carry this engine's magic number, so the file is not
+    /// one of ours or predates the magic.
+    InvalidFormat {
+        magic: u32,
+    },
+    /// The page was written in a layout version this library does not read.
+    UnsupportedVersion {
+        found: u8,
+        supported: u8,
+    },
+    /// A page size `Storage::with_page_size` does not accept.
+    InvalidPageSize(usize),
+    /// The file was written with `found`-byte pages, but the Storage reads
+    /// pages of `expected` bytes.
+    PageSizeMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// The file is locked by another Storage or process, and the Storage was
+    /// set to `LockWait::Fail`.
+    FileLocked(String),
+    /// A modification was attempted on a Storage opened with
+    /// `AccessMode::ReadOnly`.
+    ReadOnly,
+    /// A write transaction is open on the Storage, and only it may write
+    /// until it commits or rolls back.
+    TransactionInProgress,
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io(err) => write!(f, "I/O error: {}", err),
+            StorageError::PageFull { needed, available } => write!(
+                f,
+                "Insufficient space in page: needed {} bytes, {} available",
+                needed, available
+            ),
+            StorageError::EntryTooLarge { size, max } => write!(
+                f,
+                "Entry of {} bytes exceeds the {}-byte limit of a page",
+                size, max
+            ),
+            StorageError::SpecialSpaceOverflow { size, available } => write!(
+                f,
+                "{} bytes do not fit in the {} bytes available for special space",
+                size, available
+            ),
+            StorageError::NotFound(what) => write!(f, "Not found: {}", what),
+            StorageError::DuplicateTableName(name) => {
+                write!(f, "A table named {:?} already exists", name)
+            }
+            StorageError::DuplicateColumnName(name) => {
+                write!(f, "A column named {:?} already exists in the table", name)
+            }
+            StorageError::DuplicateCatalogEntry { index, key } => write!(
+                f,
+                "Catalog entry {} of the batch repeats the {} of another entry",
+                index, key
+            ),
+            StorageError::InvalidUtf8(err) => write!(f, "Invalid UTF-8 in entry: {}", err),
+            StorageError::UnknownDataType(name) => write!(f, "Unknown data type {:?}", name),
+            StorageError::ArityMismatch { expected, actual } => write!(
+                f,
+                "Row has {} values but the table has {} columns",
+                actual, expected
+            ),
+            StorageError::TooManyFields { count, max } => write!(
+                f,
+                "Row has {} fields, but a tuple holds at most {}",
+                count, max
+            ),
+            StorageError::NullViolation { column_name } => {
+                write!(f, "NULL value in non-nullable column {:?}", column_name)
+            }
+            StorageError::InvalidXid(xid) => write!(f, "Transaction id {} is reserved", xid),
+            StorageError::InvalidCsv {
+                line,
+                column_name: Some(column_name),
+                reason,
+            } => write!(f, "CSV line {}, column {:?}: {}", line, column_name, reason),
+            StorageError::InvalidCsv {
+                line,
+                column_name: None,
+                reason,
+            } => write!(f, "CSV line {}: {}", line, reason),
+            StorageError::InvalidCsvDelimiter(delimiter) => write!(
+                f,
+                "CSV delimiter {:?} cannot be a double quote or a line break",
+                *delimiter as char
+            ),
+            StorageError::InvalidPgCopy {
+                tuple: Some(tuple),
+                column_name: Some(column_name),
+                reason,
+            } => write!(
+                f,
+                "binary COPY tuple {}, column {:?}: {}",
+                tuple, column_name, reason
+            ),
+            StorageError::InvalidPgCopy {
+                tuple: Some(tuple),
+                column_name: None,
+                reason,
+            } => write!(f, "binary COPY tuple {}: {}", tuple, reason),
+            StorageError::InvalidPgCopy {
+                tuple: None,
+                reason,
+                ..
+            } => write!(f, "binary COPY data: {}", reason),
+            StorageError::UniqueViolation {
+                column_names,
+                values,
+            } => write!(
+                f,
+                "A row with primary key ({}) = {:?} already exists",
+                column_names.join(", "),
+                values
+            ),
+            StorageError::TypeMismatch {
+                column_name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Column {:?} has type {} but the value is {}",
+                column_name, expected, actual
+            ),
+            StorageError::ValueTooLong {
+                column_name,
+                length,
+                max,
+            } => write!(
+                f,
+                "Value of {} bytes in column {:?} exceeds the {}-byte limit",
+                length, column_name, max
+            ),
+            StorageError::CorruptValue { reason } => write!(f, "Corrupt value: {}", reason),
+            StorageError::ParserPanicked {
+                page,
+                slot,
+                message,
+            } => write!(
+                f,
+                "Entry parser panicked on page {} slot {}: {}",
+                page, slot, message
+            ),
+            StorageError::ChecksumMismatch {
+                page_no,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Checksum mismatch on page {}: stored {:#06x}, computed {:#06x}",
+                page_no, stored, computed
+            ),
+            StorageError::InvalidPageHeader { reason } => {
+                write!(f, "Invalid page header: {}", reason)
+            }
+            StorageError::CorruptPage {
+                page_no,
+                slot: None,
+                reason,
+            } => write!(f, "Corrupt page {}: {}", page_no, reason),
+            StorageError::CorruptPage {
+                page_no,
+                slot: Some(slot),
+                reason,
+            } => write!(
+                f,
+                "Corrupt entry on page {} slot {}: {}",
+                page_no, slot, reason
+            ),
+            StorageError::CorruptWalRecord { lsn, reason } => {
+                write!(f, "Corrupt WAL record at LSN {}: {}", lsn, reason)
+            }
+            StorageError::CorruptControlFile { reason } => {
+                write!(f, "Corrupt control file: {}", reason)
+            }
+            StorageError::InvalidFormat { magic } => write!(
+                f,
+                "Not a storage file: page magic {:#010x}, expected {:#010x}",
+                magic,
+                crate::page::PAGE_MAGIC
+            ),
+            StorageError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Page layout version {} is not supported; this library reads version {}",
+                found, supported
+            ),
+            StorageError::InvalidPageSize(page_size) => write!(
+                f,
+                "Page size {} is not a power of two between {} and {}",
+                page_size,
+                crate::page::MIN_PAGE_SIZE,
+                crate::page::MAX_PAGE_SIZE
+            ),
+            StorageError::PageSizeMismatch { expected, found } => write!(
+                f,
+                "File has {}-byte pages but the storage uses {}-byte pages",
+                found, expected
+            ),
+            StorageError::FileLocked(path) => write!(f, "File {:?} is locked", path),
+            StorageError::ReadOnly => write!(f, "Storage is read-only"),
+            StorageError::TransactionInProgress => {
+                write!(f, "A write transaction is already open on this storage")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Io(err) => Some(err),
+            StorageError::InvalidUtf8(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        StorageError::Io(err)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for StorageError {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        StorageError::InvalidUtf8(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+
+/// Readers-writer locks on data files, keyed by path, for the threads sharing
+/// one Storage.
+///
+/// Advisory file locks are not reliably exclusive between threads of one
+/// process on every platform, so operations take one of these first and the
+/// file lock only has to keep out other Storages and processes.
+///
+/// A thread holding a path exclusively may lock it again, shared or
+/// exclusive, so an operation can hold a file across calls that open it
+/// themselves. The nested lock must be dropped before the one it nests in.
+pub(crate) struct FileLocks {
+    holders: Mutex<HashMap<String, Holders>>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct Holders {
+    readers: usize,
+    writer: Option<ThreadId>,
+}
+
+impl Holders {
+    fn admits(&self, exclusive: bool) -> bool {
+        self.writer.is_none() && (!exclusive || self.readers == 0)
+    }
+}
+
+/// A lock taken with `FileLocks::lock`, released on drop.
+pub(crate) struct FileLockGuard<'a> {
+    locks: &'a FileLocks,
+    path: String,
+    exclusive: bool,
+    nested: bool,
+}
+
+impl FileLockGuard<'_> {
+    /// Whether the lock nests in an exclusive lock this thread already
+    /// holds, so the file is locked already too.
+    pub(crate) fn is_nested(&self) -> bool {
+        self.nested
+    }
+}
+
+impl FileLocks {
+    pub(crate) fn new() -> Self {
+        FileLocks {
+            holders: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Takes the lock on `path`, exclusive or shared. Waits for conflicting
+    /// holders to release it, or with `wait` false returns `None` instead.
+    pub(crate) fn lock(
+        &self,
+        path: &str,
+        exclusive: bool,
+        wait: bool,
+    ) -> Option<FileLockGuard<'_>> {
+        let mut holders = self.holders.lock().unwrap_or_else(PoisonError::into_inner);
+        let current_thread = thread::current().id();
+        if holders
+            .get(path)
+            .is_some_and(|current| current.writer == Some(current_thread))
+        {
+            return Some(FileLockGuard {
+                locks: self,
+                path: path.to_string(),
+                exclusive,
+                nested: true,
+            });
+        }
+        while !holders
+            .get(path)
+            .is_none_or(|current| current.admits(exclusive))
+        {
+            if !wait {
+                return None;
+            }
+            holders = self
+                .released
+                .wait(holders)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        let current = holders.entry(path.to_string()).or_default();
+        if exclusive {
+            current.writer = Some(current_thread);
+        } else {
+            current.readers += 1;
+        }
+        Some(FileLockGuard {
+            locks: self,
+            path: path.to_string(),
+            exclusive,
+            nested: false,
+        })
+    }
+}
+
+impl Drop for FileLockGuard<'_> {
+    fn drop(&mut self) {
+        if self.nested {
+            return;
+        }
+        let mut holders = self
+            .locks
+            .holders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(current) = holders.get_mut(&self.path) {
+            if self.exclusive {
+                current.writer = None;
+            } else {
+                current.readers -= 1;
+            }
+            if current.readers == 0 && current.writer.is_none() {
+                holders.remove(&self.path);
+            }
+        }
+        self.locks.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_locks_exclude_writers() {
+        let locks = FileLocks::new();
+        let first = locks.lock("a", false, false).unwrap();
+        let second = locks.lock("a", false, false).unwrap();
+        assert!(locks.lock("a", true, false).is_none());
+        assert!(locks.lock("b", true, false).is_some());
+
+        drop(first);
+        assert!(locks.lock("a", true, false).is_none());
+        drop(second);
+        let writer = locks.lock("a", true, false).unwrap();
+        let other_thread = std::thread::scope(|scope| {
+            scope
+                .spawn(|| locks.lock("a", false, false).is_none())
+                .join()
+                .unwrap()
+        });
+        assert!(other_thread);
+        drop(writer);
+        assert!(locks.holders.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_writer_may_lock_again() {
+        let locks = FileLocks::new();
+        let writer = locks.lock("a", true, false).unwrap();
+        assert!(!writer.is_nested());
+        let nested = locks.lock("a", false, false).unwrap();
+        assert!(nested.is_nested());
+        assert!(locks.lock("a", true, false).unwrap().is_nested());
+        drop(nested);
+        assert!(!locks.holders.lock().unwrap().is_empty());
+        drop(writer);
+        assert!(locks.holders.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::ErrorKind;
+
+use crate::Result;
+
+/// Free bytes are recorded in units of this many bytes, rounded down, so the
+/// map never claims more room than a page has.
+pub const FSM_BUCKET_SIZE: usize = 32;
+
+/// Approximate free space of every page of a data file, kept in a
+/// `<path>.fsm` sidecar with one byte per page.
+///
+/// The map is only a hint: writers check the page header before using a page
+/// it points them to, and correct the map when it was wrong.
+pub(crate) struct FreeSpaceMap {
+    path: String,
+    buckets: Vec<u8>,
+    dirty: bool,
+}
+
+impl FreeSpaceMap {
+    pub(crate) fn path_for(file_path: &str) -> String {
+        format!("{}.fsm", file_path)
+    }
+
+    /// Loads the map of `file_path`; a missing sidecar is an empty map.
+    pub(crate) fn load(file_path: &str) -> Result<FreeSpaceMap> {
+        let path = Self::path_for(file_path);
+        let buckets = match std::fs::read(&path) {
+            Ok(buckets) => buckets,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(FreeSpaceMap {
+            path,
+            buckets,
+            dirty: false,
+        })
+    }
+
+    /// Number of pages the map covers.
+    pub(crate) fn len(&self) -> u32 {
+        self.buckets.len() as u32
+    }
+
+    /// Drops the entries of pages at or past `page_count`.
+    pub(crate) fn truncate(&mut self, page_count: u32) {
+        if self.len() > page_count {
+            self.buckets.truncate(page_count as usize);
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn get(&self, page_no: u32) -> Option<usize> {
+        self.buckets
+            .get(page_no as usize)
+            .map(|bucket| *bucket as usize * FSM_BUCKET_SIZE)
+    }
+
+    /// Records the page's free space, extending the map if the page is new.
+    pub(crate) fn set(&mut self, page_no: u32, free_bytes: usize) {
+        let bucket = (free_bytes / FSM_BUCKET_SIZE).min(u8::MAX as usize) as u8;
+        let index = page_no as usize;
+        if index >= self.buckets.len() {
+            self.buckets.resize(index + 1, 0);
+        }
+        if self.buckets[index] != bucket {
+            self.buckets[index] = bucket;
+            self.dirty = true;
+        }
+    }
+
+    /// The lowest page recorded with at least `needed` free bytes, among the
+    /// pages `skip` does not exclude.
+    pub(crate) fn find(&self, needed: usize, skip: impl Fn(u32) -> bool) -> Option<u32> {
+        (0..self.len()).find(|page_no| !skip(*page_no) && self.get(*page_no).unwrap() >= needed)
+    }
+
+    /// Writes the map back if it changed.
+    pub(crate) fn save(&mut self) -> Result<()> {
+        if self.dirty {
+            std::fs::write(&self.path, &self.buckets)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
@@ -1,369 +1,6685 @@
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::Seek;
-use std::io::{Read, Result, Write};
+use std::borrow::Cow;
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 
-const PAGE_SIZE: usize = 8192;
+#[cfg(feature = "tokio")]
+mod async_storage;
+mod btree;
+mod buffer_pool;
+#[cfg(feature = "compression")]
+mod compression;
+mod control;
+mod csv;
+mod data_type;
+mod dump;
+mod error;
+mod file_lock;
+mod fsm;
+#[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+mod mmap;
+mod mvcc;
+mod page;
+mod pg_copy;
+mod scan;
+mod stats;
+mod txn;
+mod verify;
+mod wal;
 
-#[derive(Debug, Clone)]
-pub enum DataType {
-    String(String),
-    Integer32(i32),
-    Float32(f32),
+#[cfg(feature = "tokio")]
+pub use async_storage::AsyncStorage;
+pub use btree::Index;
+use buffer_pool::BufferPool;
+use control::{ControlData, CONTROL_FILE_NAME, CONTROL_FILE_SIZE};
+pub use csv::{CsvImport, CsvOptions};
+pub use data_type::DataType;
+use data_type::{take, truncated};
+pub use dump::{PageInfo, SlotInfo};
+pub use error::{Result, StorageError};
+use file_lock::{FileLockGuard, FileLocks};
+use fsm::FreeSpaceMap;
+pub use fsm::FSM_BUCKET_SIZE;
+#[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+pub use mmap::{MappedFile, MappedMetadataIter};
+use mvcc::{parse_tuple_header, parse_tuple_prefix, stamp_xmax, TupleHeader, TUPLE_HAS_HEADER};
+pub use mvcc::{Snapshot, FROZEN_XID, MAX_TUPLE_FIELDS};
+use page::{
+    is_valid_page_size, item_ids, overflow_chunk_size, page_checksum, read_item_id, write_item_id,
+    ITEM_ID_SIZE, NO_NEXT_PAGE, OVERFLOW_SPECIAL_SIZE, PAGE_HEADER_SIZE,
+};
+pub use page::{
+    read_special, write_special, ItemId, ItemStatus, PageHeader, PageInit, MAX_PAGE_SIZE,
+    MIN_PAGE_SIZE, PAGE_LAYOUT_VERSION, PAGE_MAGIC, PAGE_SIZE,
+};
+pub use scan::{RowIter, ScanOptions};
+pub use stats::TableStats;
+pub use txn::Txn;
+use txn::TxnState;
+use verify::EntryFormat;
+pub use verify::{VerifyProblem, VerifyReport};
+pub use wal::{Lsn, Wal, WalEntry, WalRecord};
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Rows a bulk load like `copy_from_csv` places on pages and writes
+/// together.
+const COPY_BATCH_ROWS: usize = 1024;
+
+/// Field tag of a string stored in an overflow chain, followed by its u32
+/// byte length and the u32 number of the chain's first page.
+const TAG_EXTERNAL: u8 = 0xFF;
+
+const EXTERNAL_FIELD_SIZE: usize = 1 + 4 + 4;
+
+/// Location of a tuple: the page it lives on and its index in that page's
+/// slot directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TupleId {
+    pub page_no: u32,
+    pub slot: u16,
+}
+
+impl TupleId {
+    fn from_pointer_offset((page_no, offset): (u32, u16)) -> Self {
+        Self {
+            page_no,
+            slot: (offset - PAGE_HEADER_SIZE as u16) / ITEM_ID_SIZE as u16,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-struct TableMetadata {
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableMetadata {
     pub table_id: u32,
     pub table_name: String,
 }
 
-#[derive(Debug, PartialEq)]
-struct ColumnMetadata {
-    column_id: u32,
-    table_id: u32,
-    column_name: String,
-    data_type: String,
-    is_nullable: bool,
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMetadata {
+    pub column_id: u32,
+    pub table_id: u32,
+    pub column_name: String,
+    pub data_type: String,
+    pub is_nullable: bool,
+    /// Whether the column is part of the table's primary key. A key of
+    /// several columns is unique as a whole, compared in column order.
+    pub is_primary_key: bool,
+    /// The value `insert_row` stores when the row leaves the column out or
+    /// gives NULL for it, and that rows stored before the column was added
+    /// read as.
+    pub default: Option<DataType>,
 }
 
-struct HeaderOffsets {
-    pub lsn: (usize, usize),
-    pub checksum: (usize, usize),
-    pub flags: (usize, usize),
-    pub lower: (usize, usize),
-    pub higher: (usize, usize),
-    pub special_space: (usize, usize),
+/// Reads a page of `page_size` bytes from the file, verifying it with
+/// `verify_page`.
+fn read_page_from_disk(
+    file: &mut File,
+    page_no: u32,
+    page_size: usize,
+) -> Result<(Box<[u8]>, PageHeader)> {
+    let page = read_raw_page(file, page_no, page_size)?;
+    let header = verify_page(&page, page_no)?;
+    Ok((page, header))
 }
 
-struct Storage {
-    header_offsets: HeaderOffsets,
+fn read_raw_page(file: &mut File, page_no: u32, page_size: usize) -> Result<Box<[u8]>> {
+    let mut page = read_page_slot(file, page_no, page_size)?;
+    expand_stored_page(&mut page, page_no)?;
+    Ok(page)
 }
 
-impl Storage {
-    pub fn new() -> Self {
-        Self {
-            header_offsets: HeaderOffsets {
-                lsn: (0, 8),
-                checksum: (8, 10),
-                flags: (10, 12),
-                lower: (12, 14),
-                higher: (14, 16),
-                special_space: (16, 18),
-            },
+/// Reads the bytes of a page's slot as they are in the file, without
+/// expanding a compressed page.
+fn read_page_slot(file: &mut File, page_no: u32, page_size: usize) -> Result<Box<[u8]>> {
+    let mut slot = vec![0u8; page_size].into_boxed_slice();
+    file.seek(SeekFrom::Start(page_no as u64 * page_size as u64))?;
+    file.read_exact(&mut slot)?;
+    Ok(slot)
+}
+
+/// Turns a page slot read from a file back into the page, if the page was
+/// stored compressed. A compressed page read without the `compression`
+/// feature fails with `CorruptPage`.
+fn expand_stored_page(slot: &mut [u8], page_no: u32) -> Result<()> {
+    if slot.len() < PAGE_HEADER_SIZE || !PageHeader::decode(slot).is_compressed() {
+        return Ok(());
+    }
+    #[cfg(feature = "compression")]
+    return compression::expand_page(slot, page_no);
+    #[cfg(not(feature = "compression"))]
+    Err(StorageError::CorruptPage {
+        page_no,
+        slot: None,
+        reason: "page is compressed, which needs the compression feature".to_string(),
+    })
+}
+
+/// Checks the format, page size, checksum and header invariants of a page
+/// read from a file, and that every slot with an entry points into the data
+/// area.
+fn verify_page(page: &[u8], page_no: u32) -> Result<PageHeader> {
+    let header = PageHeader::decode(page);
+    header.check_format()?;
+    if header.is_compressed() {
+        return Err(StorageError::CorruptPage {
+            page_no,
+            slot: None,
+            reason: "page is still compressed".to_string(),
+        });
+    }
+    if header.page_size as usize != page.len() {
+        return Err(StorageError::PageSizeMismatch {
+            expected: page.len(),
+            found: header.page_size as usize,
+        });
+    }
+    if header.has_checksum() {
+        let computed = page_checksum(page);
+        if header.checksum != computed {
+            return Err(StorageError::ChecksumMismatch {
+                page_no,
+                stored: header.checksum,
+                computed,
+            });
         }
     }
+    header
+        .check_invariants()
+        .map_err(|reason| StorageError::CorruptPage {
+            page_no,
+            slot: None,
+            reason,
+        })?;
+    for (slot, item_id) in (0..).zip(item_ids(page, &header)) {
+        header
+            .check_item_id(&item_id)
+            .map_err(|reason| StorageError::CorruptPage {
+                page_no,
+                slot: Some(slot),
+                reason,
+            })?;
+    }
+    Ok(header)
+}
 
-    pub fn read_metadata<F, T>(&self, file_path: &str, parse_entry: F) -> Result<Vec<T>>
-    where
-        F: Fn(&[u8], usize) -> (T, usize),
-    {
-        let mut file = OpenOptions::new().read(true).write(true).open(file_path)?;
-        let mut page = [0u8; PAGE_SIZE];
-        file.read_exact(&mut page)?;
+/// Stamps the page checksum, if the page carries one, and writes it out.
+fn write_page_to_disk(file: &mut File, page_no: u32, page: &mut [u8]) -> Result<()> {
+    stamp_checksum(page)?;
+    file.seek(SeekFrom::Start(page_no as u64 * page.len() as u64))?;
+    file.write_all(page)?;
+    Ok(())
+}
 
-        let lower = u16::from_le_bytes(
-            page[self.header_offsets.lower.0..self.header_offsets.lower.1]
-                .try_into()
-                .unwrap(),
-        );
+fn stamp_checksum(page: &mut [u8]) -> Result<()> {
+    let mut header = PageHeader::parse(page)?;
+    if header.has_checksum() {
+        header.checksum = page_checksum(page);
+        header.write_to(page);
+    }
+    Ok(())
+}
 
-        let mut pointers = Vec::new();
-        let mut offset = 18; // Start of the directory
-        while offset < lower {
-            let pointer = u16::from_le_bytes(
-                page[offset as usize..offset as usize + 2]
-                    .try_into()
-                    .unwrap(),
-            );
-            pointers.push(pointer as usize);
-            offset += 2;
+/// Makes the directory entries of files created in `path`'s directory durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+/// Directories cannot be opened for syncing here; the file itself was synced.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// End of the space the entry at `offset` may grow into: the start of the next
+/// entry up in the data area, live or dead, or the special space if there is
+/// none.
+fn entry_extent_end(page: &[u8], header: &PageHeader, offset: usize) -> usize {
+    item_ids(page, header)
+        .into_iter()
+        .filter(ItemId::has_storage)
+        .map(|item_id| item_id.offset as usize)
+        .filter(|other| *other > offset)
+        .min()
+        .unwrap_or(header.special_space as usize)
+}
+
+/// The number stored in an overflow page's special space.
+fn next_overflow_page(page: &[u8], header: &PageHeader) -> u32 {
+    let special_space = header.special_space as usize;
+    u32::from_le_bytes(page[special_space..special_space + 4].try_into().unwrap())
+}
+
+/// `name` in the directory, as the string paths are keyed by.
+fn path_in(dir: &Path, name: &str) -> String {
+    dir.join(name)
+        .to_str()
+        .expect("base directory must be valid UTF-8")
+        .to_string()
+}
+
+/// The primary keys a bulk load has seen, in the table and in its own rows.
+/// Keys are kept encoded, so each row is looked up rather than compared
+/// with every key before it. The heap file stays locked while they are
+/// held, so no other thread stores a key between the check and the load.
+struct UniqueKeys<'a> {
+    key_columns: Vec<usize>,
+    column_names: Vec<String>,
+    seen: BTreeSet<Vec<u8>>,
+    _heap: DataFile<'a>,
+}
+
+impl UniqueKeys<'_> {
+    /// Adds the row's key, failing with `UniqueViolation` if it was seen
+    /// before. A table without a primary key takes every row.
+    fn insert(&mut self, row: &[Option<DataType>]) -> Result<()> {
+        if self.key_columns.is_empty() {
+            return Ok(());
+        }
+        let key: Vec<Option<DataType>> = self
+            .key_columns
+            .iter()
+            .map(|index| row[*index].clone())
+            .collect();
+        if !self.seen.insert(UniqueKeys::encode(&key)) {
+            return Err(StorageError::UniqueViolation {
+                column_names: self.column_names.clone(),
+                values: key.into_iter().flatten().collect(),
+            });
         }
+        Ok(())
+    }
 
-        let mut entries = Vec::new();
-        for pointer in pointers {
-            let (entry, _) = parse_entry(&page, pointer);
-            entries.push(entry);
+    /// Encodes a key as each value tagged, after a byte marking whether it
+    /// is NULL. Keys are told apart by their encoding, so floats compare by
+    /// their bits.
+    fn encode(key: &[Option<DataType>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in key {
+            match value {
+                Some(value) => {
+                    data.push(1);
+                    value.encode_tagged(&mut data);
+                }
+                None => data.push(0),
+            }
         }
-        Ok(entries)
+        data
     }
+}
 
-    pub fn write_metadata<F>(
-        &self,
-        file_path: &str,
-        entries: Vec<Vec<u8>>,
-        calculate_size: F,
-    ) -> Result<Vec<u16>>
-    where
-        F: Fn(&[u8]) -> usize,
-    {
-        let mut file = OpenOptions::new().read(true).write(true).open(file_path)?;
-        let mut page = [0u8; PAGE_SIZE];
-        file.read_exact(&mut page)?;
+/// Checks that `values` is a row of `columns`; see `Storage::insert_row`.
+fn check_row(columns: &[ColumnMetadata], values: &[Option<DataType>]) -> Result<()> {
+    if values.len() != columns.len() {
+        return Err(StorageError::ArityMismatch {
+            expected: columns.len(),
+            actual: values.len(),
+        });
+    }
+    for (column, value) in columns.iter().zip(values) {
+        let Some(value) = value else {
+            if column.is_nullable && !column.is_primary_key {
+                continue;
+            }
+            return Err(StorageError::NullViolation {
+                column_name: column.column_name.clone(),
+            });
+        };
+        check_value(&column.column_name, &column.data_type, value)?;
+    }
+    Ok(())
+}
 
-        let (lower_offset_start, lower_offset_end) =
-            (self.header_offsets.lower.0, self.header_offsets.lower.1);
-        let mut lower = u16::from_le_bytes(
-            page[lower_offset_start..lower_offset_end]
-                .try_into()
-                .unwrap(),
-        );
-        let (higher_offset_start, higher_offset_end) =
-            (self.header_offsets.higher.0, self.header_offsets.higher.1);
-        let mut higher = u16::from_le_bytes(
-            page[higher_offset_start..higher_offset_end]
-                .try_into()
-                .unwrap(),
-        );
+/// Checks that `value` is of the column's type and short enough to store.
+/// Strings too long to keep in the row move to overflow chains, so the
+/// limit is the chains' u32 length.
+fn check_value(column_name: &str, data_type: &str, value: &DataType) -> Result<()> {
+    let expected = DataType::from_type_name(data_type)?.type_name();
+    if value.type_name() != expected {
+        return Err(StorageError::TypeMismatch {
+            column_name: column_name.to_string(),
+            expected,
+            actual: value.type_name(),
+        });
+    }
+    if let DataType::String(string) = value {
+        if string.len() > u32::MAX as usize {
+            return Err(StorageError::ValueTooLong {
+                column_name: column_name.to_string(),
+                length: string.len(),
+                max: u32::MAX as usize,
+            });
+        }
+    }
+    Ok(())
+}
 
-        let mut pointer_offsets = Vec::new();
-        for entry in entries {
-            let entry_size = calculate_size(&entry);
+/// The row with the defaults of `columns` in place of NULLs and of values
+/// left off its end. A column left off without a default fails with
+/// `ArityMismatch`, as does a row longer than the columns.
+fn with_defaults(
+    columns: &[ColumnMetadata],
+    values: &[Option<DataType>],
+) -> Result<Vec<Option<DataType>>> {
+    let arity_mismatch = StorageError::ArityMismatch {
+        expected: columns.len(),
+        actual: values.len(),
+    };
+    if values.len() > columns.len() {
+        return Err(arity_mismatch);
+    }
+    let mut row = Vec::with_capacity(columns.len());
+    for (index, column) in columns.iter().enumerate() {
+        match values.get(index) {
+            Some(Some(value)) => row.push(Some(value.clone())),
+            Some(None) => row.push(column.default.clone()),
+            None if column.default.is_some() => row.push(column.default.clone()),
+            None => return Err(arity_mismatch),
+        }
+    }
+    Ok(row)
+}
+
+fn remove_if_exists(path: &str) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn missing_as_empty<T>(result: Result<Vec<T>>) -> Result<Vec<T>> {
+    match result {
+        Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+/// Runs the parser on the entry of `item_id`, turning an error it returns into
+/// `CorruptPage` and a panic into `ParserPanicked`. The parser is given the
+/// page up to the end of the entry, so reading past the entry fails instead of
+/// returning other entries' bytes.
+fn parse_caught<F, T>(parse_entry: &F, page: &[u8], tuple_id: TupleId, item_id: ItemId) -> Result<T>
+where
+    F: Fn(&[u8], usize) -> Result<(T, usize)>,
+{
+    let entry_end = item_id.offset as usize + item_id.length as usize;
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        parse_entry(&page[..entry_end], item_id.offset as usize)
+    })) {
+        Ok(Ok((entry, _))) => Ok(entry),
+        Ok(Err(err)) => Err(StorageError::CorruptPage {
+            page_no: tuple_id.page_no,
+            slot: Some(tuple_id.slot),
+            reason: err.to_string(),
+        }),
+        Err(payload) => {
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.clone()
+            } else {
+                "unknown panic payload".to_string()
+            };
+            Err(StorageError::ParserPanicked {
+                page: tuple_id.page_no,
+                slot: tuple_id.slot,
+                message,
+            })
+        }
+    }
+}
+
+/// Reads a string prefixed with its u16 byte length at `offset`, returning it
+/// with the offset just past it.
+fn read_string(page: &[u8], offset: usize, what: &str) -> Result<(String, usize)> {
+    let length = u16::from_le_bytes(take(page, offset, what)?) as usize;
+    let bytes = page
+        .get(offset + 2..offset + 2 + length)
+        .ok_or_else(|| truncated(what))?;
+    Ok((String::from_utf8(bytes.to_vec())?, offset + 2 + length))
+}
+
+/// Fails with `DuplicateCatalogEntry` for the first entry of `batch` whose
+/// table_id or table_name is in `existing` or earlier in the batch.
+fn check_unique_tables(existing: &[TableMetadata], batch: &[TableMetadata]) -> Result<()> {
+    let mut ids: BTreeSet<u32> = existing.iter().map(|table| table.table_id).collect();
+    let mut names: BTreeSet<&str> = existing
+        .iter()
+        .map(|table| table.table_name.as_str())
+        .collect();
+    for (index, table) in batch.iter().enumerate() {
+        let key = if !ids.insert(table.table_id) {
+            format!("table id {}", table.table_id)
+        } else if !names.insert(&table.table_name) {
+            format!("table name {:?}", table.table_name)
+        } else {
+            continue;
+        };
+        return Err(StorageError::DuplicateCatalogEntry { index, key });
+    }
+    Ok(())
+}
+
+/// Fails with `DuplicateCatalogEntry` for the first entry of `batch` whose
+/// table_id and column_name are in `existing` or earlier in the batch.
+fn check_unique_columns(existing: &[ColumnMetadata], batch: &[ColumnMetadata]) -> Result<()> {
+    let mut keys: BTreeSet<(u32, &str)> = existing
+        .iter()
+        .map(|column| (column.table_id, column.column_name.as_str()))
+        .collect();
+    for (index, column) in batch.iter().enumerate() {
+        if !keys.insert((column.table_id, &column.column_name)) {
+            return Err(StorageError::DuplicateCatalogEntry {
+                index,
+                key: format!(
+                    "column name {:?} in table {}",
+                    column.column_name, column.table_id
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn parse_postgres_class(page: &[u8], pointer: usize) -> Result<(TableMetadata, usize)> {
+    let table_id = u32::from_le_bytes(take(page, pointer, "table id")?);
+    let (table_name, offset) = read_string(page, pointer + 4, "table name")?;
+    Ok((
+        TableMetadata {
+            table_id,
+            table_name,
+        },
+        offset,
+    ))
+}
+
+/// Bits of the flags byte after a pg_attribute entry's strings. Entries
+/// written before primary keys and defaults hold only 0 or 1 there, so they
+/// read as columns outside the key without a default.
+const COLUMN_NULLABLE: u8 = 0x01;
+const COLUMN_PRIMARY_KEY: u8 = 0x02;
+/// The flags byte is followed by the default, tagged.
+const COLUMN_HAS_DEFAULT: u8 = 0x04;
+
+fn parse_postgres_attribute(page: &[u8], pointer: usize) -> Result<(ColumnMetadata, usize)> {
+    let mut offset = pointer;
+
+    let column_id = u32::from_le_bytes(take(page, offset, "column id")?);
+    offset += 4;
+
+    let table_id = u32::from_le_bytes(take(page, offset, "table id")?);
+    offset += 4;
+
+    let (column_name, next_offset) = read_string(page, offset, "column name")?;
+    offset = next_offset;
+
+    let (data_type, next_offset) = read_string(page, offset, "data type")?;
+    offset = next_offset;
+
+    let [flags] = take(page, offset, "column flags")?;
+    offset += 1;
+    if flags & !(COLUMN_NULLABLE | COLUMN_PRIMARY_KEY | COLUMN_HAS_DEFAULT) != 0 {
+        return Err(StorageError::CorruptValue {
+            reason: format!("unknown column flags {:#04x}", flags),
+        });
+    }
 
-            if higher < entry_size as u16 || (lower as usize + 2) > PAGE_SIZE {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Insufficient space in page.",
-                ));
+    let mut default = None;
+    if flags & COLUMN_HAS_DEFAULT != 0 {
+        let (value, next_offset) = DataType::decode_tagged(page, offset)?;
+        default = Some(value);
+        offset = next_offset;
+    }
+
+    Ok((
+        ColumnMetadata {
+            column_id,
+            table_id,
+            column_name,
+            data_type,
+            is_nullable: flags & COLUMN_NULLABLE != 0,
+            is_primary_key: flags & COLUMN_PRIMARY_KEY != 0,
+            default,
+        },
+        offset,
+    ))
+}
+
+/// Lays out a pg_attribute entry as `parse_postgres_attribute` reads it.
+fn encode_postgres_attribute(column: &ColumnMetadata) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&column.column_id.to_le_bytes());
+    data.extend_from_slice(&column.table_id.to_le_bytes());
+    data.extend_from_slice(&(column.column_name.len() as u16).to_le_bytes());
+    data.extend_from_slice(column.column_name.as_bytes());
+    data.extend_from_slice(&(column.data_type.len() as u16).to_le_bytes());
+    data.extend_from_slice(column.data_type.as_bytes());
+    let mut flags = 0;
+    if column.is_nullable {
+        flags |= COLUMN_NULLABLE;
+    }
+    if column.is_primary_key {
+        flags |= COLUMN_PRIMARY_KEY;
+    }
+    if column.default.is_some() {
+        flags |= COLUMN_HAS_DEFAULT;
+    }
+    data.push(flags);
+    if let Some(default) = &column.default {
+        default.encode_tagged(&mut data);
+    }
+    data
+}
+
+/// A field of a stored tuple: its value, or where the value was moved to.
+#[derive(Clone)]
+enum StoredField {
+    Inline(DataType),
+    /// A string of `length` bytes in the overflow chain starting at
+    /// `first_page_no`.
+    External {
+        length: u32,
+        first_page_no: u32,
+    },
+}
+
+/// Encodes a tuple; a string with an entry in `external` is stored as a
+/// reference to the overflow chain starting at that page. Without `header`
+/// the tuple is frozen and stored without one.
+fn encode_tuple(
+    values: &[Option<DataType>],
+    external: &[Option<u32>],
+    header: Option<TupleHeader>,
+) -> Vec<u8> {
+    let mut data = vec![];
+    match header {
+        Some(header) => {
+            data.extend_from_slice(&(values.len() as u16 | TUPLE_HAS_HEADER).to_le_bytes());
+            data.extend_from_slice(&header.xmin.to_le_bytes());
+            data.extend_from_slice(&header.xmax.to_le_bytes());
+        }
+        None => data.extend_from_slice(&(values.len() as u16).to_le_bytes()),
+    }
+    let mut null_bitmap = vec![0u8; values.len().div_ceil(8)];
+    for (index, value) in values.iter().enumerate() {
+        if value.is_none() {
+            null_bitmap[index / 8] |= 1 << (index % 8);
+        }
+    }
+    data.extend_from_slice(&null_bitmap);
+    for (value, first_page_no) in values.iter().zip(external) {
+        match (value, first_page_no) {
+            (Some(DataType::String(value)), Some(first_page_no)) => {
+                data.push(TAG_EXTERNAL);
+                data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                data.extend_from_slice(&first_page_no.to_le_bytes());
             }
+            (Some(value), _) => value.encode_tagged(&mut data),
+            (None, _) => {}
+        }
+    }
+    data
+}
+
+fn parse_stored_tuple(page: &[u8], pointer: usize) -> Result<(Vec<Option<StoredField>>, usize)> {
+    parse_stored_fields(page, pointer, |_| true)
+}
 
-            higher -= entry_size as u16;
-            page[lower as usize..lower as usize + 2].copy_from_slice(&higher.to_le_bytes());
-            pointer_offsets.push(lower);
-            lower += 2;
+/// Like `parse_stored_tuple`, but fields `wanted` returns false for are
+/// skipped without being decoded and come back as `None`.
+fn parse_stored_fields(
+    page: &[u8],
+    pointer: usize,
+    wanted: impl Fn(usize) -> bool,
+) -> Result<(Vec<Option<StoredField>>, usize)> {
+    let (_, field_count, mut offset) = parse_tuple_prefix(page, pointer)?;
+    let null_bitmap = page
+        .get(offset..offset + field_count.div_ceil(8))
+        .ok_or_else(|| truncated("null bitmap"))?;
+    offset += null_bitmap.len();
 
-            page[higher as usize..higher as usize + entry_size].copy_from_slice(&entry);
+    let mut fields = Vec::with_capacity(field_count);
+    for index in 0..field_count {
+        if null_bitmap[index / 8] & (1 << (index % 8)) != 0 {
+            fields.push(None);
+            continue;
+        }
+        let external = take(page, offset, "field tag")? == [TAG_EXTERNAL];
+        if !wanted(index) {
+            fields.push(None);
+            offset = if external {
+                take::<EXTERNAL_FIELD_SIZE>(page, offset, "external field")?;
+                offset + EXTERNAL_FIELD_SIZE
+            } else {
+                DataType::skip_tagged(page, offset)?
+            };
+            continue;
         }
+        if external {
+            let length = u32::from_le_bytes(take(page, offset + 1, "external length")?);
+            let first_page_no = u32::from_le_bytes(take(page, offset + 5, "external page")?);
+            fields.push(Some(StoredField::External {
+                length,
+                first_page_no,
+            }));
+            offset += EXTERNAL_FIELD_SIZE;
+            continue;
+        }
+        let (value, next_offset) = DataType::decode_tagged(page, offset)?;
+        fields.push(Some(StoredField::Inline(value)));
+        offset = next_offset;
+    }
+    Ok((fields, offset))
+}
 
-        page[higher_offset_start..higher_offset_end].copy_from_slice(&higher.to_le_bytes());
-        page[lower_offset_start..lower_offset_end].copy_from_slice(&lower.to_le_bytes());
-        file.seek(std::io::SeekFrom::Start(0))?;
-        file.write_all(&page)?;
+/// The B-tree index on table_id kept next to a pg_attribute file.
+fn attribute_index_path(file_path: &str) -> String {
+    format!("{}.table_id.idx", file_path)
+}
 
-        Ok(pointer_offsets)
+fn slot_not_found(tuple_id: TupleId) -> StorageError {
+    StorageError::NotFound(format!(
+        "entry in slot {} of page {}",
+        tuple_id.slot, tuple_id.page_no
+    ))
+}
+
+/// A column of a table being created; ids are assigned by `create_table`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDefinition {
+    pub name: String,
+    pub data_type: String,
+    pub is_nullable: bool,
+    pub is_primary_key: bool,
+    /// Must be of the column's data type.
+    pub default: Option<DataType>,
+}
+
+impl ColumnDefinition {
+    fn check_default(&self) -> Result<()> {
+        match &self.default {
+            Some(default) => check_value(&self.name, &self.data_type, default),
+            None => Ok(()),
+        }
     }
+}
 
-    pub fn read_postgres_class(&self, file_path: &str) -> Result<Vec<TableMetadata>> {
-        self.read_metadata(file_path, |page, pointer| {
-            let mut offset = pointer;
+/// How far page and log writes are pushed before a write call returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Leave the data wherever `write_all` put it.
+    #[default]
+    None,
+    /// Flush userspace buffers to the OS, without waiting for the disk.
+    Flush,
+    /// Wait for the data to reach the disk with `sync_data`, and make the
+    /// directory entries of newly created files durable.
+    Fsync,
+}
 
-            let table_id = u32::from_le_bytes(page[offset..offset + 4].try_into().unwrap());
-            offset += 4;
+/// What opening a data file does when another Storage or process holds a
+/// conflicting lock on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockWait {
+    /// Wait until the lock is released.
+    #[default]
+    Block,
+    /// Fail with `StorageError::FileLocked` instead of waiting.
+    Fail,
+}
 
-            let table_name_length =
-                u16::from_le_bytes(page[offset..offset + 2].try_into().unwrap());
-            offset += 2;
+/// Whether a Storage may modify the files it opens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessMode {
+    #[default]
+    ReadWrite,
+    /// Files are only ever opened for reading, and every method that would
+    /// modify one fails with `StorageError::ReadOnly` instead.
+    ReadOnly,
+}
 
-            let table_name =
-                String::from_utf8(page[offset..offset + table_name_length as usize].to_vec())
-                    .unwrap();
-            offset += table_name_length as usize;
+/// Storage is `Send + Sync` and meant to be shared as `Arc<Storage>`;
+/// operations on the same file from different threads take turns.
+pub struct Storage {
+    base_dir: PathBuf,
+    page_size: usize,
+    table_file_path: String,
+    column_file_path: String,
+    buffer_pool: Mutex<BufferPool>,
+    /// Pages after a missed one that iteration reads along with it.
+    read_ahead: u32,
+    /// Reads of data file pages made for the buffer pool; see `page_reads`.
+    page_reads: AtomicU64,
+    #[cfg(feature = "compression")]
+    page_compression: bool,
+    wal: Option<Mutex<Wal>>,
+    sync_mode: SyncMode,
+    access_mode: AccessMode,
+    lock_wait: LockWait,
+    file_locks: FileLocks,
+    /// The open write transaction, if there is one.
+    txn: Mutex<Option<TxnState>>,
+    next_txn_id: AtomicU64,
+}
 
-            (
-                TableMetadata {
-                    table_id,
-                    table_name,
-                },
-                offset,
-            )
-        })
+// Fails to compile if a field ever stops Storage from being shared between
+// threads.
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    let _ = assert_send_sync::<Storage>;
+};
+
+/// A data file opened for one operation, with the path the buffer pool knows
+/// it by and the Storage's lock on that path while it holds one.
+struct DataFile<'a> {
+    path: Cow<'a, str>,
+    file: File,
+    lock: Option<FileLockGuard<'a>>,
+}
+
+type EntryParser<'a, T> = Box<dyn Fn(&[u8], usize) -> Result<(T, usize)> + 'a>;
+
+/// Live entries of a data file, parsed one at a time. See
+/// `Storage::iter_metadata`.
+pub struct MetadataIter<'a, T> {
+    storage: &'a Storage,
+    file: DataFile<'a>,
+    parse_entry: EntryParser<'a, T>,
+    page_count: u32,
+    /// The page being iterated and its slot directory.
+    page: Option<(Box<[u8]>, Vec<ItemId>)>,
+    next_page_no: u32,
+    next_slot: usize,
+    /// Whether an error ends only the entry or page it came from, as in
+    /// `Storage::scan`, rather than the iteration.
+    continue_after_error: bool,
+    done: bool,
+}
+
+impl<T> MetadataIter<'_, T> {
+    /// Reads the next page under a shared lock held only for the read, so
+    /// writers can get in between pages.
+    fn read_next_page(&mut self) -> Result<(Box<[u8]>, PageHeader)> {
+        self.storage.lock_data_file(&mut self.file, false)?;
+        let result =
+            self.storage
+                .read_page_ahead(&mut self.file, self.next_page_no, self.page_count);
+        self.storage.unlock_data_file(&mut self.file)?;
+        result
     }
 
-    pub fn write_postgres_class(
-        &self,
-        file_path: &str,
-        tables_metadata: &Vec<TableMetadata>,
-    ) -> Result<()> {
-        let entries: Vec<Vec<u8>> = tables_metadata
-            .iter()
-            .map(|table| {
-                let mut data = vec![];
-                data.extend_from_slice(&table.table_id.to_le_bytes());
-                data.extend_from_slice(&(table.table_name.len() as u16).to_le_bytes());
-                data.extend_from_slice(table.table_name.as_bytes());
-                data
-            })
-            .collect();
+    fn next_entry(&mut self) -> Option<Result<(TupleId, T)>> {
+        while !self.done {
+            if let Some((page, item_ids)) = &self.page {
+                while let Some(&item_id) = item_ids.get(self.next_slot) {
+                    let tuple_id = TupleId {
+                        page_no: self.next_page_no - 1,
+                        slot: self.next_slot as u16,
+                    };
+                    self.next_slot += 1;
+                    if !item_id.is_normal() {
+                        continue;
+                    }
+                    // The parser only sees a shared view of this iterator's page
+                    // buffer and Storage keeps no state across calls, so a panic
+                    // cannot leave anything half-updated.
+                    let result = parse_caught(&self.parse_entry, page, tuple_id, item_id);
+                    self.done = result.is_err() && !self.continue_after_error;
+                    return Some(result.map(|entry| (tuple_id, entry)));
+                }
+            }
+            if self.next_page_no == self.page_count {
+                self.done = true;
+                break;
+            }
+            match self.read_next_page() {
+                Ok((page, header)) => {
+                    // Overflow pages hold parts of values, not entries.
+                    let item_ids = if header.is_overflow() {
+                        Vec::new()
+                    } else {
+                        item_ids(&page, &header)
+                    };
+                    self.page = Some((page, item_ids));
+                    self.next_page_no += 1;
+                    self.next_slot = 0;
+                }
+                Err(err) => {
+                    if self.continue_after_error {
+                        self.page = None;
+                        self.next_page_no += 1;
+                    } else {
+                        self.done = true;
+                    }
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
 
-        self.write_metadata(file_path, entries, |entry| entry.len());
-        Ok(())
+impl<T> Iterator for MetadataIter<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry()
+            .map(|result| result.map(|(_, entry)| entry))
     }
+}
 
-    pub fn read_postgres_attribute(&self, file_path: &str) -> Result<Vec<ColumnMetadata>> {
-        self.read_metadata(file_path, |page, pointer| {
-            let mut offset = pointer;
+impl<T> std::iter::FusedIterator for MetadataIter<'_, T> {}
+
+impl Drop for Storage {
+    /// Errors are ignored here; call `flush_all` to see them.
+    fn drop(&mut self) {
+        let _ = self.flush_all();
+    }
+}
 
-            let column_id = u32::from_le_bytes(page[offset..offset + 4].try_into().unwrap());
-            offset += 4;
+impl Storage {
+    /// Keeps every file under `base_dir`: the catalog in `pg_class` and
+    /// `pg_attribute`, and the rows of each table in a heap file named after
+    /// its table_id. The directory is created when the first file is.
+    ///
+    /// Panics if `base_dir` is not valid UTF-8, since files are keyed by their
+    /// path as a string.
+    pub fn new(base_dir: impl AsRef<Path>) -> Self {
+        let base_dir = base_dir.as_ref().to_path_buf();
+        Self {
+            table_file_path: path_in(&base_dir, "pg_class"),
+            column_file_path: path_in(&base_dir, "pg_attribute"),
+            base_dir,
+            page_size: PAGE_SIZE,
+            buffer_pool: Mutex::new(BufferPool::new(0)),
+            read_ahead: 0,
+            page_reads: AtomicU64::new(0),
+            #[cfg(feature = "compression")]
+            page_compression: false,
+            wal: None,
+            sync_mode: SyncMode::None,
+            access_mode: AccessMode::ReadWrite,
+            lock_wait: LockWait::Block,
+            file_locks: FileLocks::new(),
+            txn: Mutex::new(None),
+            next_txn_id: AtomicU64::new(1),
+        }
+    }
 
-            let table_id = u32::from_le_bytes(page[offset..offset + 4].try_into().unwrap());
-            offset += 4;
+    /// Caches up to `capacity` pages across all files, evicting the least
+    /// recently used. Writes then stay in the cache until `flush`, `flush_all`
+    /// or eviction writes them back, and `Drop` flushes whatever is left. With
+    /// the default capacity of 0 every page write goes straight to disk.
+    ///
+    /// Cached pages are trusted over the file, so nothing else may write the
+    /// files this Storage has cached.
+    pub fn with_buffer_pool_capacity(mut self, capacity: usize) -> Self {
+        self.buffer_pool = Mutex::new(BufferPool::new(capacity));
+        self
+    }
 
-            let column_name_length =
-                u16::from_le_bytes(page[offset..offset + 2].try_into().unwrap());
-            offset += 2;
+    /// When iterating a file, as scans and catalog reads do, makes a page
+    /// missing from the buffer pool come in with up to `pages` pages after
+    /// it in one read, so the pages that follow are cache hits. Nothing is
+    /// read past the end of the file, read-ahead stops at the first page
+    /// already cached, and it never evicts a dirty page to make room. The
+    /// window is capped at the pool's capacity less one, so it has no effect
+    /// without a pool. The default of 0 reads a page at a time.
+    pub fn with_read_ahead(mut self, pages: u32) -> Self {
+        self.read_ahead = pages;
+        self
+    }
 
-            let column_name =
-                String::from_utf8(page[offset..offset + column_name_length as usize].to_vec())
-                    .unwrap();
-            offset += column_name_length as usize;
+    /// Number of reads of data file pages made to fill the buffer pool or
+    /// serve a miss, where a read-ahead run counts as one. Reads for
+    /// `dump_page`, `verify_file` and recovery are left out.
+    pub fn page_reads(&self) -> u64 {
+        self.page_reads.load(Ordering::Relaxed)
+    }
 
-            let data_type_length = u16::from_le_bytes(page[offset..offset + 2].try_into().unwrap());
-            offset += 2;
+    /// Stores pages compressed when that saves at least one 4 KiB file system
+    /// block, and as they are otherwise. A compressed page keeps its place in
+    /// the file, with the blocks it no longer needs released as a hole where
+    /// the file system supports that, so files of repetitive pages take less
+    /// space on disk but not a shorter length. Pages are expanded as they are
+    /// read, so the buffer pool and everything above it see the usual pages,
+    /// and compressed when written back. Only built with the `compression`
+    /// feature.
+    ///
+    /// Files with compressed pages are read by any Storage built with the
+    /// feature, compressing or not, but not through `open_mapped`, which
+    /// fails on the first compressed page.
+    #[cfg(feature = "compression")]
+    pub fn with_page_compression(mut self) -> Self {
+        self.page_compression = true;
+        self
+    }
 
-            let data_type =
-                String::from_utf8(page[offset..offset + data_type_length as usize].to_vec())
-                    .unwrap();
-            offset += data_type_length as usize;
+    /// Writes the page to its place in the file, compressed if the Storage
+    /// compresses pages.
+    fn write_page_out(&self, file: &mut File, page_no: u32, page: &mut [u8]) -> Result<()> {
+        #[cfg(feature = "compression")]
+        if self.page_compression {
+            return compression::write_compressed_page(file, page_no, page);
+        }
+        write_page_to_disk(file, page_no, page)
+    }
 
-            let is_nullable = page[offset] != 0;
-            offset += 1;
+    /// Reads and writes pages of `page_size` bytes instead of `PAGE_SIZE`.
+    /// The size must be a power of two from `MIN_PAGE_SIZE` to
+    /// `MAX_PAGE_SIZE`, or this fails with `InvalidPageSize`. Every page
+    /// records its size, and a file written with another page size fails with
+    /// `PageSizeMismatch` when it is opened.
+    pub fn with_page_size(mut self, page_size: usize) -> Result<Self> {
+        if !is_valid_page_size(page_size) {
+            return Err(StorageError::InvalidPageSize(page_size));
+        }
+        self.page_size = page_size;
+        Ok(self)
+    }
 
-            (
-                ColumnMetadata {
-                    column_id,
-                    table_id,
-                    column_name,
-                    data_type,
-                    is_nullable,
-                },
-                offset,
-            )
-        })
+    pub fn with_sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
     }
 
-    pub fn write_postgres_attribute(
-        &self,
-        file_path: &str,
-        columns_metadata: &Vec<ColumnMetadata>,
-    ) -> Result<()> {
-        let entries: Vec<Vec<u8>> = columns_metadata
-            .iter()
-            .map(|column| {
-                let mut data = vec![];
-                data.extend_from_slice(&column.column_id.to_le_bytes());
-                data.extend_from_slice(&column.table_id.to_le_bytes());
-                data.extend_from_slice(&(column.column_name.len() as u16).to_le_bytes());
-                data.extend_from_slice(column.column_name.as_bytes());
-                data.extend_from_slice(&(column.data_type.len() as u16).to_le_bytes());
-                data.extend_from_slice(column.data_type.as_bytes());
-                data.push(if column.is_nullable { 1 } else { 0 });
-                data
-            })
-            .collect();
+    /// With `AccessMode::ReadOnly`, files are opened read-only, so catalogs
+    /// on read-only media or without write permission can still be read.
+    pub fn with_access_mode(mut self, access_mode: AccessMode) -> Self {
+        self.access_mode = access_mode;
+        self
+    }
 
-        let column_pointer_offsets = self
-            .write_metadata(file_path, entries, |entry| entry.len())
-            .unwrap();
-        self.write_to_table_to_columns_index_file(column_pointer_offsets)?;
-        Ok(())
+    /// Data files are locked for each operation on them: shared while reading,
+    /// exclusive while modifying, so writers in other Storages or processes
+    /// cannot interleave their read-modify-write of a page. The locks are
+    /// advisory and only keep out writers that lock too. With
+    /// `LockWait::Fail`, an operation that would wait fails with
+    /// `StorageError::FileLocked` instead.
+    pub fn with_lock_wait(mut self, lock_wait: LockWait) -> Self {
+        self.lock_wait = lock_wait;
+        self
     }
 
-    pub fn create_postgres_file(&self, file_path: &str) -> Result<()> {
-        let mut file = File::create(file_path)?;
-        let mut page = [0u8; PAGE_SIZE];
-        let lsn: u64 = 12345678;
-        page[0..8].copy_from_slice(&lsn.to_le_bytes());
-        let checksum: u16 = 42;
-        page[8..10].copy_from_slice(&checksum.to_le_bytes());
-        let flags: u16 = 40;
-        page[10..12].copy_from_slice(&flags.to_le_bytes());
-        let lower = 18;
-        page[12..14].copy_from_slice(&(lower as u16).to_le_bytes());
-        let higher = PAGE_SIZE as u16;
-        page[14..16].copy_from_slice(&higher.to_le_bytes());
-        let special_space: u16 = PAGE_SIZE as u16;
-        page[16..18].copy_from_slice(&special_space.to_le_bytes());
-        file.write_all(&page)?;
+    fn sync_file(&self, file: &mut File) -> Result<()> {
+        match self.sync_mode {
+            SyncMode::None => {}
+            SyncMode::Flush => file.flush()?,
+            SyncMode::Fsync => file.sync_data()?,
+        }
         Ok(())
     }
 
-    pub fn get_table_and_columns(&self, table_name: &str) -> Result<()> {}
+    /// Logs every page write to the write-ahead log at `wal_path`, creating it
+    /// if needed. The page is stamped with its record's LSN before it is
+    /// written or cached. Fails with `ReadOnly` on a read-only Storage.
+    pub fn with_wal(mut self, wal_path: impl AsRef<Path>) -> Result<Self> {
+        self.check_writable()?;
+        self.wal = Some(Mutex::new(Wal::open(wal_path)?));
+        Ok(self)
+    }
+
+    /// Replays the write-ahead log at `wal_path` after a crash and returns how
+    /// many records were applied.
+    ///
+    /// Each record's page image is written back unless the page on disk
+    /// already carries that LSN or a later one. A page that fails its checksum
+    /// or header checks is always overwritten, since the crash may have torn
+    /// it. Records for
+    /// files that no longer exist are skipped. The log ends at the first record
+    /// that is cut short or fails its CRC, and it is truncated there so new
+    /// records follow the last complete one. The page images of a transaction
+    /// are applied only if its commit record follows them; a transaction the
+    /// crash cut off is dropped from the log. If this Storage logs to the same
+    /// path, its log is truncated in place.
+    pub fn recover(&self, wal_path: impl AsRef<Path>) -> Result<usize> {
+        self.check_writable()?;
+        match &self.wal {
+            Some(wal) => {
+                let mut wal = wal.lock().unwrap_or_else(PoisonError::into_inner);
+                if wal.path() == wal_path.as_ref() {
+                    return self.replay_wal(&mut wal);
+                }
+                drop(wal);
+                self.replay_wal(&mut Wal::open(wal_path)?)
+            }
+            None => self.replay_wal(&mut Wal::open(wal_path)?),
+        }
+    }
 
-    fn write_to_table_to_columns_index_file(&self, column_pointer_offsets: Vec<u16>) -> Result<()> {
-        let mut data = vec![];
-        for offset in column_pointer_offsets {
-            data.extend_from_slice(&offset.to_le_bytes());
+    fn replay_wal(&self, wal: &mut Wal) -> Result<usize> {
+        let mut end_lsn = None;
+        let mut replayed = 0;
+        // The page images of the transaction being read, held back until its
+        // commit record. A transaction's records are logged together, so
+        // any other record after them means it never committed.
+        let mut pending: Vec<(u64, Lsn, WalRecord)> = Vec::new();
+        for entry in wal.entries()? {
+            let (lsn, entry) = match entry {
+                Ok(entry) => entry,
+                Err(StorageError::CorruptWalRecord { lsn, .. }) => {
+                    end_lsn = Some(lsn);
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+            match entry {
+                WalEntry::Page(record) => {
+                    pending.clear();
+                    replayed += self.replay_record(lsn, record)? as usize;
+                }
+                WalEntry::TxnPage { txn_id, record } => {
+                    pending.retain(|(pending_id, _, _)| *pending_id == txn_id);
+                    pending.push((txn_id, lsn, record));
+                }
+                WalEntry::Commit { txn_id } => {
+                    for (pending_id, lsn, record) in std::mem::take(&mut pending) {
+                        if pending_id == txn_id {
+                            replayed += self.replay_record(lsn, record)? as usize;
+                        }
+                    }
+                }
+            }
         }
-        self.write_metadata("src/base/table_to_columns_index", vec![data], |entry| {
-            entry.len()
-        })?;
+        // A transaction cut off before its commit record goes with the end of
+        // the log, so a later commit record cannot be taken for its own.
+        if let Some((_, lsn, _)) = pending.first() {
+            end_lsn = Some(*lsn);
+        }
+        if let Some(lsn) = end_lsn {
+            wal.truncate(lsn)?;
+        }
+        Ok(replayed)
+    }
+
+    /// Writes the record's page image back unless the page on disk is already
+    /// as new, returning whether it did.
+    fn replay_record(&self, lsn: Lsn, record: WalRecord) -> Result<bool> {
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&record.file_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        let page_size = record.page.len();
+        let page_count = (file.metadata()?.len() / page_size as u64) as u32;
+        let up_to_date = record.page_no < page_count
+            && match read_page_from_disk(&mut file, record.page_no, page_size) {
+                Ok((_, header)) => header.lsn >= lsn,
+                Err(StorageError::ChecksumMismatch { .. })
+                | Err(StorageError::CorruptPage { .. })
+                | Err(StorageError::InvalidFormat { .. }) => false,
+                Err(err) => return Err(err),
+            };
+        if up_to_date {
+            return Ok(false);
+        }
+        let mut page = record.page;
+        self.write_page_out(&mut file, record.page_no, &mut page)?;
+        self.sync_file(&mut file)?;
+        self.buffer_pool().discard_file(&record.file_path);
+        // The map is rebuilt from the restored headers on next use.
+        remove_if_exists(&FreeSpaceMap::path_for(&record.file_path))?;
+        Ok(true)
+    }
+
+    /// Writes the cached dirty pages of `file_path` back to the file.
+    pub fn flush(&self, file_path: &str) -> Result<()> {
+        self.flush_pages(&mut self.buffer_pool(), Some(file_path))
+    }
+
+    pub fn flush_all(&self) -> Result<()> {
+        self.flush_pages(&mut self.buffer_pool(), None)
+    }
+
+    /// Makes every logged page write durable in its data file and empties the
+    /// write-ahead log, so a later `recover` replays only what was logged
+    /// after this. Dirty cached pages are written back first, and every file
+    /// the log names is synced whatever the sync mode. Without a WAL this is
+    /// `flush_all`.
+    pub fn checkpoint(&self) -> Result<()> {
+        self.check_writable()?;
+        // Page writes hold the pool's lock while they log, so holding it here
+        // keeps new records out until the log is emptied.
+        let mut pool = self.buffer_pool();
+        let Some(wal) = &self.wal else {
+            return self.flush_pages(&mut pool, None);
+        };
+        let mut wal = wal.lock().unwrap_or_else(PoisonError::into_inner);
+        self.flush_pages(&mut pool, None)?;
+        let mut file_paths = BTreeSet::new();
+        for record in wal.records()? {
+            file_paths.insert(record?.1.file_path);
+        }
+        for file_path in file_paths {
+            match OpenOptions::new().write(true).open(&file_path) {
+                Ok(file) => file.sync_all()?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        wal.reset()
+    }
+
+    /// Opens a write transaction. Writes made through the returned `Txn` are
+    /// kept from the files, and from every read not made through it, until
+    /// `Txn::commit`; `Txn::rollback`, or dropping the `Txn`, discards them.
+    /// One write transaction may be open at a time: while it is, `begin` and
+    /// every write not made through it fail with `TransactionInProgress`.
+    /// Writes already under way on other threads are not waited for.
+    ///
+    /// With a WAL, committing logs the transaction's pages and a commit
+    /// record and syncs the log before writing any page back, so after a
+    /// crash `recover` restores all of the transaction or none of it. Without
+    /// one, a crash part way through a commit can leave some of it written.
+    pub fn begin(&self) -> Result<Txn<'_>> {
+        self.check_writable()?;
+        let mut txn = self.txn_state();
+        if txn.is_some() {
+            return Err(StorageError::TransactionInProgress);
+        }
+        let txn_id = self.next_txn_id.fetch_add(1, Ordering::Relaxed);
+        *txn = Some(TxnState::new());
+        Ok(Txn::new(self, txn_id))
+    }
 
+    fn flush_pages(&self, pool: &mut BufferPool, file_path: Option<&str>) -> Result<()> {
+        let dirty_pages = pool.dirty_pages(file_path);
+        // Pages are marked clean only once their file has been synced.
+        for file_pages in dirty_pages.chunk_by(|a, b| a.0 .0 == b.0 .0) {
+            let mut file = OpenOptions::new().write(true).open(&file_pages[0].0 .0)?;
+            for (key, page) in file_pages {
+                self.write_page_out(&mut file, key.1, &mut page.clone())?;
+            }
+            self.sync_file(&mut file)?;
+            for (key, _) in file_pages {
+                pool.mark_clean(key);
+            }
+        }
         Ok(())
     }
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn test_write_postgres_class() {
-        let storage = Storage::new();
-        let tables_to_column_index_file_path = "src/base/table_to_columns_index";
-        storage.create_postgres_file("src/base/table_to_columns_index");
-        let table_file_path = "src/base/table".to_string();
-        let columns_metadata = vec![
-            ColumnMetadata {
-                column_id: 1,
-                table_id: 42,
-                column_name: "user_id".to_string(),
-                data_type: "INTEGER".to_string(),
+    fn buffer_pool(&self) -> MutexGuard<'_, BufferPool> {
+        // The pool is consistent between calls, so a panic elsewhere while it
+        // was locked leaves nothing half-updated.
+        self.buffer_pool
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Caches the page, first writing back the page it evicts if that one is
+    /// dirty. Does nothing when the pool has no capacity.
+    fn cache_page(
+        &self,
+        pool: &mut BufferPool,
+        file_path: &str,
+        page_no: u32,
+        page: &[u8],
+        dirty: bool,
+    ) -> Result<()> {
+        if pool.capacity() == 0 {
+            return Ok(());
+        }
+        if let Some(victim) = pool.victim(file_path, page_no) {
+            if let Some(mut victim_page) = pool.dirty_page(&victim) {
+                let mut file = OpenOptions::new().write(true).open(&victim.0)?;
+                self.write_page_out(&mut file, victim.1, &mut victim_page)?;
+                self.sync_file(&mut file)?;
+            }
+            pool.remove(&victim);
+        }
+        pool.put(file_path, page_no, page, dirty);
+        Ok(())
+    }
+
+    /// Registers a new table and its columns in the catalog and returns the
+    /// assigned table_id. The table and column ids come from `next_table_id`
+    /// and `next_column_id`, so the columns' ids increase in definition order
+    /// and no id is reused after its table is dropped.
+    ///
+    /// The attribute entries are written before the pg_class entry, so a
+    /// failure part way leaves at most orphaned columns, never a table without
+    /// its columns. Their table id has been handed out, so no later table
+    /// picks them up.
+    ///
+    /// A default not of its column's type fails with `TypeMismatch` before
+    /// anything is written.
+    pub fn create_table(&self, name: &str, columns: &[ColumnDefinition]) -> Result<u32> {
+        for column in columns {
+            column.check_default()?;
+        }
+        self.ensure_catalog_file(&self.table_file_path)?;
+        self.ensure_catalog_file(&self.column_file_path)?;
+
+        let tables = self.read_postgres_class_in(&self.table_file_path)?;
+        if tables.iter().any(|table| table.table_name == name) {
+            return Err(StorageError::DuplicateTableName(name.to_string()));
+        }
+        let table_id = self.next_table_id()?;
+
+        let columns_metadata = columns
+            .iter()
+            .map(|column| {
+                Ok(ColumnMetadata {
+                    column_id: self.next_column_id()?,
+                    table_id,
+                    column_name: column.name.clone(),
+                    data_type: column.data_type.clone(),
+                    is_nullable: column.is_nullable,
+                    is_primary_key: column.is_primary_key,
+                    default: column.default.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !columns_metadata.is_empty() {
+            self.write_postgres_attribute_in(&self.column_file_path, &columns_metadata)?;
+        }
+        self.write_postgres_class_in(
+            &self.table_file_path,
+            &[TableMetadata {
+                table_id,
+                table_name: name.to_string(),
+            }],
+        )?;
+        Ok(table_id)
+    }
+
+    /// Appends a column to the table and returns its column_id, taken from
+    /// `next_column_id`. Rows already stored have no value for it and read as
+    /// its default, or NULL without one. So a column that is not nullable and
+    /// has no default, or is part of the primary key, can only be added while
+    /// the table has no rows; otherwise this fails with `NullViolation`.
+    pub fn add_column(&self, table_id: u32, column: ColumnDefinition) -> Result<u32> {
+        column.check_default()?;
+        if self.get_table_by_id(table_id)?.is_none() {
+            return Err(StorageError::NotFound(format!(
+                "table with id {}",
+                table_id
+            )));
+        }
+        let columns = self.get_columns_for_table(table_id)?;
+        if columns
+            .iter()
+            .any(|existing| existing.column_name == column.name)
+        {
+            return Err(StorageError::DuplicateColumnName(column.name));
+        }
+        let needs_value =
+            column.is_primary_key || (!column.is_nullable && column.default.is_none());
+        if needs_value && self.has_rows(table_id)? {
+            return Err(StorageError::NullViolation {
+                column_name: column.name,
+            });
+        }
+        let column_id = self.next_column_id()?;
+        self.write_postgres_attribute_in(
+            &self.column_file_path,
+            &[ColumnMetadata {
+                column_id,
+                table_id,
+                column_name: column.name,
+                data_type: column.data_type,
+                is_nullable: column.is_nullable,
+                is_primary_key: column.is_primary_key,
+                default: column.default,
+            }],
+        )?;
+        Ok(column_id)
+    }
+
+    fn has_rows(&self, table_id: u32) -> Result<bool> {
+        let heap_file_path = self.heap_path(table_id);
+        let mut rows = match self.iter_metadata(&heap_file_path, |_, pointer| Ok(((), pointer))) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(false)
+            }
+            result => result?,
+        };
+        Ok(rows.next().transpose()?.is_some())
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Tuples encoding to more than a quarter of a page have their longest
+    /// strings moved out to overflow pages until they fit.
+    fn toast_threshold(&self) -> usize {
+        self.page_size / 4
+    }
+
+    pub fn pg_class_path(&self) -> &str {
+        &self.table_file_path
+    }
+
+    pub fn pg_attribute_path(&self) -> &str {
+        &self.column_file_path
+    }
+
+    /// Path of the heap file holding the rows of `table_id`.
+    pub fn heap_path(&self, table_id: u32) -> String {
+        path_in(&self.base_dir, &table_id.to_string())
+    }
+
+    /// Hands out the next table id from the control file in the base
+    /// directory. The counter is written and synced before the id is
+    /// returned, so an id is never handed out twice, even across a crash;
+    /// a crash before the id is used only leaves a gap.
+    pub fn next_table_id(&self) -> Result<u32> {
+        self.next_id(|control| &mut control.next_table_id)
+    }
+
+    /// Like `next_table_id`, for column ids. They are unique across tables.
+    pub fn next_column_id(&self) -> Result<u32> {
+        self.next_id(|control| &mut control.next_column_id)
+    }
+
+    fn next_id(&self, counter: impl Fn(&mut ControlData) -> &mut u32) -> Result<u32> {
+        self.check_writable()?;
+        let control_path = path_in(&self.base_dir, CONTROL_FILE_NAME);
+        let open = || {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&control_path)
+        };
+        let mut file = match open() {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.create_control_file(&control_path)?;
+                open()?
+            }
+            result => result?,
+        };
+        let _guard = self.lock_file(&control_path, &file, true)?;
+        let result = (|| {
+            let mut bytes = Vec::with_capacity(CONTROL_FILE_SIZE);
+            file.read_to_end(&mut bytes)?;
+            let mut control = ControlData::decode(&bytes)?;
+            let id = *counter(&mut control);
+            *counter(&mut control) =
+                id.checked_add(1)
+                    .ok_or_else(|| StorageError::CorruptControlFile {
+                        reason: "id counter is exhausted".to_string(),
+                    })?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&control.encode())?;
+            file.sync_all()?;
+            Ok(id)
+        })();
+        file.unlock()?;
+        result
+    }
+
+    /// Creates the control file with counters past every id already in the
+    /// catalog, so a base directory written before the file existed does not
+    /// get its ids handed out again.
+    fn create_control_file(&self, control_path: &str) -> Result<()> {
+        let tables = self.catalog_tables()?;
+        let columns = missing_as_empty(self.read_postgres_attribute())?;
+        let control = ControlData {
+            next_table_id: tables
+                .iter()
+                .map(|table| table.table_id)
+                .chain(columns.iter().map(|column| column.table_id))
+                .max()
+                .map_or(1, |max| max + 1),
+            next_column_id: columns
+                .iter()
+                .map(|column| column.column_id)
+                .max()
+                .map_or(1, |max| max + 1),
+        };
+        match self.install_new_file(control_path, &control.encode()) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Removes the table's pg_class entry, then its pg_attribute entries, then
+    /// its heap file if there is one; `create_table` writes them in the
+    /// reverse order. The catalog entries go in one transaction, so with a WAL
+    /// a crash leaves both or neither. The catalog pages the entries were on
+    /// are vacuumed, so their bytes do not linger in dead slots. Fails with
+    /// `NotFound` if there is no table with that id.
+    pub fn drop_table(&self, table_id: u32) -> Result<()> {
+        self.atomically(|storage| {
+            let table_tuple_ids: Vec<TupleId> =
+                missing_as_empty(storage.read_postgres_class_entries(&storage.table_file_path))?
+                    .into_iter()
+                    .filter(|(_, table)| table.table_id == table_id)
+                    .map(|(tuple_id, _)| tuple_id)
+                    .collect();
+            if table_tuple_ids.is_empty() {
+                return Err(StorageError::NotFound(format!(
+                    "table with id {}",
+                    table_id
+                )));
+            }
+            storage.delete_and_vacuum(&storage.table_file_path, &table_tuple_ids)?;
+
+            let column_tuple_ids: Vec<TupleId> = missing_as_empty(
+                storage.read_postgres_attribute_entries(&storage.column_file_path),
+            )?
+            .into_iter()
+            .filter(|(_, column)| column.table_id == table_id)
+            .map(|(tuple_id, _)| tuple_id)
+            .collect();
+            storage.delete_and_vacuum(&storage.column_file_path, &column_tuple_ids)
+        })?;
+
+        let heap_file_path = self.heap_path(table_id);
+        self.buffer_pool().discard_file(&heap_file_path);
+        remove_if_exists(&heap_file_path)?;
+        remove_if_exists(&FreeSpaceMap::path_for(&heap_file_path))
+    }
+
+    /// Renames the table, failing with `DuplicateTableName` if another table
+    /// already has the name and with `NotFound` if there is no such table.
+    pub fn rename_table(&self, table_id: u32, new_name: &str) -> Result<()> {
+        let tables = self.read_postgres_class_entries(&self.table_file_path)?;
+        if tables
+            .iter()
+            .any(|(_, table)| table.table_name == new_name && table.table_id != table_id)
+        {
+            return Err(StorageError::DuplicateTableName(new_name.to_string()));
+        }
+        let (tuple_id, mut table) = tables
+            .into_iter()
+            .find(|(_, table)| table.table_id == table_id)
+            .ok_or_else(|| StorageError::NotFound(format!("table with id {}", table_id)))?;
+        table.table_name = new_name.to_string();
+        self.update_or_move_entry(
+            &self.table_file_path,
+            tuple_id,
+            &self.encode_postgres_class(&table),
+        )?;
+        Ok(())
+    }
+
+    /// Renames column `column_id` of `table_id`, failing with `NotFound` if
+    /// the table has no such column, or `DuplicateColumnName` if another of
+    /// its columns already has the name.
+    pub fn rename_column(&self, table_id: u32, column_id: u32, new_name: &str) -> Result<()> {
+        self.check_writable()?;
+        let columns: Vec<(TupleId, ColumnMetadata)> = self
+            .read_postgres_attribute_entries(&self.column_file_path)?
+            .into_iter()
+            .filter(|(_, column)| column.table_id == table_id)
+            .collect();
+        if columns
+            .iter()
+            .any(|(_, column)| column.column_name == new_name && column.column_id != column_id)
+        {
+            return Err(StorageError::DuplicateColumnName(new_name.to_string()));
+        }
+        let (tuple_id, mut column) = columns
+            .into_iter()
+            .find(|(_, column)| column.column_id == column_id)
+            .ok_or_else(|| {
+                StorageError::NotFound(format!("column {} in table {}", column_id, table_id))
+            })?;
+        column.column_name = new_name.to_string();
+        let index = self
+            .attribute_index(&self.column_file_path)?
+            .ok_or(StorageError::TransactionInProgress)?;
+        let new_tuple_id = self.update_or_move_entry(
+            &self.column_file_path,
+            tuple_id,
+            &encode_postgres_attribute(&column),
+        )?;
+        if new_tuple_id != tuple_id {
+            index.insert(table_id, new_tuple_id)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the entry through `update_entry`, or if its page has no room
+    /// for the new bytes, writes them as a new entry wherever they fit and
+    /// deletes the old one. Returns where the entry ends up.
+    ///
+    /// The move is made `atomically`, so with a WAL a crash leaves either
+    /// the old entry or the new one, never both or neither.
+    fn update_or_move_entry(
+        &self,
+        file_path: &str,
+        tuple_id: TupleId,
+        new_bytes: &[u8],
+    ) -> Result<TupleId> {
+        match self.update_entry(file_path, tuple_id, new_bytes) {
+            Err(StorageError::PageFull { .. }) => self.atomically(|storage| {
+                let pointer_offsets =
+                    storage
+                        .write_metadata(file_path, vec![new_bytes.to_vec()], |entry| entry.len())?;
+                storage.delete_entry(file_path, tuple_id)?;
+                Ok(TupleId::from_pointer_offset(pointer_offsets[0]))
+            }),
+            result => result.map(|()| tuple_id),
+        }
+    }
+
+    /// Like `drop_table`, for the table named `name`.
+    pub fn drop_table_by_name(&self, name: &str) -> Result<()> {
+        let table = self
+            .get_table_by_name(name)?
+            .ok_or_else(|| StorageError::NotFound(format!("table {:?}", name)))?;
+        self.drop_table(table.table_id)
+    }
+
+    /// Deletes the entries, then vacuums each page they were on.
+    fn delete_and_vacuum(&self, file_path: &str, tuple_ids: &[TupleId]) -> Result<()> {
+        for tuple_id in tuple_ids {
+            self.delete_entry(file_path, *tuple_id)?;
+        }
+        let page_nos: BTreeSet<u32> = tuple_ids.iter().map(|tuple_id| tuple_id.page_no).collect();
+        for page_no in page_nos {
+            self.vacuum_page(file_path, page_no)?;
+        }
+        Ok(())
+    }
+
+    fn ensure_catalog_file(&self, file_path: &str) -> Result<()> {
+        match self.create_postgres_file(file_path) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+            result => result,
+        }
+    }
+
+    pub fn read_metadata<F, T>(&self, file_path: &str, parse_entry: F) -> Result<Vec<T>>
+    where
+        F: Fn(&[u8], usize) -> Result<(T, usize)>,
+    {
+        self.iter_metadata(file_path, parse_entry)?.collect()
+    }
+
+    /// Like `read_metadata`, but reads a page only once the iteration reaches
+    /// it, so stopping early skips the remaining pages. Each page is read
+    /// under its own shared lock, so writes between pages are not blocked and
+    /// may or may not be seen. Pages appended after the call are not visited.
+    /// After the first error the iterator yields nothing more.
+    pub fn iter_metadata<'a, F, T>(
+        &'a self,
+        file_path: &'a str,
+        parse_entry: F,
+    ) -> Result<MetadataIter<'a, T>>
+    where
+        F: Fn(&[u8], usize) -> Result<(T, usize)> + 'a,
+    {
+        self.iter_entries(file_path, parse_entry, false)
+    }
+
+    fn iter_entries<'a, F, T>(
+        &'a self,
+        file_path: impl Into<Cow<'a, str>>,
+        parse_entry: F,
+        continue_after_error: bool,
+    ) -> Result<MetadataIter<'a, T>>
+    where
+        F: Fn(&[u8], usize) -> Result<(T, usize)> + 'a,
+    {
+        let mut file = self.open_data_file(file_path)?;
+        let page_count = self.page_count(&file)?;
+        self.unlock_data_file(&mut file)?;
+        Ok(MetadataIter {
+            storage: self,
+            page_count,
+            file,
+            parse_entry: Box::new(parse_entry),
+            page: None,
+            next_page_no: 0,
+            next_slot: 0,
+            continue_after_error,
+            done: false,
+        })
+    }
+
+    /// Maps the file into memory for reading without copying pages. Only built
+    /// with the `mmap` feature on 64-bit Unix.
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    pub fn open_mapped<'a>(&'a self, file_path: &str) -> Result<MappedFile<'a>> {
+        MappedFile::open(self, file_path)
+    }
+
+    /// Like `read_metadata`, but also returns where each live entry is stored.
+    fn read_entries<F, T>(&self, file_path: &str, parse_entry: F) -> Result<Vec<(TupleId, T)>>
+    where
+        F: Fn(&[u8], usize) -> Result<(T, usize)>,
+    {
+        let mut entries = self.iter_metadata(file_path, parse_entry)?;
+        std::iter::from_fn(|| entries.next_entry()).collect()
+    }
+
+    /// Appends the entries to the file, keeping the ones already there. Each
+    /// entry goes on the first page the free space map shows room on, else on
+    /// the last page if it fits there, else on a freshly initialized page at
+    /// the end of the file. Returns the page number and slot directory offset
+    /// of each entry.
+    ///
+    /// Every entry is placed in memory before any page is written, so a batch
+    /// rejected with `EntryTooLarge` leaves the file as it was.
+    pub fn write_metadata<F>(
+        &self,
+        file_path: &str,
+        entries: Vec<Vec<u8>>,
+        calculate_size: F,
+    ) -> Result<Vec<(u32, u16)>>
+    where
+        F: Fn(&[u8]) -> usize,
+    {
+        self.store_entries(file_path, entries, calculate_size, 0, false)
+    }
+
+    /// Like `write_metadata`, but pages this call adds to the file reserve
+    /// `special_size` bytes at their end for `write_special`, and entries
+    /// stop short of them. Entries still go on existing pages with room,
+    /// whatever special space those have. Fails with `SpecialSpaceOverflow`
+    /// if the special space would leave no room for an entry.
+    pub fn write_metadata_with_special<F>(
+        &self,
+        file_path: &str,
+        entries: Vec<Vec<u8>>,
+        calculate_size: F,
+        special_size: u16,
+    ) -> Result<Vec<(u32, u16)>>
+    where
+        F: Fn(&[u8]) -> usize,
+    {
+        self.store_entries(file_path, entries, calculate_size, special_size, false)
+    }
+
+    /// Replaces the whole contents of the file with the entries, packed from
+    /// page 0 on. Every existing page is started over empty, so nothing of
+    /// the old entries is left behind; pages the new entries do not need stay
+    /// in the file as free pages. Returns the same as `write_metadata`.
+    ///
+    /// The pages are rewritten `atomically`, so with a WAL a crash leaves the
+    /// old entries or the new ones, never a mix.
+    pub fn rewrite_metadata<F>(
+        &self,
+        file_path: &str,
+        entries: Vec<Vec<u8>>,
+        calculate_size: F,
+    ) -> Result<Vec<(u32, u16)>>
+    where
+        F: Fn(&[u8]) -> usize,
+    {
+        self.atomically(|storage| {
+            storage.store_entries(file_path, entries, calculate_size, 0, true)
+        })
+    }
+
+    fn store_entries<F>(
+        &self,
+        file_path: &str,
+        entries: Vec<Vec<u8>>,
+        calculate_size: F,
+        special_size: u16,
+        replace: bool,
+    ) -> Result<Vec<(u32, u16)>>
+    where
+        F: Fn(&[u8]) -> usize,
+    {
+        let mut file = self.open_writable_data_file(file_path)?;
+
+        let available = self.page_size - PAGE_HEADER_SIZE - ITEM_ID_SIZE;
+        if special_size as usize > available {
+            return Err(StorageError::SpecialSpaceOverflow {
+                size: special_size as usize,
+                available,
+            });
+        }
+        let init = PageInit {
+            special_size,
+            ..PageInit::default()
+        };
+        let entry_sizes: Vec<usize> = entries.iter().map(|entry| calculate_size(entry)).collect();
+        let empty_page = init.build(self.page_size);
+        let empty_header = PageHeader::decode(&empty_page);
+        let max_entry_size = empty_header.free_space() - ITEM_ID_SIZE;
+        if let Some(&size) = entry_sizes.iter().find(|size| **size > max_entry_size) {
+            return Err(StorageError::EntryTooLarge {
+                size,
+                max: max_entry_size,
+            });
+        }
+
+        let mut fsm = self.load_fsm(&mut file)?;
+        let mut page_count = self.page_count(&file)?;
+        let mut pages = BTreeMap::new();
+        if replace {
+            for page_no in 0..page_count {
+                pages.insert(page_no, (empty_page.clone(), empty_header));
+                fsm.set(page_no, empty_header.free_space());
+            }
+        }
+
+        let mut pointer_offsets = Vec::new();
+        for (entry, entry_size) in entries.iter().zip(entry_sizes) {
+            let page_no = self.page_for_entry(
+                &mut file,
+                &mut fsm,
+                &mut pages,
+                &mut page_count,
+                entry_size,
+                init,
+            )?;
+            let (page, header): &mut (Box<[u8]>, PageHeader) = pages.get_mut(&page_no).unwrap();
+
+            header.higher -= entry_size as u16;
+            let slot = header.slot_count() as u16;
+            write_item_id(page, slot, ItemId::normal(header.higher, entry_size as u16));
+            pointer_offsets.push((page_no, header.lower));
+            header.lower += ITEM_ID_SIZE as u16;
+
+            let higher = header.higher as usize;
+            page[higher..higher + entry_size].copy_from_slice(entry);
+            fsm.set(page_no, header.free_space());
+        }
+
+        // New pages sit past the end of the file, so writing in page order
+        // never leaves a hole.
+        for (page_no, (mut page, header)) in pages {
+            header.write_to(&mut page);
+            self.write_page(&mut file, page_no, &mut page)?;
+        }
+        fsm.save()?;
+
+        Ok(pointer_offsets)
+    }
+
+    /// Picks the page an entry of `entry_size` bytes goes on, loading it into
+    /// `pages`. Pages already loaded are checked first since their headers are
+    /// current, then the pages the free space map shows room on. A page added
+    /// at the end is built from `init`.
+    fn page_for_entry(
+        &self,
+        file: &mut DataFile,
+        fsm: &mut FreeSpaceMap,
+        pages: &mut BTreeMap<u32, (Box<[u8]>, PageHeader)>,
+        page_count: &mut u32,
+        entry_size: usize,
+        init: PageInit,
+    ) -> Result<u32> {
+        if let Some(page_no) = pages
+            .iter()
+            .find(|(_, (_, header))| header.entry_fits(entry_size))
+            .map(|(page_no, _)| *page_no)
+        {
+            return Ok(page_no);
+        }
+
+        let mut candidates = Vec::new();
+        while let Some(page_no) = fsm.find(entry_size + ITEM_ID_SIZE, |page_no| {
+            pages.contains_key(&page_no) || candidates.contains(&page_no)
+        }) {
+            candidates.push(page_no);
+            let (page, header) = self.read_page(file, page_no)?;
+            if header.entry_fits(entry_size) {
+                pages.insert(page_no, (page, header));
+                return Ok(page_no);
+            }
+            fsm.set(page_no, header.free_space());
+        }
+
+        // The map rounds down, so the last page may still fit an entry it
+        // shows no room for.
+        if let Some(last_page_no) = page_count.checked_sub(1) {
+            if let btree_map::Entry::Vacant(vacant) = pages.entry(last_page_no) {
+                let (page, header) = self.read_page(file, last_page_no)?;
+                if header.entry_fits(entry_size) {
+                    vacant.insert((page, header));
+                    return Ok(last_page_no);
+                }
+            }
+        }
+
+        let page_no = *page_count;
+        *page_count += 1;
+        let page = init.build(self.page_size);
+        let header = PageHeader::decode(&page);
+        pages.insert(page_no, (page, header));
+        Ok(page_no)
+    }
+
+    /// Approximate free bytes of the page according to the free space map, in
+    /// multiples of `FSM_BUCKET_SIZE`.
+    pub fn free_space(&self, file_path: &str, page_no: u32) -> Result<usize> {
+        let mut file = self.open_data_file(file_path)?;
+        let mut fsm = self.load_fsm(&mut file)?;
+        if self.access_mode == AccessMode::ReadWrite {
+            fsm.save()?;
+        }
+        fsm.get(page_no)
+            .ok_or_else(|| StorageError::NotFound(format!("page {}", page_no)))
+    }
+
+    /// Loads the file's free space map, filling in pages it does not cover
+    /// from their headers.
+    fn load_fsm(&self, file: &mut DataFile) -> Result<FreeSpaceMap> {
+        let mut fsm = FreeSpaceMap::load(&file.path)?;
+        let page_count = self.page_count(file)?;
+        fsm.truncate(page_count);
+        for page_no in fsm.len()..page_count {
+            let (_, header) = self.read_page(file, page_no)?;
+            fsm.set(page_no, header.free_space());
+        }
+        Ok(fsm)
+    }
+
+    /// Marks the entry's slot as dead. The slot itself is never reused, so the
+    /// slot numbers of the remaining entries stay the same. If the entry is the
+    /// lowest one in the data area, its bytes go back to the page's free space
+    /// and the slot becomes unused; otherwise they stay until `vacuum_page`
+    /// reclaims them.
+    pub fn delete_entry(&self, file_path: &str, tuple_id: TupleId) -> Result<()> {
+        let mut file = self.open_writable_data_file(file_path)?;
+        let mut fsm = self.load_fsm(&mut file)?;
+        let (mut page, mut header, item_id) = self.read_live_slot(&mut file, tuple_id)?;
+
+        if item_id.offset == header.higher {
+            let extent_end = entry_extent_end(&page, &header, item_id.offset as usize);
+            page[item_id.offset as usize..extent_end].fill(0);
+            write_item_id(&mut page, tuple_id.slot, ItemId::UNUSED);
+            header.higher = extent_end as u16;
+            header.write_to(&mut page);
+        } else {
+            write_item_id(
+                &mut page,
+                tuple_id.slot,
+                ItemId {
+                    status: ItemStatus::Dead,
+                    ..item_id
+                },
+            );
+        }
+        self.write_page(&mut file, tuple_id.page_no, &mut page)?;
+        fsm.set(tuple_id.page_no, header.free_space());
+        fsm.save()
+    }
+
+    /// Replaces the entry's bytes, keeping its slot number. The entry may grow
+    /// into the bytes up to the next entry on the page; a payload that fits
+    /// there is written in place with the remainder zeroed, anything larger is
+    /// copied into the page's free space and the slot repointed at it.
+    pub fn update_entry(&self, file_path: &str, tuple_id: TupleId, new_bytes: &[u8]) -> Result<()> {
+        let mut file = self.open_writable_data_file(file_path)?;
+        let mut fsm = self.load_fsm(&mut file)?;
+        let (mut page, mut header, item_id) = self.read_live_slot(&mut file, tuple_id)?;
+        let pointer = item_id.offset as usize;
+        let extent_end = entry_extent_end(&page, &header, pointer);
+
+        if new_bytes.len() <= extent_end - pointer {
+            page[pointer..pointer + new_bytes.len()].copy_from_slice(new_bytes);
+            page[pointer + new_bytes.len()..extent_end].fill(0);
+            write_item_id(
+                &mut page,
+                tuple_id.slot,
+                ItemId::normal(item_id.offset, new_bytes.len() as u16),
+            );
+        } else {
+            if new_bytes.len() > header.free_space() {
+                return Err(StorageError::PageFull {
+                    needed: new_bytes.len(),
+                    available: header.free_space(),
+                });
+            }
+
+            let higher = header.higher as usize;
+            header.higher -= new_bytes.len() as u16;
+            page[pointer..extent_end].fill(0);
+            page[header.higher as usize..higher].copy_from_slice(new_bytes);
+            write_item_id(
+                &mut page,
+                tuple_id.slot,
+                ItemId::normal(header.higher, new_bytes.len() as u16),
+            );
+            header.write_to(&mut page);
+        }
+        self.write_page(&mut file, tuple_id.page_no, &mut page)?;
+        fsm.set(tuple_id.page_no, header.free_space());
+        fsm.save()
+    }
+
+    /// Moves the page's live entries together at the top of the data area so
+    /// the bytes of deleted entries become free space, and returns how many
+    /// bytes were reclaimed. Dead slots are marked unused but never handed
+    /// out again, and live entries keep their slot numbers, so existing
+    /// `TupleId`s remain valid.
+    ///
+    /// The compacted page is written as a whole through `write_page`, so with a
+    /// WAL its image is logged first and `recover` restores it if a crash tears
+    /// the write.
+    pub fn vacuum_page(&self, file_path: &str, page_no: u32) -> Result<usize> {
+        let mut file = self.open_writable_data_file(file_path)?;
+        if page_no >= self.page_count(&file)? {
+            return Err(StorageError::NotFound(format!("page {}", page_no)));
+        }
+        let mut fsm = self.load_fsm(&mut file)?;
+        let reclaimed = self.vacuum_loaded_page(&mut file, &mut fsm, page_no)?;
+        fsm.save()?;
+        Ok(reclaimed)
+    }
+
+    /// Vacuums every page of the file and returns the total bytes reclaimed.
+    pub fn vacuum(&self, file_path: &str) -> Result<usize> {
+        let mut file = self.open_writable_data_file(file_path)?;
+        let mut fsm = self.load_fsm(&mut file)?;
+        let mut reclaimed = 0;
+        for page_no in 0..self.page_count(&file)? {
+            reclaimed += self.vacuum_loaded_page(&mut file, &mut fsm, page_no)?;
+        }
+        fsm.save()?;
+        Ok(reclaimed)
+    }
+
+    fn vacuum_loaded_page(
+        &self,
+        file: &mut DataFile,
+        fsm: &mut FreeSpaceMap,
+        page_no: u32,
+    ) -> Result<usize> {
+        let (page, mut header) = self.read_page(file, page_no)?;
+        let item_ids = item_ids(&page, &header);
+        let mut live_slots: Vec<(u16, ItemId)> = (0..)
+            .zip(item_ids.iter().copied())
+            .filter(|(_, item_id)| item_id.is_normal())
+            .collect();
+        // Copying from the highest entry down keeps the entries in the same
+        // order and never overwrites bytes not yet copied.
+        live_slots.sort_by_key(|(_, item_id)| std::cmp::Reverse(item_id.offset));
+
+        let mut compacted = page.clone();
+        for (slot, item_id) in (0..).zip(&item_ids) {
+            if !item_id.is_normal() {
+                write_item_id(&mut compacted, slot, ItemId::UNUSED);
+            }
+        }
+        let mut higher = header.special_space as usize;
+        for (slot, item_id) in live_slots {
+            let offset = item_id.offset as usize;
+            let length = item_id.length as usize;
+            compacted.copy_within(offset..offset + length, higher - length);
+            higher -= length;
+            write_item_id(
+                &mut compacted,
+                slot,
+                ItemId::normal(higher as u16, item_id.length),
+            );
+        }
+        compacted[header.higher as usize..higher].fill(0);
+
+        let reclaimed = higher - header.higher as usize;
+        if compacted != page {
+            header.higher = higher as u16;
+            header.write_to(&mut compacted);
+            self.write_page(file, page_no, &mut compacted)?;
+        }
+        fsm.set(page_no, header.free_space());
+        Ok(reclaimed)
+    }
+
+    /// Reads the page holding `tuple_id` and returns it with its header and the
+    /// entry's `ItemId`, failing if the slot does not exist or does not hold a
+    /// live entry.
+    fn read_live_slot(
+        &self,
+        file: &mut DataFile,
+        tuple_id: TupleId,
+    ) -> Result<(Box<[u8]>, PageHeader, ItemId)> {
+        if tuple_id.page_no >= self.page_count(file)? {
+            return Err(slot_not_found(tuple_id));
+        }
+        let (page, header) = self.read_page(file, tuple_id.page_no)?;
+        if header.is_overflow() || tuple_id.slot as usize >= header.slot_count() {
+            return Err(slot_not_found(tuple_id));
+        }
+        let item_id = read_item_id(&page, tuple_id.slot);
+        if !item_id.is_normal() {
+            return Err(slot_not_found(tuple_id));
+        }
+        Ok((page, header, item_id))
+    }
+
+    /// Opens the file for reading under a shared lock, held until the
+    /// `DataFile` is dropped.
+    fn open_data_file<'a>(&'a self, file_path: impl Into<Cow<'a, str>>) -> Result<DataFile<'a>> {
+        let path = file_path.into();
+        let mut file = DataFile {
+            file: File::open(&*path)?,
+            path,
+            lock: None,
+        };
+        self.lock_data_file(&mut file, false)?;
+        self.check_page_size(&file.file)?;
+        Ok(file)
+    }
+
+    /// Opens the file for an operation that modifies it, under an exclusive
+    /// lock held until the `DataFile` is dropped. Fails with `ReadOnly`
+    /// before touching the file if this Storage is read-only.
+    fn open_writable_data_file<'a>(
+        &'a self,
+        file_path: impl Into<Cow<'a, str>>,
+    ) -> Result<DataFile<'a>> {
+        self.check_writable()?;
+        let path = file_path.into();
+        let mut file = DataFile {
+            file: OpenOptions::new().read(true).write(true).open(&*path)?,
+            path,
+            lock: None,
+        };
+        self.lock_data_file(&mut file, true)?;
+        self.check_page_size(&file.file)?;
+        Ok(file)
+    }
+
+    /// Like `open_data_file`, but without the page size check, for
+    /// inspecting a file that may be damaged.
+    fn open_data_file_unchecked<'a>(&'a self, file_path: &'a str) -> Result<DataFile<'a>> {
+        let mut file = DataFile {
+            path: Cow::Borrowed(file_path),
+            file: File::open(file_path)?,
+            lock: None,
+        };
+        self.lock_data_file(&mut file, false)?;
+        Ok(file)
+    }
+
+    /// Fails with `PageSizeMismatch` if the first page of the file records a
+    /// page size other than this Storage's. Only the header is read, so a
+    /// file of larger pages is caught before its first page is read as
+    /// several smaller ones. A file too short for a header, or whose first
+    /// page is not in the current layout, is left for the page reads to
+    /// reject.
+    fn check_page_size(&self, mut file: &File) -> Result<()> {
+        let mut bytes = [0u8; PAGE_HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        match file.read_exact(&mut bytes) {
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let header = PageHeader::decode(&bytes);
+        if header.check_format().is_ok() && header.page_size as usize != self.page_size {
+            return Err(StorageError::PageSizeMismatch {
+                expected: self.page_size,
+                found: header.page_size as usize,
+            });
+        }
+        Ok(())
+    }
+
+    fn lock_data_file<'a>(&'a self, file: &mut DataFile<'a>, exclusive: bool) -> Result<()> {
+        file.lock = Some(self.lock_file(&file.path, &file.file, exclusive)?);
+        Ok(())
+    }
+
+    /// Locks `path` against the other threads of this Storage, then the open
+    /// file against other Storages and processes. The path stays locked until
+    /// the guard is dropped, the file until it is unlocked or closed.
+    fn lock_file(&self, path: &str, file: &File, exclusive: bool) -> Result<FileLockGuard<'_>> {
+        let wait = self.lock_wait == LockWait::Block;
+        let locked = || StorageError::FileLocked(path.to_string());
+        let guard = self
+            .file_locks
+            .lock(path, exclusive, wait)
+            .ok_or_else(locked)?;
+        if guard.is_nested() {
+            return Ok(guard);
+        }
+        let result = match (wait, exclusive) {
+            (true, false) => file.lock_shared().map_err(TryLockError::Error),
+            (true, true) => file.lock().map_err(TryLockError::Error),
+            (false, false) => file.try_lock_shared(),
+            (false, true) => file.try_lock(),
+        };
+        match result {
+            Ok(()) => Ok(guard),
+            Err(TryLockError::WouldBlock) => Err(locked()),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+
+    fn unlock_data_file(&self, file: &mut DataFile) -> Result<()> {
+        file.file.unlock()?;
+        file.lock = None;
+        Ok(())
+    }
+
+    /// Fails with `ReadOnly` on a read-only Storage, and with
+    /// `TransactionInProgress` while a write transaction is open, unless the
+    /// call is made through it.
+    fn check_writable(&self) -> Result<()> {
+        match self.access_mode {
+            AccessMode::ReadWrite => {}
+            AccessMode::ReadOnly => return Err(StorageError::ReadOnly),
+        }
+        match &*self.txn_state() {
+            Some(txn) if !txn.in_scope() => Err(StorageError::TransactionInProgress),
+            _ => Ok(()),
+        }
+    }
+
+    fn txn_state(&self) -> MutexGuard<'_, Option<TxnState>> {
+        // The state is only changed whole, so a panic elsewhere while it was
+        // locked leaves nothing half-updated.
+        self.txn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `f` on the open transaction if the calling thread is in a call
+    /// made through it, so the call sees and makes the transaction's writes.
+    fn with_scoped_txn<T>(&self, f: impl FnOnce(&mut TxnState) -> T) -> Option<T> {
+        self.txn_state()
+            .as_mut()
+            .filter(|txn| txn.in_scope())
+            .map(f)
+    }
+
+    /// Runs `call` in a transaction of its own, committed when it returns, so
+    /// with a WAL a crash leaves all of its page writes or none. A call that
+    /// is already part of a transaction just runs, and commits with it.
+    fn atomically<T>(&self, call: impl FnOnce(&Storage) -> Result<T>) -> Result<T> {
+        if self.with_scoped_txn(|_| ()).is_some() {
+            return call(self);
+        }
+        let mut txn = self.begin()?;
+        let result = txn.run(call)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    /// Number of complete pages in the file, counting cached pages that have
+    /// not been written back yet and, inside a transaction, the pages it
+    /// added. A trailing partial page is ignored.
+    fn page_count(&self, file: &DataFile) -> Result<u32> {
+        let on_disk = (file.file.metadata()?.len() / self.page_size as u64) as u32;
+        let buffered = self
+            .with_scoped_txn(|txn| txn.page_count(&file.path))
+            .unwrap_or(0);
+        Ok(on_disk
+            .max(self.buffer_pool().page_count(&file.path))
+            .max(buffered))
+    }
+
+    /// Decodes the page for inspection, as cached in the buffer pool or else
+    /// as on disk. The page is not verified first: a corrupt or foreign page
+    /// is reported as far as it decodes, with its problems listed in
+    /// `PageInfo::anomalies`, and only failing to read it is an error. A
+    /// file written with another page size is read in pages of this Storage's
+    /// size, with the mismatch among the anomalies. A compressed page that
+    /// does not expand is reported as stored.
+    pub fn dump_page(&self, file_path: &str, page_no: u32) -> Result<PageInfo> {
+        let mut file = self.open_data_file_unchecked(file_path)?;
+        let cached = self.buffer_pool().get(file_path, page_no);
+        let page = match cached {
+            Some(page) => page,
+            None => {
+                let mut slot = read_page_slot(&mut file.file, page_no, self.page_size)?;
+                match expand_stored_page(&mut slot, page_no) {
+                    Ok(()) => slot,
+                    // The slot as stored is shown instead, with why it does
+                    // not expand leading the anomalies.
+                    Err(StorageError::CorruptPage { reason, .. }) => {
+                        let mut info = PageInfo::new(&slot, page_no);
+                        info.anomalies.insert(0, reason);
+                        return Ok(info);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        };
+        Ok(PageInfo::new(&page, page_no))
+    }
+
+    /// Checks every page of the file on disk: its header, checksum and
+    /// invariants, that its slots point into the data area without
+    /// overlapping, and that its entries decode within their bounds. Entries
+    /// are decoded as catalog rows or heap tuples depending on the file, and
+    /// the overflow chains of heap tuples are followed to their end. All
+    /// problems are listed rather than stopping at the first, and only
+    /// failing to read the file is an error. Pages are read from disk, not the
+    /// buffer pool, so `flush` first to check pages not yet written back.
+    pub fn verify_file(&self, file_path: &str) -> Result<VerifyReport> {
+        let mut file = self.open_data_file_unchecked(file_path)?;
+        verify::verify(&mut file.file, self.page_size, self.entry_format(file_path))
+    }
+
+    fn entry_format(&self, file_path: &str) -> EntryFormat {
+        let path = Path::new(file_path);
+        if file_path == self.table_file_path {
+            EntryFormat::PgClass
+        } else if file_path == self.column_file_path {
+            EntryFormat::PgAttribute
+        } else if path.parent() == Some(self.base_dir.as_path())
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.parse::<u32>().is_ok())
+        {
+            EntryFormat::Heap
+        } else {
+            EntryFormat::Opaque
+        }
+    }
+
+    /// Reads a page from the buffer pool, or from disk if it is not cached.
+    /// Inside a transaction, the transaction's own image of the page comes
+    /// first.
+    fn read_page(&self, file: &mut DataFile, page_no: u32) -> Result<(Box<[u8]>, PageHeader)> {
+        if let Some(Some(page)) = self.with_scoped_txn(|txn| txn.page(&file.path, page_no)) {
+            let header = PageHeader::decode(&page);
+            return Ok((page, header));
+        }
+        let mut pool = self.buffer_pool();
+        if let Some(page) = pool.get(&file.path, page_no) {
+            let header = PageHeader::decode(&page);
+            return Ok((page, header));
+        }
+        let (page, header) = read_page_from_disk(&mut file.file, page_no, self.page_size)?;
+        self.page_reads.fetch_add(1, Ordering::Relaxed);
+        self.cache_page(&mut pool, &file.path, page_no, &page, false)?;
+        Ok((page, header))
+    }
+
+    /// Like `read_page`, but on a miss also reads the pages after it, up to
+    /// the read-ahead window and page `end`, and caches them; see
+    /// `with_read_ahead`.
+    fn read_page_ahead(
+        &self,
+        file: &mut DataFile,
+        page_no: u32,
+        end: u32,
+    ) -> Result<(Box<[u8]>, PageHeader)> {
+        let capacity = self.buffer_pool().capacity();
+        let window = self
+            .read_ahead
+            .min(capacity.saturating_sub(1).try_into().unwrap_or(u32::MAX));
+        let in_txn = self.with_scoped_txn(|txn| txn.page(&file.path, page_no).is_some());
+        if window == 0 || in_txn == Some(true) {
+            return self.read_page(file, page_no);
+        }
+        let mut pool = self.buffer_pool();
+        if let Some(page) = pool.get(&file.path, page_no) {
+            let header = PageHeader::decode(&page);
+            return Ok((page, header));
+        }
+        let on_disk = (file.file.metadata()?.len() / self.page_size as u64) as u32;
+        let last = end.min(on_disk).min(page_no.saturating_add(window + 1));
+        let mut run = 1;
+        while page_no + run < last && !pool.contains(&file.path, page_no + run) {
+            run += 1;
+        }
+        let mut pages = vec![0u8; run as usize * self.page_size];
+        file.file
+            .seek(SeekFrom::Start(page_no as u64 * self.page_size as u64))?;
+        file.file.read_exact(&mut pages)?;
+        self.page_reads.fetch_add(1, Ordering::Relaxed);
+
+        let mut chunks = pages.chunks_mut(self.page_size);
+        let first = chunks.next().expect("the run holds the page");
+        expand_stored_page(first, page_no)?;
+        let page: Box<[u8]> = (*first).into();
+        let header = verify_page(&page, page_no)?;
+        self.cache_page(&mut pool, &file.path, page_no, &page, false)?;
+        for (ahead_no, ahead) in (page_no + 1..).zip(chunks) {
+            // A page that does not verify is left for the read that reaches
+            // it to report.
+            if expand_stored_page(ahead, ahead_no).is_err() || verify_page(ahead, ahead_no).is_err()
+            {
+                break;
+            }
+            if let Some(victim) = pool.victim(&file.path, ahead_no) {
+                if pool.dirty_page(&victim).is_some() {
+                    break;
+                }
+                pool.remove(&victim);
+            }
+            pool.put(&file.path, ahead_no, ahead, false);
+        }
+        Ok((page, header))
+    }
+
+    /// Logs the page if there is a WAL, then writes it into the buffer pool, or
+    /// straight to disk if the pool has no capacity. Inside a transaction the
+    /// page is kept with the transaction's other writes instead, to be logged
+    /// and written when it commits.
+    fn write_page(&self, file: &mut DataFile, page_no: u32, page: &mut [u8]) -> Result<()> {
+        let mut header = PageHeader::parse(page)?;
+        if self
+            .with_scoped_txn(|txn| txn.put_page(&file.path, page_no, page))
+            .is_some()
+        {
+            return Ok(());
+        }
+        let mut pool = self.buffer_pool();
+        if let Some(wal) = &self.wal {
+            let mut wal = wal.lock().unwrap_or_else(PoisonError::into_inner);
+            header.lsn = wal.next_lsn();
+            header.write_to(page);
+            wal.append(&WalRecord {
+                file_path: file.path.to_string(),
+                page_no,
+                page: Box::from(&*page),
+            })?;
+            match self.sync_mode {
+                SyncMode::None => {}
+                SyncMode::Flush => wal.flush()?,
+                SyncMode::Fsync => wal.sync()?,
+            }
+        }
+        self.store_page(&mut pool, file, page_no, page)
+    }
+
+    /// Writes an already logged page into the buffer pool, or straight to disk
+    /// if the pool has no capacity.
+    fn store_page(
+        &self,
+        pool: &mut BufferPool,
+        file: &mut DataFile,
+        page_no: u32,
+        page: &mut [u8],
+    ) -> Result<()> {
+        if pool.capacity() == 0 {
+            self.write_page_out(&mut file.file, page_no, page)?;
+            return self.sync_file(&mut file.file);
+        }
+        self.cache_page(pool, &file.path, page_no, page, true)
+    }
+
+    /// Entries of the Storage's pg_class file. The pg_class and pg_attribute
+    /// methods below work on the files under the Storage's base directory and
+    /// create them when writing to them for the first time.
+    pub fn read_postgres_class(&self) -> Result<Vec<TableMetadata>> {
+        self.read_postgres_class_in(&self.table_file_path)
+    }
+
+    /// Appends the entries, failing with `DuplicateCatalogEntry` and writing
+    /// none of them if any has the table_id or table_name of an entry already
+    /// there or earlier in the batch. Names are compared byte for byte, so
+    /// they are case-sensitive.
+    pub fn write_postgres_class(&self, tables_metadata: &[TableMetadata]) -> Result<()> {
+        self.ensure_catalog_file(&self.table_file_path)?;
+        self.write_postgres_class_in(&self.table_file_path, tables_metadata)
+    }
+
+    /// Replaces every pg_class entry with `tables_metadata`, which like a
+    /// batch for `write_postgres_class` must not repeat a table_id or name.
+    pub fn replace_postgres_class(&self, tables_metadata: &[TableMetadata]) -> Result<()> {
+        self.ensure_catalog_file(&self.table_file_path)?;
+        self.replace_postgres_class_in(&self.table_file_path, tables_metadata)
+    }
+
+    /// Replaces the pg_class entry for `table_id` with `new_metadata`,
+    /// failing with `DuplicateCatalogEntry` if another entry already has its
+    /// table_id or name.
+    pub fn update_postgres_class(&self, table_id: u32, new_metadata: &TableMetadata) -> Result<()> {
+        self.update_postgres_class_in(&self.table_file_path, table_id, new_metadata)
+    }
+
+    /// Deletes the pg_class entry for `table_id`.
+    pub fn delete_postgres_class(&self, table_id: u32) -> Result<()> {
+        self.delete_postgres_class_in(&self.table_file_path, table_id)
+    }
+
+    pub fn read_postgres_attribute(&self) -> Result<Vec<ColumnMetadata>> {
+        self.read_postgres_attribute_in(&self.column_file_path)
+    }
+
+    /// Appends the entries, failing with `DuplicateCatalogEntry` and writing
+    /// none of them if any has the table_id and column_name of an entry
+    /// already there or earlier in the batch. Names are compared byte for
+    /// byte.
+    pub fn write_postgres_attribute(&self, columns_metadata: &[ColumnMetadata]) -> Result<()> {
+        self.ensure_catalog_file(&self.column_file_path)?;
+        self.write_postgres_attribute_in(&self.column_file_path, columns_metadata)
+    }
+
+    /// Replaces every pg_attribute entry with `columns_metadata` and rebuilds
+    /// the table_id index over them. The entries must not repeat a column
+    /// name within a table.
+    pub fn replace_postgres_attribute(&self, columns_metadata: &[ColumnMetadata]) -> Result<()> {
+        self.ensure_catalog_file(&self.column_file_path)?;
+        self.replace_postgres_attribute_in(&self.column_file_path, columns_metadata)
+    }
+
+    /// Deletes the pg_attribute entry for column `column_id` of `table_id`.
+    pub fn delete_postgres_attribute(&self, table_id: u32, column_id: u32) -> Result<()> {
+        self.delete_postgres_attribute_in(&self.column_file_path, table_id, column_id)
+    }
+
+    fn read_postgres_class_in(&self, file_path: &str) -> Result<Vec<TableMetadata>> {
+        Ok(self
+            .read_postgres_class_entries(file_path)?
+            .into_iter()
+            .map(|(_, table)| table)
+            .collect())
+    }
+
+    fn read_postgres_class_entries(
+        &self,
+        file_path: &str,
+    ) -> Result<Vec<(TupleId, TableMetadata)>> {
+        self.read_entries(file_path, parse_postgres_class)
+    }
+
+    fn write_postgres_class_in(
+        &self,
+        file_path: &str,
+        tables_metadata: &[TableMetadata],
+    ) -> Result<()> {
+        self.check_writable()?;
+        let existing = self.read_postgres_class_in(file_path)?;
+        check_unique_tables(&existing, tables_metadata)?;
+        let entries: Vec<Vec<u8>> = tables_metadata
+            .iter()
+            .map(|table| self.encode_postgres_class(table))
+            .collect();
+
+        self.write_metadata(file_path, entries, |entry| entry.len())?;
+        Ok(())
+    }
+
+    /// Replaces every pg_class entry in the file with `tables_metadata`.
+    fn replace_postgres_class_in(
+        &self,
+        file_path: &str,
+        tables_metadata: &[TableMetadata],
+    ) -> Result<()> {
+        check_unique_tables(&[], tables_metadata)?;
+        let entries: Vec<Vec<u8>> = tables_metadata
+            .iter()
+            .map(|table| self.encode_postgres_class(table))
+            .collect();
+
+        self.rewrite_metadata(file_path, entries, |entry| entry.len())?;
+        Ok(())
+    }
+
+    /// Replaces the pg_class entry for `table_id` with `new_metadata`, after
+    /// checking it against every other entry as `check_unique_tables` does.
+    fn update_postgres_class_in(
+        &self,
+        file_path: &str,
+        table_id: u32,
+        new_metadata: &TableMetadata,
+    ) -> Result<()> {
+        let (others, entry): (Vec<_>, Vec<_>) = self
+            .read_postgres_class_entries(file_path)?
+            .into_iter()
+            .partition(|(_, table)| table.table_id != table_id);
+        let (tuple_id, _) = entry
+            .into_iter()
+            .next()
+            .ok_or_else(|| StorageError::NotFound(format!("table with id {}", table_id)))?;
+        let others: Vec<TableMetadata> = others.into_iter().map(|(_, table)| table).collect();
+        check_unique_tables(&others, std::slice::from_ref(new_metadata))?;
+        self.update_entry(
+            file_path,
+            tuple_id,
+            &self.encode_postgres_class(new_metadata),
+        )
+    }
+
+    fn encode_postgres_class(&self, table: &TableMetadata) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&table.table_id.to_le_bytes());
+        data.extend_from_slice(&(table.table_name.len() as u16).to_le_bytes());
+        data.extend_from_slice(table.table_name.as_bytes());
+        data
+    }
+
+    /// Deletes the pg_class entry for `table_id`.
+    fn delete_postgres_class_in(&self, file_path: &str, table_id: u32) -> Result<()> {
+        let tuple_ids: Vec<TupleId> = self
+            .read_postgres_class_entries(file_path)?
+            .into_iter()
+            .filter(|(_, table)| table.table_id == table_id)
+            .map(|(tuple_id, _)| tuple_id)
+            .collect();
+        if tuple_ids.is_empty() {
+            return Err(StorageError::NotFound(format!(
+                "table with id {}",
+                table_id
+            )));
+        }
+        for tuple_id in tuple_ids {
+            self.delete_entry(file_path, tuple_id)?;
+        }
+        Ok(())
+    }
+
+    fn read_postgres_attribute_in(&self, file_path: &str) -> Result<Vec<ColumnMetadata>> {
+        Ok(self
+            .read_postgres_attribute_entries(file_path)?
+            .into_iter()
+            .map(|(_, column)| column)
+            .collect())
+    }
+
+    /// Deletes the pg_attribute entry for column `column_id` of `table_id`.
+    fn delete_postgres_attribute_in(
+        &self,
+        file_path: &str,
+        table_id: u32,
+        column_id: u32,
+    ) -> Result<()> {
+        let tuple_ids: Vec<TupleId> = self
+            .read_postgres_attribute_entries(file_path)?
+            .into_iter()
+            .filter(|(_, column)| column.table_id == table_id && column.column_id == column_id)
+            .map(|(tuple_id, _)| tuple_id)
+            .collect();
+        if tuple_ids.is_empty() {
+            return Err(StorageError::NotFound(format!(
+                "column {} in table {}",
+                column_id, table_id
+            )));
+        }
+        for tuple_id in tuple_ids {
+            self.delete_entry(file_path, tuple_id)?;
+        }
+        Ok(())
+    }
+
+    fn read_postgres_attribute_entries(
+        &self,
+        file_path: &str,
+    ) -> Result<Vec<(TupleId, ColumnMetadata)>> {
+        self.read_entries(file_path, parse_postgres_attribute)
+    }
+
+    fn write_postgres_attribute_in(
+        &self,
+        file_path: &str,
+        columns_metadata: &[ColumnMetadata],
+    ) -> Result<()> {
+        self.check_writable()?;
+        let index = self
+            .attribute_index(file_path)?
+            .ok_or(StorageError::TransactionInProgress)?;
+        let table_ids: BTreeSet<u32> = columns_metadata
+            .iter()
+            .map(|column| column.table_id)
+            .collect();
+        let mut existing = Vec::new();
+        for table_id in table_ids {
+            existing.extend(self.indexed_columns(file_path, &index, table_id)?);
+        }
+        check_unique_columns(&existing, columns_metadata)?;
+        let entries: Vec<Vec<u8>> = columns_metadata
+            .iter()
+            .map(encode_postgres_attribute)
+            .collect();
+
+        let column_pointer_offsets =
+            self.write_metadata(file_path, entries, |entry| entry.len())?;
+        for (column, pointer_offset) in columns_metadata.iter().zip(&column_pointer_offsets) {
+            index.insert(
+                column.table_id,
+                TupleId::from_pointer_offset(*pointer_offset),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Replaces every pg_attribute entry in the file with `columns_metadata`
+    /// and rebuilds the table_id index over them. The index is removed before
+    /// the entries are rewritten and built once they are, so a crash part way
+    /// leaves no index over slots that changed; a missing index is built
+    /// again from the file when next needed.
+    fn replace_postgres_attribute_in(
+        &self,
+        file_path: &str,
+        columns_metadata: &[ColumnMetadata],
+    ) -> Result<()> {
+        check_unique_columns(&[], columns_metadata)?;
+        let entries: Vec<Vec<u8>> = columns_metadata
+            .iter()
+            .map(encode_postgres_attribute)
+            .collect();
+
+        self.check_writable()?;
+        remove_if_exists(&attribute_index_path(file_path))?;
+        self.rewrite_metadata(file_path, entries, |entry| entry.len())?;
+        self.attribute_index(file_path)?;
+        Ok(())
+    }
+
+    /// Opens the table_id index of the pg_attribute file, building it from the
+    /// file's entries if it does not exist yet. A read-only Storage cannot
+    /// build it and gets `None`, and so does a call made outside an open
+    /// transaction.
+    fn attribute_index(&self, file_path: &str) -> Result<Option<Index<'_>>> {
+        self.open_data_file(file_path)?;
+        let index_path = attribute_index_path(file_path);
+        match Index::open(self, &index_path) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {}
+            result => return result.map(Some),
+        }
+        if self.access_mode == AccessMode::ReadOnly {
+            return Ok(None);
+        }
+        let index = match Index::create(self, &index_path) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Index::open(self, &index_path).map(Some)
+            }
+            // Building the index is a write, which waits for the open
+            // transaction to end.
+            Err(StorageError::TransactionInProgress) => return Ok(None),
+            result => result?,
+        };
+        for (tuple_id, column) in self.read_postgres_attribute_entries(file_path)? {
+            index.insert(column.table_id, tuple_id)?;
+        }
+        Ok(Some(index))
+    }
+
+    /// Inserts a row into the heap file of `table_id`, creating the file if
+    /// the table has none yet. The row must have one value per column of the
+    /// table, in column order, each of the column's declared type and NULL
+    /// only where the column is nullable and not in the primary key;
+    /// otherwise this fails with `ArityMismatch`, `TypeMismatch` or
+    /// `NullViolation` before anything is written. Long strings are moved to
+    /// overflow pages as in `insert_tuple`, and only a string of more than
+    /// `u32::MAX` bytes fails with `ValueTooLong`. A column with a default
+    /// takes it in place of NULL, and may be left off the end of the row.
+    ///
+    /// If the table has a primary key, a row whose key values equal those of
+    /// a stored row fails with `UniqueViolation`. The stored rows are scanned
+    /// for it, so this costs a read of the table, and the heap file is held
+    /// exclusively from the scan to the write.
+    ///
+    /// The row is frozen: stored without a tuple header, so every snapshot
+    /// sees it until it is deleted.
+    pub fn insert_row(&self, table_id: u32, values: &[Option<DataType>]) -> Result<TupleId> {
+        self.insert_stamped_row(table_id, values, None)
+    }
+
+    /// Inserts the row like `insert_row`, but as the work of transaction
+    /// `xid`: only snapshots that see `xid` see the row. Fails with
+    /// `InvalidXid` for `FROZEN_XID`.
+    pub fn insert_row_as(
+        &self,
+        table_id: u32,
+        values: &[Option<DataType>],
+        xid: u32,
+    ) -> Result<TupleId> {
+        if xid == FROZEN_XID {
+            return Err(StorageError::InvalidXid(xid));
+        }
+        let header = TupleHeader { xmin: xid, xmax: 0 };
+        self.insert_stamped_row(table_id, values, Some(header))
+    }
+
+    /// Deletes the row as the work of transaction `xid` by stamping `xid`
+    /// into its header as xmax. Its bytes stay, so snapshots that do not see
+    /// `xid` still see the row, until `vacuum_table` removes it. A frozen row
+    /// is given a header, which takes 8 more bytes on its page and fails with
+    /// `PageFull` if they are not free. A row already deleted fails with
+    /// `NotFound`, and `FROZEN_XID` with `InvalidXid`.
+    pub fn delete_row_as(&self, table_id: u32, tuple_id: TupleId, xid: u32) -> Result<()> {
+        if xid == FROZEN_XID {
+            return Err(StorageError::InvalidXid(xid));
+        }
+        let heap_path = self.heap_path(table_id);
+        // The file stays locked from the xmax check to the stamp, so of two
+        // deleters only one finds the row live.
+        let mut file = self.open_writable_data_file(&*heap_path)?;
+        let (page, _, item_id) = self.read_live_slot(&mut file, tuple_id)?;
+        let header: TupleHeader = parse_caught(&parse_tuple_header, &page, tuple_id, item_id)?;
+        if header.xmax != 0 {
+            return Err(slot_not_found(tuple_id));
+        }
+        let start = item_id.offset as usize;
+        let stamped = stamp_xmax(&page[start..start + item_id.length as usize], xid)?;
+        self.update_entry(&heap_path, tuple_id, &stamped)
+    }
+
+    /// Removes the rows of `table_id` deleted by a transaction before
+    /// `horizon`, freeing their overflow pages, then vacuums the heap file.
+    /// `horizon` must be no later than the oldest xid of a snapshot still in
+    /// use, so that no snapshot sees the rows removed. Returns how many there
+    /// were.
+    pub fn vacuum_table(&self, table_id: u32, horizon: u32) -> Result<usize> {
+        let heap_path = self.heap_path(table_id);
+        let headers = match self.read_entries(&heap_path, parse_tuple_header) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(0)
+            }
+            headers => headers?,
+        };
+        let mut removed = 0;
+        for (tuple_id, header) in headers {
+            if header.xmax != 0 && header.xmax < horizon {
+                self.delete_tuple(&heap_path, tuple_id)?;
+                removed += 1;
+            }
+        }
+        self.vacuum(&heap_path)?;
+        Ok(removed)
+    }
+
+    fn insert_stamped_row(
+        &self,
+        table_id: u32,
+        values: &[Option<DataType>],
+        header: Option<TupleHeader>,
+    ) -> Result<TupleId> {
+        if self.get_table_by_id(table_id)?.is_none() {
+            return Err(StorageError::NotFound(format!(
+                "table with id {}",
+                table_id
+            )));
+        }
+        let columns = self.get_columns_for_table(table_id)?;
+        let values = with_defaults(&columns, values)?;
+        check_row(&columns, &values)?;
+        let heap_path = self.heap_path(table_id);
+        self.ensure_catalog_file(&heap_path)?;
+        // The heap stays locked from the key check to the write, so another
+        // thread cannot store the same key in between.
+        let _file = self.open_writable_data_file(&heap_path)?;
+        self.check_unique_key(table_id, &columns, &values)?;
+        self.insert_stamped_tuple(&heap_path, &values, header)
+    }
+
+    fn check_unique_key(
+        &self,
+        table_id: u32,
+        columns: &[ColumnMetadata],
+        values: &[Option<DataType>],
+    ) -> Result<()> {
+        let key_columns: Vec<usize> = (0..columns.len())
+            .filter(|index| columns[*index].is_primary_key)
+            .collect();
+        if key_columns.is_empty() {
+            return Ok(());
+        }
+        let key: Vec<Option<DataType>> = key_columns
+            .iter()
+            .map(|index| values[*index].clone())
+            .collect();
+        let options = ScanOptions::new().with_projection(key_columns.clone());
+        for row in self.scan(table_id, options)? {
+            if row? == key {
+                return Err(StorageError::UniqueViolation {
+                    column_names: key_columns
+                        .iter()
+                        .map(|index| columns[*index].column_name.clone())
+                        .collect(),
+                    values: key.into_iter().flatten().collect(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a row as a u16 field count, a null bitmap of one bit per field
+    /// (set for NULL), then each non-null field tagged. Rows inserted under a
+    /// transaction also have a tuple header of their xmin and xmax after the
+    /// count, flagged by its top bit, so a row of more than
+    /// `MAX_TUPLE_FIELDS` fields fails with `TooManyFields`.
+    ///
+    /// If that comes to more than `toast_threshold` bytes, the longest strings
+    /// are moved out one by one until it does not. Each goes into a chain of
+    /// overflow pages appended to the file, and the row keeps a reference to
+    /// the chain in its place. The chains are written before the row, so a
+    /// failure part way leaves at most unreferenced overflow pages.
+    pub fn insert_tuple(&self, file_path: &str, values: &[Option<DataType>]) -> Result<TupleId> {
+        self.insert_stamped_tuple(file_path, values, None)
+    }
+
+    fn insert_stamped_tuple(
+        &self,
+        file_path: &str,
+        values: &[Option<DataType>],
+        header: Option<TupleHeader>,
+    ) -> Result<TupleId> {
+        let data = self.encode_stored_tuple(file_path, values, header)?;
+        let pointer_offsets = self.write_metadata(file_path, vec![data], |entry| entry.len())?;
+        Ok(TupleId::from_pointer_offset(pointer_offsets[0]))
+    }
+
+    /// Encodes the row for the heap file, first writing the overflow chains
+    /// of the strings it moves out. A row of more than `MAX_TUPLE_FIELDS`
+    /// fields fails with `TooManyFields` before anything is written.
+    fn encode_stored_tuple(
+        &self,
+        file_path: &str,
+        values: &[Option<DataType>],
+        header: Option<TupleHeader>,
+    ) -> Result<Vec<u8>> {
+        if values.len() > MAX_TUPLE_FIELDS {
+            return Err(StorageError::TooManyFields {
+                count: values.len(),
+                max: MAX_TUPLE_FIELDS,
+            });
+        }
+        let mut external = vec![None; values.len()];
+        let mut size = encode_tuple(values, &external, header).len();
+        let mut strings: Vec<(usize, &str)> = values
+            .iter()
+            .enumerate()
+            .filter_map(|(index, value)| match value {
+                Some(DataType::String(value)) if value.len() > EXTERNAL_FIELD_SIZE => {
+                    Some((index, value.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        strings.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+        for (index, value) in strings {
+            if size <= self.toast_threshold() {
+                break;
+            }
+            external[index] = Some(self.write_overflow_chain(file_path, value.as_bytes())?);
+            size = size - (1 + 2 + value.len()) + EXTERNAL_FIELD_SIZE;
+        }
+
+        Ok(encode_tuple(values, &external, header))
+    }
+
+    /// Reads the rows of the heap file that are not deleted, in storage order.
+    pub fn read_tuples(&self, file_path: &str) -> Result<Vec<Vec<Option<DataType>>>> {
+        let parse_live = |page: &[u8], pointer: usize| {
+            let (header, _) = parse_tuple_header(page, pointer)?;
+            let (fields, offset) = parse_stored_tuple(page, pointer)?;
+            Ok((header.visible_to(None).then_some(fields), offset))
+        };
+        self.read_metadata(file_path, parse_live)?
+            .into_iter()
+            .flatten()
+            .map(|fields| {
+                fields
+                    .into_iter()
+                    .map(|field| {
+                        field
+                            .map(|field| self.load_field(file_path, field))
+                            .transpose()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Deletes the row like `delete_entry`, then frees the overflow pages of
+    /// its moved-out strings. They become empty pages that later entries may
+    /// use. Deleting a row with `delete_entry` instead leaves its overflow
+    /// pages in the file for good.
+    pub fn delete_tuple(&self, file_path: &str, tuple_id: TupleId) -> Result<()> {
+        let fields = {
+            let mut file = self.open_data_file(file_path)?;
+            let (page, _, item_id) = self.read_live_slot(&mut file, tuple_id)?;
+            parse_caught(&parse_stored_tuple, &page, tuple_id, item_id)?
+        };
+        self.delete_entry(file_path, tuple_id)?;
+
+        let mut file = self.open_writable_data_file(file_path)?;
+        let mut fsm = self.load_fsm(&mut file)?;
+        for field in fields.into_iter().flatten() {
+            let StoredField::External { first_page_no, .. } = field else {
+                continue;
+            };
+            let mut page_no = first_page_no;
+            while page_no != NO_NEXT_PAGE {
+                let (page, header) = self.read_overflow_page(&mut file, page_no)?;
+                let mut empty_page = PageInit::default().build(self.page_size);
+                self.write_page(&mut file, page_no, &mut empty_page)?;
+                fsm.set(page_no, PageHeader::decode(&empty_page).free_space());
+                page_no = next_overflow_page(&page, &header);
+            }
+        }
+        fsm.save()
+    }
+
+    /// Writes the value across new overflow pages at the end of the file and
+    /// returns the number of the first.
+    fn write_overflow_chain(&self, file_path: &str, value: &[u8]) -> Result<u32> {
+        let mut file = self.open_writable_data_file(file_path)?;
+        let mut fsm = self.load_fsm(&mut file)?;
+        let first_page_no = self.page_count(&file)?;
+        let chunks: Vec<&[u8]> = value.chunks(overflow_chunk_size(self.page_size)).collect();
+        for (page_no, chunk) in (first_page_no..).zip(&chunks) {
+            let next_page_no = if page_no + 1 < first_page_no + chunks.len() as u32 {
+                page_no + 1
+            } else {
+                NO_NEXT_PAGE
+            };
+            let mut page = PageInit::overflow().build(self.page_size);
+            let mut header = PageHeader::decode(&page);
+            header.higher -= chunk.len() as u16;
+            write_item_id(
+                &mut page,
+                0,
+                ItemId::normal(header.higher, chunk.len() as u16),
+            );
+            header.lower += ITEM_ID_SIZE as u16;
+            let higher = header.higher as usize;
+            page[higher..higher + chunk.len()].copy_from_slice(chunk);
+            let special_space = header.special_space as usize;
+            page[special_space..].copy_from_slice(&next_page_no.to_le_bytes());
+            header.write_to(&mut page);
+            self.write_page(&mut file, page_no, &mut page)?;
+            fsm.set(page_no, header.free_space());
+        }
+        fsm.save()?;
+        Ok(first_page_no)
+    }
+
+    fn load_field(&self, file_path: &str, field: StoredField) -> Result<DataType> {
+        let (length, mut page_no) = match field {
+            StoredField::Inline(value) => return Ok(value),
+            StoredField::External {
+                length,
+                first_page_no,
+            } => (length as usize, first_page_no),
+        };
+        let mut file = self.open_data_file(file_path)?;
+        let mut value = Vec::new();
+        let mut visited = BTreeSet::new();
+        while value.len() < length {
+            if page_no == NO_NEXT_PAGE {
+                return Err(StorageError::CorruptValue {
+                    reason: format!(
+                        "overflow chain ends after {} of {} bytes",
+                        value.len(),
+                        length
+                    ),
+                });
+            }
+            if !visited.insert(page_no) {
+                return Err(StorageError::CorruptValue {
+                    reason: format!("overflow chain loops back to page {}", page_no),
+                });
+            }
+            let (page, header) = self.read_overflow_page(&mut file, page_no)?;
+            let item_id = read_item_id(&page, 0);
+            let start = item_id.offset as usize;
+            value.extend_from_slice(&page[start..start + item_id.length as usize]);
+            page_no = next_overflow_page(&page, &header);
+        }
+        if value.len() != length {
+            return Err(StorageError::CorruptValue {
+                reason: format!(
+                    "overflow chain holds {} bytes for a {}-byte value",
+                    value.len(),
+                    length
+                ),
+            });
+        }
+        Ok(DataType::String(String::from_utf8(value)?))
+    }
+
+    /// Reads a page of an overflow chain, failing with `CorruptPage` unless
+    /// it holds one non-empty chunk and has room for the next page's number.
+    fn read_overflow_page(
+        &self,
+        file: &mut DataFile,
+        page_no: u32,
+    ) -> Result<(Box<[u8]>, PageHeader)> {
+        if page_no >= self.page_count(file)? {
+            return Err(StorageError::NotFound(format!("overflow page {}", page_no)));
+        }
+        let (page, header) = self.read_page(file, page_no)?;
+        let item_id = read_item_id(&page, 0);
+        if !header.is_overflow()
+            || header.slot_count() != 1
+            || !item_id.is_normal()
+            || item_id.length == 0
+            || item_id.offset as usize + item_id.length as usize > header.special_space as usize
+            || header.special_space as usize + OVERFLOW_SPECIAL_SIZE > page.len()
+        {
+            return Err(StorageError::CorruptPage {
+                page_no,
+                slot: None,
+                reason: "expected an overflow page holding one chunk".to_string(),
+            });
+        }
+        Ok((page, header))
+    }
+
+    /// Reads the rows of `columns`, with one value per column. Rows stored
+    /// before trailing columns were added with `add_column` get the columns'
+    /// defaults for them, or NULL for a column without one.
+    pub fn read_rows(
+        &self,
+        file_path: &str,
+        columns: &[ColumnMetadata],
+    ) -> Result<Vec<Vec<Option<DataType>>>> {
+        let mut rows = self.read_tuples(file_path)?;
+        for row in &mut rows {
+            if row.len() < columns.len() {
+                let missing = &columns[row.len()..];
+                row.extend(missing.iter().map(|column| column.default.clone()));
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Reads the rows of `table_id` with the options' predicate and
+    /// projection applied, a page at a time like `iter_metadata`. Deleted
+    /// rows are skipped. A row that does not decode, or a page that does not
+    /// read, comes back as an error item and the scan goes on with the next
+    /// row or page. Fails with `NotFound` if there is no such table or the
+    /// projection names a column it does not have.
+    pub fn scan<'a>(&'a self, table_id: u32, options: ScanOptions<'a>) -> Result<RowIter<'a>> {
+        RowIter::open(self, table_id, options)
+    }
+
+    /// Counts the live and dead rows and the free space of `table_id` by
+    /// walking its heap pages' headers and slot directories; no row is
+    /// decoded. Fails with `NotFound` if there is no such table.
+    pub fn analyze(&self, table_id: u32) -> Result<TableStats> {
+        if self.get_table_by_id(table_id)?.is_none() {
+            return Err(StorageError::NotFound(format!(
+                "table with id {}",
+                table_id
+            )));
+        }
+        let mut stats = TableStats::default();
+        let mut file = match self.open_data_file(self.heap_path(table_id)) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(stats)
+            }
+            file => file?,
+        };
+        for page_no in 0..self.page_count(&file)? {
+            let (page, header) = self.read_page(&mut file, page_no)?;
+            stats.add_page(&page, &header);
+        }
+        Ok(stats)
+    }
+
+    /// The primary keys of the table's rows, for a bulk load to keep its own
+    /// rows' keys apart from without a scan per row.
+    fn stored_keys(&self, table_id: u32, columns: &[ColumnMetadata]) -> Result<UniqueKeys<'_>> {
+        let heap_path = self.heap_path(table_id);
+        self.ensure_catalog_file(&heap_path)?;
+        let heap = self.open_writable_data_file(heap_path)?;
+        let key_columns: Vec<usize> = (0..columns.len())
+            .filter(|index| columns[*index].is_primary_key)
+            .collect();
+        let mut seen = BTreeSet::new();
+        if !key_columns.is_empty() {
+            let options = ScanOptions::new().with_projection(key_columns.clone());
+            for key in self.scan(table_id, options)? {
+                seen.insert(UniqueKeys::encode(&key?));
+            }
+        }
+        Ok(UniqueKeys {
+            column_names: key_columns
+                .iter()
+                .map(|index| columns[*index].column_name.clone())
+                .collect(),
+            key_columns,
+            seen,
+            _heap: heap,
+        })
+    }
+
+    /// Appends checked rows to the table's heap file in batches of
+    /// `COPY_BATCH_ROWS`, writing each page once per batch.
+    fn store_rows(&self, table_id: u32, rows: &[Vec<Option<DataType>>]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let heap_path = self.heap_path(table_id);
+        self.ensure_catalog_file(&heap_path)?;
+        for batch in rows.chunks(COPY_BATCH_ROWS) {
+            let entries = batch
+                .iter()
+                .map(|row| self.encode_stored_tuple(&heap_path, row, None))
+                .collect::<Result<Vec<_>>>()?;
+            self.write_metadata(&heap_path, entries, |entry| entry.len())?;
+        }
+        Ok(())
+    }
+
+    /// Loads the CSV records from `reader` into the table, a record per row
+    /// with a field per column in column order. Fields are parsed as the
+    /// columns' types: numbers in Rust's syntax, booleans as true/false, t/f
+    /// or 1/0, strings as they are. An unquoted field that is empty or `\N`
+    /// is NULL, which a column with a default takes it in place of.
+    ///
+    /// A record that does not parse, or whose row `insert_row` would reject,
+    /// fails with `InvalidCsv` giving its line and column. Every record is
+    /// checked before any row is written, so by default such a failure loads
+    /// nothing; with `CsvOptions::with_bad_lines_skipped` the bad records are
+    /// left out instead. The rows are then written in batches, each page
+    /// once, so an I/O error part way can leave some of them stored.
+    pub fn copy_from_csv(
+        &self,
+        table_id: u32,
+        mut reader: impl std::io::BufRead,
+        options: CsvOptions,
+    ) -> Result<CsvImport> {
+        self.check_writable()?;
+        if self.get_table_by_id(table_id)?.is_none() {
+            return Err(StorageError::NotFound(format!(
+                "table with id {}",
+                table_id
+            )));
+        }
+        let columns = self.get_columns_for_table(table_id)?;
+        let mut keys = self.stored_keys(table_id, &columns)?;
+
+        let mut import = CsvImport::default();
+        let mut rows = Vec::new();
+        let mut line = 1;
+        if options.header {
+            if let Some(record) = csv::read_record(&mut reader, options.delimiter)? {
+                line += record.line_count;
+            }
+        }
+        while let Some(record) = csv::read_record(&mut reader, options.delimiter)? {
+            let record_line = line;
+            line += record.line_count;
+            let row = csv::parse_row(&columns, record, record_line).and_then(|row| {
+                keys.insert(&row).map_err(|err| StorageError::InvalidCsv {
+                    line: record_line,
+                    column_name: None,
+                    reason: err.to_string(),
+                })?;
+                Ok(row)
+            });
+            match row {
+                Ok(row) => rows.push(row),
+                Err(StorageError::InvalidCsv { line, .. }) if options.skip_bad_lines => {
+                    import.skipped_lines.push(line)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.store_rows(table_id, &rows)?;
+        import.rows_loaded = rows.len();
+        Ok(import)
+    }
+
+    /// Writes the table's rows to `writer` as CSV in storage order, in the
+    /// format `copy_from_csv` reads, and returns how many there were. NULL is
+    /// an empty field, and strings are quoted when they hold the delimiter,
+    /// a quote or a line break, or would read back as NULL.
+    pub fn copy_to_csv(
+        &self,
+        table_id: u32,
+        writer: impl Write,
+        options: CsvOptions,
+    ) -> Result<u64> {
+        let rows = self.scan(table_id, ScanOptions::new())?;
+        let mut writer = std::io::BufWriter::new(writer);
+        if options.header {
+            let names: Vec<Option<Cow<str>>> = self
+                .get_columns_for_table(table_id)?
+                .into_iter()
+                .map(|column| Some(Cow::Owned(column.column_name)))
+                .collect();
+            writer.write_all(csv::format_record(&names, options.delimiter).as_bytes())?;
+        }
+        let mut count = 0;
+        for row in rows {
+            let row = row?;
+            let fields: Vec<Option<Cow<str>>> = row
+                .iter()
+                .map(|value| value.as_ref().map(csv::format_value))
+                .collect();
+            writer.write_all(csv::format_record(&fields, options.delimiter).as_bytes())?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Loads rows from PostgreSQL's binary COPY format, as written by
+    /// `COPY ... TO STDOUT (FORMAT binary)` or `copy_to_pg_binary`, and
+    /// returns how many there were. Each tuple must have a field per column
+    /// in column order, in the binary form of the type `copy_to_pg_binary`
+    /// maps the column's to. A NULL takes the column's default.
+    ///
+    /// Data that does not decode, or a row `insert_row` would reject, fails
+    /// with `InvalidPgCopy`. As with `copy_from_csv`, every tuple is checked
+    /// before any row is written.
+    pub fn copy_from_pg_binary(&self, table_id: u32, reader: impl Read) -> Result<usize> {
+        self.check_writable()?;
+        if self.get_table_by_id(table_id)?.is_none() {
+            return Err(StorageError::NotFound(format!(
+                "table with id {}",
+                table_id
+            )));
+        }
+        let columns = self.get_columns_for_table(table_id)?;
+        let mut keys = self.stored_keys(table_id, &columns)?;
+        let mut reader = std::io::BufReader::new(reader);
+        pg_copy::read_header(&mut reader)?;
+        let mut rows = Vec::new();
+        while let Some(row) = pg_copy::read_tuple(&mut reader, &columns, rows.len() + 1)? {
+            keys.insert(&row)
+                .map_err(|err| StorageError::InvalidPgCopy {
+                    tuple: Some(rows.len() + 1),
+                    column_name: None,
+                    reason: err.to_string(),
+                })?;
+            rows.push(row);
+        }
+        self.store_rows(table_id, &rows)?;
+        Ok(rows.len())
+    }
+
+    /// Writes the table's rows to `writer` in PostgreSQL's binary COPY
+    /// format, for `COPY ... FROM STDIN (FORMAT binary)` into a table with
+    /// matching columns, and returns how many there were. Integer32,
+    /// Float32, Integer64 and Float64 columns are written as int4, float4,
+    /// int8 and float8, Boolean as bool and String as text.
+    pub fn copy_to_pg_binary(&self, table_id: u32, writer: impl Write) -> Result<u64> {
+        let rows = self.scan(table_id, ScanOptions::new())?;
+        let mut writer = std::io::BufWriter::new(writer);
+        pg_copy::write_header(&mut writer)?;
+        let mut count = 0;
+        for row in rows {
+            pg_copy::write_tuple(&mut writer, &row?)?;
+            count += 1;
+        }
+        pg_copy::write_trailer(&mut writer)?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Fails with `AlreadyExists` if the file is already there, in which case
+    /// it holds a complete initial page written by whoever created it.
+    pub fn create_postgres_file(&self, file_path: &str) -> Result<()> {
+        self.install_new_file(file_path, &PageInit::default().build(self.page_size))?;
+        // Anything cached, mapped or indexed under this path belonged to a file
+        // that was removed. The map is rebuilt from the page headers and the
+        // index from the entries on next use.
+        self.buffer_pool().discard_file(file_path);
+        remove_if_exists(&FreeSpaceMap::path_for(file_path))?;
+        remove_if_exists(&attribute_index_path(file_path))
+    }
+
+    /// Writes `contents` to a uniquely named temp file in the same directory,
+    /// syncs it, then hard-links it to `file_path`. Linking refuses to replace
+    /// an existing file, so of several racing callers exactly one succeeds and
+    /// nobody can observe a partially written file. Missing parent directories
+    /// are created first. A file created inside a transaction is removed
+    /// again if the transaction rolls back.
+    fn install_new_file(&self, file_path: &str, contents: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        let path = Path::new(file_path);
+        let file_name = path.file_name().ok_or_else(|| {
+            StorageError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Path has no file name.",
+            ))
+        })?;
+        let temp_path = path.with_file_name(format!(
+            ".{}.tmp.{}.{}",
+            file_name.to_string_lossy(),
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut temp_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        let result = temp_file
+            .write_all(contents)
+            .and_then(|_| temp_file.sync_all())
+            .and_then(|_| std::fs::hard_link(&temp_path, path));
+        std::fs::remove_file(&temp_path)?;
+        result?;
+        self.with_scoped_txn(|txn| txn.created_files.push(file_path.to_string()));
+        if self.sync_mode == SyncMode::Fsync {
+            sync_parent_dir(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_table_by_name(&self, name: &str) -> Result<Option<TableMetadata>> {
+        Ok(self
+            .catalog_tables()?
+            .into_iter()
+            .find(|table| table.table_name == name))
+    }
+
+    pub fn get_table_by_id(&self, table_id: u32) -> Result<Option<TableMetadata>> {
+        Ok(self
+            .catalog_tables()?
+            .into_iter()
+            .find(|table| table.table_id == table_id))
+    }
+
+    /// Columns of `table_id` ordered by column_id, looked up through the
+    /// pg_attribute table_id index. A read-only Storage scans pg_attribute if
+    /// the index has not been built.
+    pub fn get_columns_for_table(&self, table_id: u32) -> Result<Vec<ColumnMetadata>> {
+        let index = match self.attribute_index(&self.column_file_path) {
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Vec::new())
+            }
+            result => result?,
+        };
+        let Some(index) = index else {
+            let mut columns: Vec<ColumnMetadata> = self
+                .read_postgres_attribute_in(&self.column_file_path)?
+                .into_iter()
+                .filter(|column| column.table_id == table_id)
+                .collect();
+            columns.sort_by_key(|column| column.column_id);
+            return Ok(columns);
+        };
+        let mut columns = self.indexed_columns(&self.column_file_path, &index, table_id)?;
+        columns.sort_by_key(|column| column.column_id);
+        Ok(columns)
+    }
+
+    /// Columns of `table_id` in the pg_attribute file, found through its
+    /// table_id index, in index order.
+    fn indexed_columns(
+        &self,
+        file_path: &str,
+        index: &Index,
+        table_id: u32,
+    ) -> Result<Vec<ColumnMetadata>> {
+        let mut file = self.open_data_file(file_path)?;
+        let mut columns = Vec::new();
+        for tuple_id in index.search(table_id)? {
+            // Deleted entries stay in the index; their slots are never reused.
+            let (page, _, item_id) = match self.read_live_slot(&mut file, tuple_id) {
+                Err(StorageError::NotFound(_)) => continue,
+                result => result?,
+            };
+            let column: ColumnMetadata =
+                parse_caught(&parse_postgres_attribute, &page, tuple_id, item_id)?;
+            if column.table_id == table_id {
+                columns.push(column);
+            }
+        }
+        Ok(columns)
+    }
+
+    pub fn get_table_and_columns(
+        &self,
+        table_name: &str,
+    ) -> Result<Option<(TableMetadata, Vec<ColumnMetadata>)>> {
+        match self.get_table_by_name(table_name)? {
+            Some(table) => {
+                let columns = self.get_columns_for_table(table.table_id)?;
+                Ok(Some((table, columns)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Entries of the Storage's pg_class file; a missing file is an empty
+    /// catalog.
+    fn catalog_tables(&self) -> Result<Vec<TableMetadata>> {
+        missing_as_empty(self.read_postgres_class_in(&self.table_file_path))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_postgres_class() {
+        let base_dir = temp_base_dir("write_postgres_class");
+        let storage = Storage::new(&base_dir);
+        let columns_metadata = vec![
+            ColumnMetadata {
+                column_id: 1,
+                table_id: 42,
+                column_name: "user_id".to_string(),
+                data_type: "INTEGER".to_string(),
                 is_nullable: false,
+                is_primary_key: false,
+                default: None,
             },
             ColumnMetadata {
                 column_id: 2,
                 table_id: 42,
                 column_name: "username".to_string(),
                 data_type: "VARCHAR".to_string(),
-                is_nullable: false,
+                is_nullable: false,
+                is_primary_key: false,
+                default: None,
+            },
+            ColumnMetadata {
+                column_id: 3,
+                table_id: 42,
+                column_name: "email".to_string(),
+                data_type: "VARCHAR".to_string(),
+                is_nullable: true,
+                is_primary_key: false,
+                default: None,
+            },
+            ColumnMetadata {
+                column_id: 4,
+                table_id: 42,
+                column_name: "created_at".to_string(),
+                data_type: "TIMESTAMP".to_string(),
+                is_nullable: false,
+                is_primary_key: false,
+                default: None,
+            },
+        ];
+        let tables_metadata = vec![
+            TableMetadata {
+                table_id: 1,
+                table_name: "accounts".to_string(),
+            },
+            TableMetadata {
+                table_id: 2,
+                table_name: "users".to_string(),
+            },
+            TableMetadata {
+                table_id: 3,
+                table_name: "orders".to_string(),
+            },
+            TableMetadata {
+                table_id: 4,
+                table_name: "products".to_string(),
+            },
+            TableMetadata {
+                table_id: 5,
+                table_name: "transactions".to_string(),
+            },
+        ];
+        storage.write_postgres_class(&tables_metadata).unwrap();
+        let read_tables_metadata = storage.read_postgres_class().unwrap();
+        assert_eq!(read_tables_metadata, tables_metadata);
+        storage.write_postgres_attribute(&columns_metadata).unwrap();
+        let read_columns_metadata = storage.read_postgres_attribute().unwrap();
+        assert_eq!(read_columns_metadata, columns_metadata);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    pub(crate) fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rsrdms_{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    /// A base directory of its own for a test, emptied first.
+    pub(crate) fn temp_base_dir(name: &str) -> String {
+        let base_dir = temp_path(name);
+        let _ = std::fs::remove_dir_all(&base_dir);
+        base_dir
+    }
+
+    /// A column without a default, in the primary key if it is not nullable.
+    pub(crate) fn column(name: &str, data_type: &str, is_nullable: bool) -> ColumnDefinition {
+        ColumnDefinition {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable,
+            is_primary_key: !is_nullable,
+            default: None,
+        }
+    }
+
+    #[test]
+    fn test_page_init_headers() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("page_init");
+        storage.create_postgres_file(&file_path).unwrap();
+        let mut created = [0u8; PAGE_SIZE];
+        File::open(&file_path)
+            .unwrap()
+            .read_exact(&mut created)
+            .unwrap();
+        std::fs::remove_file(&file_path).unwrap();
+
+        let default_page = PageInit::default().build(PAGE_SIZE);
+        assert_eq!(created[..], *default_page);
+        let header = PageHeader::parse(&created).unwrap();
+        assert_eq!(
+            header,
+            PageHeader {
+                lsn: 0,
+                checksum: page_checksum(&created),
+                flags: u16::from_le_bytes([
+                    page::PAGE_FLAG_HAS_CHECKSUM,
+                    page::PAGE_LAYOUT_VERSION
+                ]),
+                lower: PAGE_HEADER_SIZE as u16,
+                higher: PAGE_SIZE as u16,
+                special_space: PAGE_SIZE as u16,
+                magic: page::PAGE_MAGIC,
+                page_size: PAGE_SIZE as u16,
+            }
+        );
+
+        // Only the intentionally different fields may differ between paths.
+        let special_page = PageInit {
+            special_size: 16,
+            ..PageInit::default()
+        }
+        .build(PAGE_SIZE);
+        let special_header = PageHeader::parse(&special_page).unwrap();
+        assert_eq!(
+            special_header,
+            PageHeader {
+                checksum: page_checksum(&special_page),
+                higher: (PAGE_SIZE - 16) as u16,
+                special_space: (PAGE_SIZE - 16) as u16,
+                ..header
+            }
+        );
+        assert_eq!(
+            special_page[PAGE_HEADER_SIZE..],
+            default_page[PAGE_HEADER_SIZE..]
+        );
+
+        let unchecked_page = PageInit {
+            flags: 0,
+            ..PageInit::default()
+        }
+        .build(PAGE_SIZE);
+        assert_eq!(PageHeader::parse(&unchecked_page).unwrap().checksum, 0);
+    }
+
+    #[test]
+    fn test_concurrent_create_postgres_file() {
+        let dir = std::path::PathBuf::from(temp_path("concurrent_create"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("table").to_str().unwrap().to_string();
+
+        let barrier = std::sync::Barrier::new(8);
+        let results: Vec<Result<()>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let storage = Storage::new(std::env::temp_dir());
+                        barrier.wait();
+                        storage.create_postgres_file(&file_path)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert_eq!(
+            results
+                .iter()
+                .filter(|r| matches!(
+                    r,
+                    Err(StorageError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists
+                ))
+                .count(),
+            7
+        );
+        let mut page = [0u8; PAGE_SIZE];
+        File::open(&file_path)
+            .unwrap()
+            .read_exact(&mut page)
+            .unwrap();
+        assert_eq!(page[..], *PageInit::default().build(PAGE_SIZE));
+        assert!(Storage::new(std::env::temp_dir())
+            .read_postgres_class_in(&file_path)
+            .unwrap()
+            .is_empty());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_metadata_catches_parser_panic() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("parser_panic");
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+        let tables_metadata: Vec<TableMetadata> = (0..5)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("table_{}", table_id),
+            })
+            .collect();
+        storage
+            .write_postgres_class_in(&file_path, &tables_metadata)
+            .unwrap();
+
+        let result = storage.read_metadata(&file_path, |page, pointer| {
+            let table_id = u32::from_le_bytes(page[pointer..pointer + 4].try_into().unwrap());
+            if table_id == 2 {
+                panic!("bad entry {}", table_id);
+            }
+            Ok((table_id, pointer + 4))
+        });
+        match result {
+            Err(StorageError::ParserPanicked {
+                page,
+                slot,
+                message,
+            }) => {
+                assert_eq!(page, 0);
+                assert_eq!(slot, 2);
+                assert_eq!(message, "bad entry 2");
+            }
+            other => panic!("expected ParserPanicked, got {:?}", other),
+        }
+
+        storage
+            .write_postgres_class_in(
+                &file_path,
+                &[TableMetadata {
+                    table_id: 5,
+                    table_name: "table_5".to_string(),
+                }],
+            )
+            .unwrap();
+        let read_tables_metadata = storage.read_postgres_class_in(&file_path).unwrap();
+        assert_eq!(read_tables_metadata.len(), 6);
+        assert_eq!(read_tables_metadata[..5], tables_metadata[..]);
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_metadata_spans_pages() {
+        let base_dir = temp_base_dir("multi_page");
+        let storage = Storage::new(&base_dir);
+        let column_file_path = temp_path("multi_page_columns");
+        let _ = std::fs::remove_file(&column_file_path);
+        storage.create_postgres_file(&column_file_path).unwrap();
+
+        let columns_metadata: Vec<ColumnMetadata> = (0..600)
+            .map(|column_id| ColumnMetadata {
+                column_id,
+                table_id: column_id / 10,
+                column_name: format!("column_{}", column_id),
+                data_type: "VARCHAR".to_string(),
+                is_nullable: column_id % 2 == 0,
+                is_primary_key: false,
+                default: None,
+            })
+            .collect();
+        storage
+            .write_postgres_attribute_in(&column_file_path, &columns_metadata[..300])
+            .unwrap();
+        storage
+            .write_postgres_attribute_in(&column_file_path, &columns_metadata[300..])
+            .unwrap();
+
+        let file_len = std::fs::metadata(&column_file_path).unwrap().len();
+        assert_eq!(file_len % PAGE_SIZE as u64, 0);
+        assert!(file_len >= 3 * PAGE_SIZE as u64);
+        let read_columns_metadata = storage
+            .read_postgres_attribute_in(&column_file_path)
+            .unwrap();
+        assert_eq!(read_columns_metadata, columns_metadata);
+        std::fs::remove_file(&column_file_path).unwrap();
+        let _ = std::fs::remove_dir_all(&base_dir);
+    }
+
+    #[test]
+    fn test_random_entries_fail_without_panicking() {
+        let file_path = temp_path("random_entries");
+        let storage = Storage::new(std::env::temp_dir());
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..300 {
+            let mut page = PageInit::default().build(PAGE_SIZE);
+            let mut header = PageHeader::decode(&page);
+            let slot_count = 1 + next() % 8;
+            header.lower += (slot_count as usize * ITEM_ID_SIZE) as u16;
+            header.higher = header.lower;
+            for byte in &mut page[header.lower as usize..] {
+                *byte = next() as u8;
+            }
+            for slot in 0..slot_count as u16 {
+                let length = next() % if slot % 2 == 0 { 32 } else { 512 };
+                let room = PAGE_SIZE as u64 - header.lower as u64 - length;
+                let offset = header.lower as u64 + next() % room;
+                write_item_id(
+                    &mut page,
+                    slot,
+                    ItemId::normal(offset as u16, length as u16),
+                );
+            }
+            header.write_to(&mut page);
+            write_page_to_disk(&mut File::create(&file_path).unwrap(), 0, &mut page).unwrap();
+
+            let results = [
+                storage.read_postgres_class_in(&file_path).map(|_| ()),
+                storage.read_postgres_attribute_in(&file_path).map(|_| ()),
+                storage.read_tuples(&file_path).map(|_| ()),
+            ];
+            for result in results {
+                assert!(
+                    !matches!(result, Err(StorageError::ParserPanicked { .. })),
+                    "{:?}",
+                    result
+                );
+            }
+        }
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_rejects_invalid_slot_pointers() {
+        let file_path = temp_path("invalid_slots");
+        let storage = Storage::new(std::env::temp_dir());
+        let parse = |page: &[u8], pointer: usize| Ok((page[pointer..].to_vec(), page.len()));
+        // A page holding 8-byte entries in slots 0 and 1, below higher 8176.
+        let valid_page = || {
+            let mut page = PageInit::default().build(PAGE_SIZE);
+            page[12..14].copy_from_slice(&32u16.to_le_bytes());
+            page[14..16].copy_from_slice(&8176u16.to_le_bytes());
+            write_item_id(&mut page, 0, ItemId::normal(8184, 8));
+            write_item_id(&mut page, 1, ItemId::normal(8176, 8));
+            page[8176..].fill(7);
+            page
+        };
+        let read = |page: &mut [u8]| {
+            page[8..10].fill(0);
+            let checksum = page_checksum(page);
+            page[8..10].copy_from_slice(&checksum.to_le_bytes());
+            std::fs::write(&file_path, &page[..]).unwrap();
+            storage.read_metadata(&file_path, parse)
+        };
+        assert_eq!(read(&mut valid_page()).unwrap(), [vec![7; 8], vec![7; 8]]);
+
+        let cases: [(u16, ItemId, Option<u16>, &str); 6] = [
+            (0, ItemId::normal(0, 8), Some(0), "into the page header"),
+            (1, ItemId::normal(10, 8), Some(1), "into the page header"),
+            (1, ItemId::normal(28, 4), Some(1), "into the slot directory"),
+            (0, ItemId::normal(8000, 8), Some(0), "below higher 8176"),
+            (
+                1,
+                ItemId::normal(8188, 8),
+                Some(1),
+                "runs past special space 8192",
+            ),
+            (
+                0,
+                ItemId {
+                    status: ItemStatus::Dead,
+                    ..ItemId::normal(0x7FFF, 8)
+                },
+                Some(0),
+                "runs past special space",
+            ),
+        ];
+        for (slot, item_id, expected_slot, expected) in cases {
+            let mut page = valid_page();
+            write_item_id(&mut page, slot, item_id);
+            match read(&mut page) {
+                Err(StorageError::CorruptPage {
+                    page_no: 0,
+                    slot,
+                    reason,
+                }) => {
+                    assert_eq!(slot, expected_slot);
+                    assert!(reason.contains(expected), "{}", reason);
+                }
+                other => panic!("expected CorruptPage, got {:?}", other),
+            }
+        }
+
+        // A special space shrinks the data area entries may reach into.
+        let mut page = valid_page();
+        page[16..18].copy_from_slice(&8184u16.to_le_bytes());
+        assert!(matches!(
+            read(&mut page),
+            Err(StorageError::CorruptPage { slot: Some(0), reason, .. })
+                if reason.contains("past special space 8184")
+        ));
+
+        // Unused slots are not checked.
+        let mut page = valid_page();
+        write_item_id(
+            &mut page,
+            0,
+            ItemId {
+                status: ItemStatus::Unused,
+                ..ItemId::normal(3, 9000)
             },
-            ColumnMetadata {
-                column_id: 3,
-                table_id: 42,
-                column_name: "email".to_string(),
+        );
+        assert_eq!(read(&mut page).unwrap(), [vec![7; 8]]);
+
+        // Nor is a slot directory read past a corrupt lower.
+        for (lower, expected) in [(0xFFFF, "past higher"), (0, "inside"), (30, "does not end")] {
+            let mut page = valid_page();
+            page[12..14].copy_from_slice(&u16::to_le_bytes(lower));
+            match read(&mut page) {
+                Err(StorageError::CorruptPage {
+                    page_no: 0,
+                    slot: None,
+                    reason,
+                }) => assert!(reason.contains(expected), "{}", reason),
+                other => panic!("expected CorruptPage, got {:?}", other),
+            }
+        }
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_iter_metadata_reads_pages_lazily() {
+        let file_path = temp_path("iter_metadata");
+        let _ = std::fs::remove_file(&file_path);
+        let writer = Storage::new(std::env::temp_dir());
+        writer.create_postgres_file(&file_path).unwrap();
+        let entries: Vec<Vec<u8>> = (0..100u8).map(|i| vec![i; 1000]).collect();
+        let pointer_offsets = writer
+            .write_metadata(&file_path, entries, |entry| entry.len())
+            .unwrap();
+        assert!(pointer_offsets.last().unwrap().0 >= 10);
+
+        // Every page read goes through the pool, so the cached pages are the
+        // pages the iteration has read.
+        let parse = |page: &[u8], pointer: usize| Ok((page[pointer], pointer + 1000));
+        let storage = Storage::new(std::env::temp_dir()).with_buffer_pool_capacity(64);
+        let first: Vec<u8> = storage
+            .iter_metadata(&file_path, parse)
+            .unwrap()
+            .take(10)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(first, (0..10).collect::<Vec<u8>>());
+        assert_eq!(storage.buffer_pool().page_count(&file_path), 2);
+        assert_eq!(
+            storage.iter_metadata(&file_path, parse).unwrap().count(),
+            100
+        );
+        assert_eq!(
+            storage.buffer_pool().page_count(&file_path),
+            pointer_offsets.last().unwrap().0 + 1
+        );
+
+        // A page that fails to read ends the iteration with its error.
+        let mut data = std::fs::read(&file_path).unwrap();
+        data[PAGE_SIZE + 100] ^= 0xFF;
+        std::fs::write(&file_path, &data).unwrap();
+        let reader = Storage::new(std::env::temp_dir());
+        let mut entries = reader.iter_metadata(&file_path, parse).unwrap();
+        let page_0_entries = pointer_offsets
+            .iter()
+            .filter(|(page_no, _)| *page_no == 0)
+            .count();
+        for i in 0..page_0_entries {
+            assert_eq!(entries.next().unwrap().unwrap(), i as u8);
+        }
+        assert!(matches!(
+            entries.next(),
+            Some(Err(StorageError::ChecksumMismatch { page_no: 1, .. }))
+        ));
+        assert!(entries.next().is_none());
+        std::fs::remove_file(&file_path).unwrap();
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+    }
+
+    #[test]
+    fn test_write_metadata_rejects_entry_larger_than_page() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("oversized_entry");
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+
+        let result =
+            storage.write_metadata(&file_path, vec![vec![0u8; PAGE_SIZE]], |entry| entry.len());
+        assert!(matches!(
+            result,
+            Err(StorageError::EntryTooLarge {
+                size: PAGE_SIZE,
+                max
+            }) if max == PAGE_SIZE - PAGE_HEADER_SIZE - ITEM_ID_SIZE
+        ));
+        assert_eq!(
+            std::fs::metadata(&file_path).unwrap().len(),
+            PAGE_SIZE as u64
+        );
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_metadata_on_nearly_full_page() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("nearly_full_page");
+        let _ = std::fs::remove_file(&file_path);
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        storage.create_postgres_file(&file_path).unwrap();
+
+        // Leave 2 bytes free on page 0: not even room for another ItemId.
+        let free_space = PageHeader::decode(&PageInit::default().build(PAGE_SIZE)).free_space();
+        let mut entries = vec![vec![1u8; 100], vec![2u8; 100]];
+        entries.push(vec![3u8; free_space - 3 * ITEM_ID_SIZE - 200 - 2]);
+        storage
+            .write_metadata(&file_path, entries.clone(), |entry| entry.len())
+            .unwrap();
+        let full_page = std::fs::read(&file_path).unwrap();
+        let header = PageHeader::decode(full_page[..PAGE_SIZE].try_into().unwrap());
+        assert_eq!(header.higher - header.lower, 2);
+
+        // Even an empty entry needs an ItemId, so it goes to a new page.
+        let pointer_offsets = storage
+            .write_metadata(&file_path, vec![Vec::new()], |entry| entry.len())
+            .unwrap();
+        assert_eq!(pointer_offsets, [(1, PAGE_HEADER_SIZE as u16)]);
+        assert_eq!(
+            std::fs::read(&file_path).unwrap()[..PAGE_SIZE],
+            full_page[..]
+        );
+
+        // A batch with an oversized entry is rejected before anything is
+        // written.
+        let before = std::fs::read(&file_path).unwrap();
+        assert!(matches!(
+            storage.write_metadata(
+                &file_path,
+                vec![vec![4u8; 10], vec![5u8; PAGE_SIZE]],
+                |entry| entry.len()
+            ),
+            Err(StorageError::EntryTooLarge { .. })
+        ));
+        assert_eq!(std::fs::read(&file_path).unwrap(), before);
+
+        entries.push(Vec::new());
+        let read = storage
+            .read_metadata(&file_path, |page, pointer| {
+                Ok((page[pointer..].to_vec(), page.len()))
+            })
+            .unwrap();
+        assert_eq!(read, entries);
+        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(FreeSpaceMap::path_for(&file_path)).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_writes_reject_duplicates() {
+        let base_dir = temp_base_dir("catalog_duplicates");
+        let storage = Storage::new(&base_dir);
+        let table = |table_id, table_name: &str| TableMetadata {
+            table_id,
+            table_name: table_name.to_string(),
+        };
+        storage
+            .write_postgres_class(&[table(1, "users"), table(2, "orders")])
+            .unwrap();
+
+        let duplicate = |result: Result<()>| match result {
+            Err(StorageError::DuplicateCatalogEntry { index, key }) => (index, key),
+            other => panic!("expected a duplicate entry, got {:?}", other),
+        };
+        assert_eq!(
+            duplicate(storage.write_postgres_class(&[table(3, "items"), table(4, "users")])),
+            (1, "table name \"users\"".to_string())
+        );
+        assert_eq!(
+            duplicate(storage.write_postgres_class(&[table(2, "items")])),
+            (0, "table id 2".to_string())
+        );
+        assert_eq!(
+            duplicate(storage.write_postgres_class(&[
+                table(3, "items"),
+                table(4, "carts"),
+                table(5, "items"),
+            ])),
+            (2, "table name \"items\"".to_string())
+        );
+        assert_eq!(
+            duplicate(storage.replace_postgres_class(&[table(3, "a"), table(3, "b")])),
+            (1, "table id 3".to_string())
+        );
+        // Nothing from a rejected batch was written, and names differing only
+        // in case are distinct.
+        assert_eq!(storage.read_postgres_class().unwrap().len(), 2);
+        storage.write_postgres_class(&[table(3, "Users")]).unwrap();
+        assert_eq!(storage.read_postgres_class().unwrap().len(), 3);
+
+        let column = |column_id, table_id, column_name: &str| ColumnMetadata {
+            column_id,
+            table_id,
+            column_name: column_name.to_string(),
+            data_type: "INTEGER".to_string(),
+            is_nullable: true,
+            is_primary_key: false,
+            default: None,
+        };
+        storage
+            .write_postgres_attribute(&[column(1, 1, "id"), column(2, 2, "id")])
+            .unwrap();
+        assert_eq!(
+            duplicate(
+                storage.write_postgres_attribute(&[column(3, 1, "name"), column(4, 2, "id")])
+            ),
+            (1, "column name \"id\" in table 2".to_string())
+        );
+        assert_eq!(
+            duplicate(storage.write_postgres_attribute(&[column(3, 3, "id"), column(4, 3, "id")])),
+            (1, "column name \"id\" in table 3".to_string())
+        );
+        assert!(storage.get_columns_for_table(3).unwrap().is_empty());
+        assert_eq!(storage.get_columns_for_table(1).unwrap().len(), 1);
+        storage
+            .write_postgres_attribute(&[column(3, 1, "ID"), column(4, 3, "id")])
+            .unwrap();
+        assert_eq!(storage.read_postgres_attribute().unwrap().len(), 4);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_replaces_old_entries() {
+        let base_dir = temp_base_dir("rewrite");
+        let storage = Storage::new(&base_dir);
+        let table_file_path = storage.pg_class_path().to_string();
+        let column_file_path = storage.pg_attribute_path().to_string();
+        storage.create_postgres_file(&table_file_path).unwrap();
+
+        // Enough long names to spill onto a second page.
+        let old_tables: Vec<TableMetadata> = (1..=40)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("{}_{}", "stale".repeat(50), table_id),
+            })
+            .collect();
+        storage
+            .write_postgres_class_in(&table_file_path, &old_tables)
+            .unwrap();
+        storage
+            .write_postgres_class_in(
+                &table_file_path,
+                &[TableMetadata {
+                    table_id: 41,
+                    table_name: "stale_41".to_string(),
+                }],
+            )
+            .unwrap();
+        assert_eq!(
+            storage
+                .read_postgres_class_in(&table_file_path)
+                .unwrap()
+                .len(),
+            41
+        );
+
+        let new_tables = vec![
+            TableMetadata {
+                table_id: 7,
+                table_name: "users".to_string(),
+            },
+            TableMetadata {
+                table_id: 8,
+                table_name: "orders".to_string(),
+            },
+        ];
+        storage
+            .replace_postgres_class_in(&table_file_path, &new_tables)
+            .unwrap();
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            new_tables
+        );
+        let data = std::fs::read(&table_file_path).unwrap();
+        assert_eq!(data.len(), 2 * PAGE_SIZE);
+        assert!(!data.windows(5).any(|window| window == b"stale"));
+        let second_page: [u8; PAGE_SIZE] = data[PAGE_SIZE..].try_into().unwrap();
+        assert_eq!(second_page[..], *PageInit::default().build(PAGE_SIZE));
+
+        // Appending after a rewrite keeps the new set.
+        storage
+            .write_postgres_class_in(&table_file_path, &old_tables[..1])
+            .unwrap();
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            [&new_tables[..], &old_tables[..1]].concat()
+        );
+
+        // The table_id index is rebuilt over the new columns.
+        storage.create_postgres_file(&column_file_path).unwrap();
+        let column = |table_id, column_id| ColumnMetadata {
+            column_id,
+            table_id,
+            column_name: format!("column_{}", column_id),
+            data_type: "INTEGER".to_string(),
+            is_nullable: false,
+            is_primary_key: false,
+            default: None,
+        };
+        storage
+            .write_postgres_attribute_in(&column_file_path, &[column(7, 1), column(7, 2)])
+            .unwrap();
+        assert_eq!(storage.get_columns_for_table(7).unwrap().len(), 2);
+        storage
+            .replace_postgres_attribute_in(&column_file_path, &[column(8, 1), column(7, 3)])
+            .unwrap();
+        assert_eq!(storage.get_columns_for_table(7).unwrap(), [column(7, 3)]);
+        assert_eq!(storage.get_columns_for_table(8).unwrap(), [column(8, 1)]);
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_drop_table() {
+        let base_dir = temp_base_dir("drop_table");
+        let storage = Storage::new(&base_dir);
+        let table_file_path = storage.pg_class_path().to_string();
+        let column_file_path = storage.pg_attribute_path().to_string();
+
+        // The middle table's columns span several pages of pg_attribute.
+        let columns = |count: usize| -> Vec<ColumnDefinition> {
+            (0..count)
+                .map(|n| ColumnDefinition {
+                    name: format!("{}_{}", "c".repeat(300), n),
+                    data_type: "INTEGER".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: None,
+                })
+                .collect()
+        };
+        for (name, column_count) in [("first", 2), ("middle", 60), ("last", 3)] {
+            let table_id = storage.create_table(name, &columns(column_count)).unwrap();
+            let heap_file_path = storage.heap_path(table_id);
+            storage.create_postgres_file(&heap_file_path).unwrap();
+            storage
+                .insert_tuple(&heap_file_path, &[Some(DataType::Integer32(1))])
+                .unwrap();
+        }
+        assert!(std::fs::metadata(&column_file_path).unwrap().len() > 2 * PAGE_SIZE as u64);
+
+        storage.drop_table_by_name("middle").unwrap();
+        assert_eq!(storage.get_table_by_id(2).unwrap(), None);
+        assert!(storage.get_columns_for_table(2).unwrap().is_empty());
+        assert!(!Path::new(&storage.heap_path(2)).exists());
+        assert!(matches!(
+            storage.drop_table(2),
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.drop_table_by_name("middle"),
+            Err(StorageError::NotFound(_))
+        ));
+
+        // Vacuuming left no dead slots behind.
+        let data = std::fs::read(&column_file_path).unwrap();
+        for page in data.chunks(PAGE_SIZE) {
+            let page: &[u8; PAGE_SIZE] = page.try_into().unwrap();
+            let header = PageHeader::parse(page).unwrap();
+            assert!(item_ids(page, &header)
+                .iter()
+                .all(|item_id| item_id.status != ItemStatus::Dead));
+        }
+
+        for (table_id, name, column_count) in [(1, "first", 2), (3, "last", 3)] {
+            assert_eq!(
+                storage
+                    .get_table_by_id(table_id)
+                    .unwrap()
+                    .unwrap()
+                    .table_name,
+                name
+            );
+            assert_eq!(
+                storage.get_columns_for_table(table_id).unwrap().len(),
+                column_count
+            );
+            assert_eq!(
+                storage
+                    .read_tuples(&storage.heap_path(table_id))
+                    .unwrap()
+                    .len(),
+                1
+            );
+            storage.drop_table(table_id).unwrap();
+        }
+        assert!(storage
+            .read_postgres_class_in(&table_file_path)
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .read_postgres_attribute_in(&column_file_path)
+            .unwrap()
+            .is_empty());
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename_table_and_column() {
+        let base_dir = temp_base_dir("rename");
+        let storage = Storage::new(&base_dir);
+        let table_file_path = storage.pg_class_path().to_string();
+        let columns: Vec<ColumnDefinition> = ["id", "name"]
+            .iter()
+            .map(|name| ColumnDefinition {
+                name: name.to_string(),
+                data_type: "INTEGER".to_string(),
+                is_nullable: false,
+                is_primary_key: false,
+                default: None,
+            })
+            .collect();
+        for name in ["customers", "orders", "items"] {
+            storage.create_table(name, &columns).unwrap();
+        }
+        let read_page = |file_path: &str, page_no: usize| -> [u8; PAGE_SIZE] {
+            std::fs::read(file_path).unwrap()[page_no * PAGE_SIZE..(page_no + 1) * PAGE_SIZE]
+                .try_into()
+                .unwrap()
+        };
+        let table_names = || -> Vec<String> {
+            storage
+                .read_postgres_class_in(&table_file_path)
+                .unwrap()
+                .into_iter()
+                .map(|table| table.table_name)
+                .collect()
+        };
+
+        // Shorter: rewritten where it was.
+        let pointer = read_item_id(&read_page(&table_file_path, 0), 0).offset;
+        storage.rename_table(1, "users").unwrap();
+        assert_eq!(
+            read_item_id(&read_page(&table_file_path, 0), 0).offset,
+            pointer
+        );
+        assert_eq!(table_names(), ["users", "orders", "items"]);
+
+        // Longer: moved into the page's free space under the same slot.
+        storage.rename_table(2, "purchase_orders").unwrap();
+        let page = read_page(&table_file_path, 0);
+        assert_eq!(
+            read_item_id(&page, 1).offset,
+            PageHeader::parse(&page).unwrap().higher
+        );
+        assert_eq!(table_names(), ["users", "purchase_orders", "items"]);
+        assert_eq!(
+            storage
+                .get_table_by_name("purchase_orders")
+                .unwrap()
+                .unwrap()
+                .table_id,
+            2
+        );
+
+        assert!(matches!(
+            storage.rename_table(3, "users"),
+            Err(StorageError::DuplicateTableName(name)) if name == "users"
+        ));
+        storage.rename_table(3, "items").unwrap();
+        assert!(matches!(
+            storage.rename_table(4, "missing"),
+            Err(StorageError::NotFound(_))
+        ));
+
+        // With the page full, a longer name moves the entry to another page.
+        let free_space = PageHeader::parse(&read_page(&table_file_path, 0))
+            .unwrap()
+            .free_space();
+        storage
+            .write_metadata(
+                &table_file_path,
+                vec![vec![0u8; free_space - ITEM_ID_SIZE]],
+                |entry| entry.len(),
+            )
+            .unwrap();
+        storage.rename_table(3, &"i".repeat(100)).unwrap();
+        assert_eq!(
+            storage.get_table_by_id(3).unwrap().unwrap().table_name,
+            "i".repeat(100)
+        );
+        let entries = storage
+            .read_entries(&table_file_path, |page, pointer| {
+                Ok((page.len() - pointer, 0))
+            })
+            .unwrap();
+        assert!(entries
+            .iter()
+            .any(|(tuple_id, size)| tuple_id.page_no == 1 && *size == 4 + 2 + 100));
+
+        // Table 2 has columns 3 and 4; column ids are unique across tables.
+        storage.rename_column(2, 4, "total").unwrap();
+        storage
+            .rename_column(2, 3, &"order_id".repeat(1000))
+            .unwrap();
+        let names: Vec<String> = storage
+            .get_columns_for_table(2)
+            .unwrap()
+            .into_iter()
+            .map(|column| column.column_name)
+            .collect();
+        assert_eq!(names, ["order_id".repeat(1000), "total".to_string()]);
+        assert_eq!(
+            storage.get_columns_for_table(1).unwrap()[1].column_name,
+            "name"
+        );
+        assert!(matches!(
+            storage.rename_column(2, 1, "missing"),
+            Err(StorageError::NotFound(_))
+        ));
+        // Another column of the table has the name; a column of another
+        // table, or the column itself, may.
+        assert!(matches!(
+            storage.rename_column(2, 3, "total"),
+            Err(StorageError::DuplicateColumnName(name)) if name == "total"
+        ));
+        storage.rename_column(2, 4, "total").unwrap();
+        storage.rename_column(2, 4, "name").unwrap();
+        // A rename waits for an open transaction like any other write.
+        let txn = storage.begin().unwrap();
+        assert!(matches!(
+            storage.rename_column(2, 4, "amount"),
+            Err(StorageError::TransactionInProgress)
+        ));
+        txn.rollback().unwrap();
+        let names: Vec<String> = storage
+            .get_columns_for_table(2)
+            .unwrap()
+            .into_iter()
+            .map(|column| column.column_name)
+            .collect();
+        assert_eq!(names, ["order_id".repeat(1000), "name".to_string()]);
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_live_under_base_dir() {
+        let base_dir = temp_base_dir("layout");
+        let nested = Path::new(&base_dir).join("data");
+        let storage = Storage::new(&nested);
+        assert_eq!(storage.base_dir(), nested);
+        assert!(!nested.exists());
+
+        let table_id = storage
+            .create_table(
+                "users",
+                &[ColumnDefinition {
+                    name: "id".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: None,
+                }],
+            )
+            .unwrap();
+        let heap_path = storage.heap_path(table_id);
+        assert_eq!(Path::new(&heap_path), nested.join(table_id.to_string()));
+        storage.create_postgres_file(&heap_path).unwrap();
+        storage
+            .insert_tuple(&heap_path, &[Some(DataType::Integer32(1))])
+            .unwrap();
+
+        for name in ["pg_class", "pg_attribute", &table_id.to_string()] {
+            assert!(nested.join(name).is_file(), "{} is missing", name);
+        }
+        assert_eq!(Path::new(storage.pg_class_path()), nested.join("pg_class"));
+        assert_eq!(
+            Path::new(storage.pg_attribute_path()),
+            nested.join("pg_attribute")
+        );
+        assert_eq!(
+            storage.read_postgres_class().unwrap()[0].table_name,
+            "users"
+        );
+
+        // A second Storage on the directory sees the same catalog.
+        let reopened = Storage::new(&nested);
+        assert_eq!(
+            reopened.get_columns_for_table(table_id).unwrap()[0].column_name,
+            "id"
+        );
+        assert_eq!(reopened.read_tuples(&heap_path).unwrap().len(), 1);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_page_size_is_configurable() {
+        for page_size in [4096, 16384] {
+            let base_dir = temp_base_dir(&format!("page_size_{}", page_size));
+            let storage = Storage::new(&base_dir).with_page_size(page_size).unwrap();
+            assert_eq!(storage.page_size(), page_size);
+            let columns = [
+                ColumnDefinition {
+                    name: "id".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: None,
+                },
+                ColumnDefinition {
+                    name: "body".to_string(),
+                    data_type: "VARCHAR".to_string(),
+                    is_nullable: true,
+                    is_primary_key: false,
+                    default: None,
+                },
+            ];
+            let table_ids: Vec<u32> = (0..300)
+                .map(|n| {
+                    storage
+                        .create_table(&format!("table_{}", n), &columns)
+                        .unwrap()
+                })
+                .collect();
+            let long_body = "x".repeat(3 * page_size);
+            let rows: Vec<Vec<Option<DataType>>> = (0..100)
+                .map(|id| {
+                    let body = if id == 7 {
+                        long_body.clone()
+                    } else {
+                        format!("row {}", id)
+                    };
+                    vec![Some(DataType::Integer32(id)), Some(DataType::String(body))]
+                })
+                .collect();
+            for row in &rows {
+                storage.insert_row(table_ids[0], row).unwrap();
+            }
+            for path in [storage.pg_class_path(), storage.pg_attribute_path()] {
+                assert_eq!(std::fs::metadata(path).unwrap().len() % page_size as u64, 0);
+            }
+            let heap_path = storage.heap_path(table_ids[0]);
+            let info = storage.dump_page(&heap_path, 1).unwrap();
+            assert_eq!(info.header.page_size as usize, page_size);
+            assert!(info.anomalies.is_empty());
+
+            let reopened = Storage::new(&base_dir).with_page_size(page_size).unwrap();
+            let (table, table_columns) = reopened
+                .get_table_and_columns("table_299")
+                .unwrap()
+                .unwrap();
+            assert_eq!(table.table_id, table_ids[299]);
+            assert_eq!(table_columns.len(), 2);
+            let scanned: Vec<_> = reopened
+                .scan(table_ids[0], ScanOptions::new())
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(scanned, rows);
+
+            // A Storage with the default page size refuses the files.
+            let mismatched = Storage::new(&base_dir);
+            assert!(matches!(
+                mismatched.get_table_by_name("table_0"),
+                Err(StorageError::PageSizeMismatch { expected: PAGE_SIZE, found })
+                    if found == page_size
+            ));
+            assert!(matches!(
+                mismatched.insert_row(table_ids[0], &rows[0]),
+                Err(StorageError::PageSizeMismatch { .. })
+            ));
+            let info = mismatched.dump_page(storage.pg_class_path(), 0).unwrap();
+            assert!(!info.anomalies.is_empty());
+            std::fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        for page_size in [0, 512, 3000, 65536] {
+            assert!(matches!(
+                Storage::new(std::env::temp_dir()).with_page_size(page_size),
+                Err(StorageError::InvalidPageSize(size)) if size == page_size
+            ));
+        }
+    }
+
+    #[test]
+    fn test_write_metadata_with_special() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("special_space");
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+
+        // Entries of 100 bytes overflow page 0, which has no special space,
+        // onto new pages that have a 16-byte one.
+        let entries: Vec<Vec<u8>> = (0..200u8).map(|n| vec![n; 100]).collect();
+        storage
+            .write_metadata_with_special(&file_path, entries.clone(), |entry| entry.len(), 16)
+            .unwrap();
+        assert_eq!(
+            storage
+                .read_metadata(&file_path, |page, pointer| Ok((
+                    page[pointer..pointer + 100].to_vec(),
+                    pointer + 100
+                )))
+                .unwrap(),
+            entries
+        );
+
+        let data = std::fs::read(&file_path).unwrap();
+        let page = &data[PAGE_SIZE..2 * PAGE_SIZE];
+        let header = PageHeader::parse(page).unwrap();
+        assert_eq!(header.special_space as usize, PAGE_SIZE - 16);
+        assert!(!header.entry_fits(100));
+        let item_ids = item_ids(page, &header);
+        let top = item_ids
+            .iter()
+            .map(|item_id| item_id.offset as usize + item_id.length as usize)
+            .max()
+            .unwrap();
+        assert_eq!(top, PAGE_SIZE - 16);
+        assert_eq!(read_special(page), [0u8; 16]);
+        let first_page = PageHeader::parse(&data[..PAGE_SIZE]).unwrap();
+        assert_eq!(first_page.special_space as usize, PAGE_SIZE);
+
+        assert!(matches!(
+            storage.write_metadata_with_special(
+                &file_path,
+                vec![vec![]],
+                |entry| entry.len(),
+                PAGE_SIZE as u16
+            ),
+            Err(StorageError::SpecialSpaceOverflow { .. })
+        ));
+        std::fs::remove_file(&file_path).unwrap();
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+    }
+
+    #[test]
+    fn test_create_table_assigns_ids() {
+        let base_dir = temp_base_dir("create_table");
+        let storage = Storage::new(&base_dir);
+        let table_file_path = storage.pg_class_path().to_string();
+        let column_file_path = storage.pg_attribute_path().to_string();
+
+        let columns = vec![
+            ColumnDefinition {
+                name: "id".to_string(),
+                data_type: "INTEGER".to_string(),
+                is_nullable: false,
+                is_primary_key: false,
+                default: None,
+            },
+            ColumnDefinition {
+                name: "name".to_string(),
                 data_type: "VARCHAR".to_string(),
                 is_nullable: true,
-            },
-            ColumnMetadata {
-                column_id: 4,
-                table_id: 42,
-                column_name: "created_at".to_string(),
-                data_type: "TIMESTAMP".to_string(),
-                is_nullable: false,
+                is_primary_key: false,
+                default: None,
             },
         ];
+        assert_eq!(storage.create_table("users", &columns).unwrap(), 1);
+        assert!(matches!(
+            storage.create_table("users", &columns),
+            Err(StorageError::DuplicateTableName(name)) if name == "users"
+        ));
+
+        // Columns left behind by an interrupted create_table keep their id taken.
+        let orphan_table_id = storage.next_table_id().unwrap();
+        assert_eq!(orphan_table_id, 2);
+        storage
+            .write_postgres_attribute_in(
+                &column_file_path,
+                &[ColumnMetadata {
+                    column_id: storage.next_column_id().unwrap(),
+                    table_id: orphan_table_id,
+                    column_name: "orphan".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: None,
+                }],
+            )
+            .unwrap();
+        assert_eq!(storage.create_table("orders", &columns[..1]).unwrap(), 3);
+
+        let tables = storage.read_postgres_class_in(&table_file_path).unwrap();
+        assert_eq!(
+            tables,
+            vec![
+                TableMetadata {
+                    table_id: 1,
+                    table_name: "users".to_string(),
+                },
+                TableMetadata {
+                    table_id: 3,
+                    table_name: "orders".to_string(),
+                },
+            ]
+        );
+        let columns_metadata = storage
+            .read_postgres_attribute_in(&column_file_path)
+            .unwrap();
+        let ids: Vec<(u32, u32, &str)> = columns_metadata
+            .iter()
+            .map(|column| {
+                (
+                    column.table_id,
+                    column.column_id,
+                    column.column_name.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ids,
+            vec![(1, 1, "id"), (1, 2, "name"), (2, 3, "orphan"), (3, 4, "id")]
+        );
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_table_with_many_columns() {
+        let base_dir = temp_base_dir("many_columns");
+        let storage = Storage::new(&base_dir);
+        let columns: Vec<ColumnDefinition> = (0..1500)
+            .map(|n| ColumnDefinition {
+                name: format!("c{}", n),
+                data_type: "INTEGER".to_string(),
+                is_nullable: true,
+                is_primary_key: false,
+                default: None,
+            })
+            .collect();
+        let table_id = storage.create_table("wide", &columns).unwrap();
+        let stored = storage.get_columns_for_table(table_id).unwrap();
+        assert_eq!(stored.len(), 1500);
+        assert_eq!(stored[1499].column_name, "c1499");
+        assert!(!Path::new(&base_dir).join("table_to_columns_index").exists());
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_ids_are_never_reused() {
+        let base_dir = temp_base_dir("id_counters");
+        let storage = Storage::new(&base_dir);
+        let columns = [ColumnDefinition {
+            name: "id".to_string(),
+            data_type: "INTEGER".to_string(),
+            is_nullable: false,
+            is_primary_key: false,
+            default: None,
+        }];
+        for name in ["a", "b", "c"] {
+            storage.create_table(name, &columns).unwrap();
+        }
+        storage.drop_table(3).unwrap();
+        assert_eq!(storage.create_table("d", &columns).unwrap(), 4);
+        assert_eq!(storage.get_columns_for_table(4).unwrap()[0].column_id, 4);
+
+        // The counters live on disk, and threads never get the same id.
+        let storage = std::sync::Arc::new(Storage::new(&base_dir));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let storage = std::sync::Arc::clone(&storage);
+                std::thread::spawn(move || {
+                    (0..25)
+                        .map(|_| storage.next_table_id().unwrap())
+                        .collect::<Vec<u32>>()
+                })
+            })
+            .collect();
+        let mut ids: Vec<u32> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (5..105).collect::<Vec<u32>>());
+        assert_eq!(storage.create_table("e", &columns).unwrap(), 105);
+
+        // A base directory from before the control file starts past its ids.
+        std::fs::remove_file(Path::new(&base_dir).join("pg_control")).unwrap();
+        assert_eq!(storage.next_table_id().unwrap(), 106);
+        assert_eq!(storage.next_column_id().unwrap(), 6);
+
+        let read_only = Storage::new(&base_dir).with_access_mode(AccessMode::ReadOnly);
+        assert!(matches!(
+            read_only.next_table_id(),
+            Err(StorageError::ReadOnly)
+        ));
+        drop(storage);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_lookups() {
+        let base_dir = temp_base_dir("lookup");
+        let storage = Storage::new(&base_dir);
+        let table_file_path = storage.pg_class_path().to_string();
+        let column_file_path = storage.pg_attribute_path().to_string();
+        assert_eq!(storage.get_table_by_name("users").unwrap(), None);
+        assert_eq!(storage.get_table_by_id(1).unwrap(), None);
+        assert!(storage.get_columns_for_table(1).unwrap().is_empty());
+
+        storage.create_postgres_file(&table_file_path).unwrap();
+        storage.create_postgres_file(&column_file_path).unwrap();
         let tables_metadata = vec![
             TableMetadata {
                 table_id: 1,
-                table_name: "accounts".to_string(),
-            },
-            TableMetadata {
-                table_id: 2,
                 table_name: "users".to_string(),
             },
             TableMetadata {
-                table_id: 3,
+                table_id: 2,
                 table_name: "orders".to_string(),
             },
-            TableMetadata {
-                table_id: 4,
-                table_name: "products".to_string(),
-            },
-            TableMetadata {
-                table_id: 5,
-                table_name: "transactions".to_string(),
-            },
         ];
+        storage
+            .write_postgres_class_in(&table_file_path, &tables_metadata)
+            .unwrap();
+        let column = |table_id: u32, column_id: u32, column_name: &str| ColumnMetadata {
+            column_id,
+            table_id,
+            column_name: column_name.to_string(),
+            data_type: "INTEGER".to_string(),
+            is_nullable: false,
+            is_primary_key: false,
+            default: None,
+        };
+        storage
+            .write_postgres_attribute_in(
+                &column_file_path,
+                &[
+                    column(2, 2, "total"),
+                    column(1, 3, "age"),
+                    column(1, 1, "id"),
+                    column(2, 1, "id"),
+                    column(1, 2, "name"),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(
+            storage.get_table_by_name("orders").unwrap(),
+            Some(tables_metadata[1].clone())
+        );
+        assert_eq!(storage.get_table_by_name("Orders").unwrap(), None);
+        assert_eq!(
+            storage.get_table_by_id(1).unwrap(),
+            Some(tables_metadata[0].clone())
+        );
+        assert_eq!(storage.get_table_by_id(3).unwrap(), None);
+        assert_eq!(
+            storage.get_columns_for_table(1).unwrap(),
+            vec![
+                column(1, 1, "id"),
+                column(1, 2, "name"),
+                column(1, 3, "age")
+            ]
+        );
+        assert_eq!(
+            storage.get_table_and_columns("orders").unwrap(),
+            Some((
+                tables_metadata[1].clone(),
+                vec![column(2, 1, "id"), column(2, 2, "total")]
+            ))
+        );
+        assert_eq!(storage.get_table_and_columns("missing").unwrap(), None);
+
+        // Deleted columns are skipped even though the index still lists them,
+        // and a lost index is rebuilt from pg_attribute.
+        storage
+            .delete_postgres_attribute_in(&column_file_path, 1, 2)
+            .unwrap();
+        let index_path = attribute_index_path(&column_file_path);
+        assert_eq!(
+            Index::open(&storage, &index_path)
+                .unwrap()
+                .search(1)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            storage.get_columns_for_table(1).unwrap(),
+            vec![column(1, 1, "id"), column(1, 3, "age")]
+        );
+        std::fs::remove_file(&index_path).unwrap();
+        assert_eq!(
+            storage.get_columns_for_table(2).unwrap(),
+            vec![column(2, 1, "id"), column(2, 2, "total")]
+        );
+        assert_eq!(
+            Index::open(&storage, &index_path)
+                .unwrap()
+                .search(1)
+                .unwrap()
+                .len(),
+            2
+        );
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file_is_io_error() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("missing_catalog");
+        let _ = std::fs::remove_file(&file_path);
+
+        match storage.read_postgres_class_in(&file_path) {
+            Err(StorageError::Io(err)) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::NotFound)
+            }
+            other => panic!("expected Io, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_add_column_to_table_with_rows() {
+        let base_dir = temp_base_dir("add_column");
+        let storage = Storage::new(&base_dir);
+        let definition = |name: &str, data_type: &str, is_nullable: bool| ColumnDefinition {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable,
+            is_primary_key: false,
+            default: None,
+        };
+        let table_id = storage
+            .create_table("users", &[definition("id", "INTEGER", false)])
+            .unwrap();
+        let other_table_id = storage
+            .create_table("empty", &[definition("id", "INTEGER", false)])
+            .unwrap();
+        let heap_file_path = storage.heap_path(table_id);
+        for id in 1..=2 {
+            storage
+                .insert_row(table_id, &[Some(DataType::Integer32(id))])
+                .unwrap();
+        }
+
+        assert!(matches!(
+            storage.add_column(table_id, definition("id", "VARCHAR", true)),
+            Err(StorageError::DuplicateColumnName(name)) if name == "id"
+        ));
+        assert!(matches!(
+            storage.add_column(table_id, definition("email", "VARCHAR", false)),
+            Err(StorageError::NullViolation { column_name }) if column_name == "email"
+        ));
+        assert!(matches!(
+            storage.add_column(99, definition("email", "VARCHAR", true)),
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(
+            storage
+                .add_column(table_id, definition("email", "VARCHAR", true))
+                .unwrap(),
+            3
+        );
+        // A table without rows takes a column that is not nullable.
+        assert_eq!(
+            storage
+                .add_column(other_table_id, definition("name", "VARCHAR", false))
+                .unwrap(),
+            4
+        );
+
+        let columns = storage.get_columns_for_table(table_id).unwrap();
+        assert_eq!(columns[1].column_name, "email");
+        let email = Some(DataType::String("c@example.com".to_string()));
+        storage
+            .insert_row(table_id, &[Some(DataType::Integer32(3)), email.clone()])
+            .unwrap();
+        assert_eq!(
+            storage.read_rows(&heap_file_path, &columns).unwrap(),
+            vec![
+                vec![Some(DataType::Integer32(1)), None],
+                vec![Some(DataType::Integer32(2)), None],
+                vec![Some(DataType::Integer32(3)), email],
+            ]
+        );
+
+        storage.drop_table(table_id).unwrap();
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_insert_and_read_tuples() {
+        let toast_threshold = PAGE_SIZE / 4;
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("tuples");
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+
+        let mut rows = vec![
+            vec![
+                Some(DataType::Integer32(1)),
+                Some(DataType::String("alice".to_string())),
+                Some(DataType::Float32(3.5)),
+            ],
+            vec![
+                Some(DataType::Float32(-0.25)),
+                Some(DataType::Integer32(i32::MIN)),
+                Some(DataType::String(String::new())),
+            ],
+            vec![],
+        ];
+        let mut tuple_ids = Vec::new();
+        for row in &rows {
+            tuple_ids.push(storage.insert_tuple(&file_path, row).unwrap());
+        }
+        assert_eq!(
+            tuple_ids,
+            (0..3)
+                .map(|slot| TupleId { page_no: 0, slot })
+                .collect::<Vec<_>>()
+        );
+
+        // Fill the page with rows small enough to stay inline, sizing the
+        // strings so that, after their slots, field counts, null bitmaps, tags
+        // and length prefixes, only 16 bytes of the page stay free.
+        let mut page = [0u8; PAGE_SIZE];
+        File::open(&file_path)
+            .unwrap()
+            .read_exact(&mut page)
+            .unwrap();
+        let mut free_space = PageHeader::parse(&page).unwrap().free_space();
+        for slot in 3.. {
+            if free_space == 16 {
+                break;
+            }
+            let length = (free_space - ITEM_ID_SIZE - 6 - 16).min(toast_threshold - 6);
+            let large_row = vec![Some(DataType::String("x".repeat(length)))];
+            assert_eq!(
+                storage.insert_tuple(&file_path, &large_row).unwrap(),
+                TupleId { page_no: 0, slot }
+            );
+            free_space -= ITEM_ID_SIZE + 6 + length;
+            rows.push(large_row);
+        }
+
+        let next_row = vec![
+            Some(DataType::Integer32(7)),
+            Some(DataType::String("y".repeat(32))),
+        ];
+        assert_eq!(
+            storage.insert_tuple(&file_path, &next_row).unwrap(),
+            TupleId {
+                page_no: 1,
+                slot: 0
+            }
+        );
+        rows.push(next_row);
+
+        assert_eq!(storage.read_tuples(&file_path).unwrap(), rows);
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_large_strings_move_to_overflow_pages() {
+        let toast_threshold = PAGE_SIZE / 4;
+        let chunk_size = overflow_chunk_size(PAGE_SIZE);
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("overflow_rows");
+        let _ = std::fs::remove_file(&file_path);
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        storage.create_postgres_file(&file_path).unwrap();
+        let page_count =
+            || (std::fs::metadata(&file_path).unwrap().len() / PAGE_SIZE as u64) as u32;
+        let text =
+            |length: usize, byte: char| Some(DataType::String(byte.to_string().repeat(length)));
+
+        let mut rows = vec![
+            // One overflow page, filled exactly.
+            vec![Some(DataType::Integer32(1)), text(chunk_size, 'a')],
+            // Three pages, the last holding a single byte.
+            vec![Some(DataType::Integer32(2)), text(2 * chunk_size + 1, 'b')],
+            vec![Some(DataType::Integer32(3)), text(100 * 1024, 'c'), None],
+            // Small enough to stay in the row.
+            vec![text(toast_threshold - 16, 'd')],
+        ];
+        let mut tuple_ids = Vec::new();
+        for row in &rows {
+            tuple_ids.push(storage.insert_tuple(&file_path, row).unwrap());
+        }
+        assert!(tuple_ids.iter().all(|tuple_id| tuple_id.page_no == 0));
+        assert_eq!(
+            page_count(),
+            1 + 1 + 3 + (100 * 1024usize).div_ceil(chunk_size) as u32
+        );
+        assert_eq!(storage.read_tuples(&file_path).unwrap(), rows);
+
+        // Of two long strings, only the longer has to move.
+        let pages_before = page_count();
+        let two_strings = vec![
+            text(toast_threshold / 2, 'e'),
+            text(toast_threshold * 2, 'f'),
+        ];
+        storage.insert_tuple(&file_path, &two_strings).unwrap();
+        assert_eq!(page_count(), pages_before + 1);
+        rows.push(two_strings);
+        assert_eq!(storage.read_tuples(&file_path).unwrap(), rows);
+
+        // Deleting a row frees its chain for later entries.
+        storage.delete_tuple(&file_path, tuple_ids[1]).unwrap();
+        rows.remove(1);
+        assert_eq!(storage.read_tuples(&file_path).unwrap(), rows);
+        let empty_free_space =
+            PageHeader::decode(&PageInit::default().build(PAGE_SIZE)).free_space();
+        for page_no in 2..=4 {
+            assert_eq!(
+                storage.free_space(&file_path, page_no).unwrap(),
+                empty_free_space / FSM_BUCKET_SIZE * FSM_BUCKET_SIZE
+            );
+        }
+        assert_eq!(storage.free_space(&file_path, 1).unwrap(), 0);
+        let filler = vec![text(toast_threshold, 'g')];
+        let tuple_id = loop {
+            let tuple_id = storage.insert_tuple(&file_path, &filler).unwrap();
+            rows.push(filler.clone());
+            if tuple_id.page_no != 0 {
+                break tuple_id;
+            }
+        };
+        assert_eq!(tuple_id.page_no, 2);
+        assert_eq!(storage.read_tuples(&file_path).unwrap(), rows);
+
+        std::fs::remove_file(&file_path).unwrap();
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+    }
+
+    #[test]
+    fn test_damaged_overflow_chains_fail() {
+        let chunk_size = overflow_chunk_size(PAGE_SIZE);
+        let file_path = temp_path("overflow_damaged");
+        let _ = std::fs::remove_file(&file_path);
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        let storage = Storage::new(std::env::temp_dir());
+        storage.create_postgres_file(&file_path).unwrap();
+        let row = vec![Some(DataType::String("a".repeat(2 * chunk_size + 1)))];
+        storage.insert_tuple(&file_path, &row).unwrap();
+        let original = std::fs::read(&file_path).unwrap();
+
+        // Without checksums nothing else catches these, so the chain reads
+        // have to.
+        let read_damaged = |page_no: usize, damage: &dyn Fn(&mut [u8], &mut PageHeader)| {
+            let mut bytes = original.clone();
+            let page = &mut bytes[page_no * PAGE_SIZE..][..PAGE_SIZE];
+            let mut header = PageHeader::decode(page);
+            damage(page, &mut header);
+            header.flags &= !(crate::page::PAGE_FLAG_HAS_CHECKSUM as u16);
+            header.write_to(page);
+            std::fs::write(&file_path, &bytes).unwrap();
+            Storage::new(std::env::temp_dir()).read_tuples(&file_path)
+        };
+        let set_next = |page: &mut [u8], header: &PageHeader, next_page_no: u32| {
+            let special_space = header.special_space as usize;
+            page[special_space..special_space + 4].copy_from_slice(&next_page_no.to_le_bytes());
+        };
+        // An empty chunk pointing to its own page.
+        assert!(matches!(
+            read_damaged(1, &|page, header| {
+                set_next(page, header, 1);
+                let item_id = read_item_id(page, 0);
+                write_item_id(page, 0, ItemId::normal(item_id.offset, 0));
+            }),
+            Err(StorageError::CorruptPage { page_no: 1, .. })
+        ));
+        // A chain looping back to its first page.
+        assert!(matches!(
+            read_damaged(2, &|page, header| set_next(page, header, 1)),
+            Err(StorageError::CorruptValue { reason }) if reason.contains("loops back to page 1")
+        ));
+        // A special space with no room for the next page's number.
+        assert!(matches!(
+            read_damaged(1, &|_, header| header.special_space = PAGE_SIZE as u16),
+            Err(StorageError::CorruptPage { page_no: 1, .. })
+        ));
+        std::fs::remove_file(&file_path).unwrap();
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+    }
+
+    #[test]
+    fn test_insert_rows_with_nulls() {
+        let base_dir = temp_base_dir("null_rows");
+        let storage = Storage::new(&base_dir);
+        let columns: Vec<ColumnDefinition> = (1..=10)
+            .map(|column_id| ColumnDefinition {
+                name: format!("c{}", column_id),
+                data_type: "VARCHAR".to_string(),
+                is_nullable: column_id != 10,
+                is_primary_key: false,
+                default: None,
+            })
+            .collect();
+        let table_id = storage.create_table("nulls", &columns).unwrap();
+        let file_path = storage.heap_path(table_id);
+        let text = |value: &str| Some(DataType::String(value.to_string()));
+        let all_null: Vec<Option<DataType>> = vec![None; 10];
+        let interleaved = vec![
+            text("a"),
+            None,
+            text(""),
+            None,
+            None,
+            text("longer value"),
+            None,
+            text("b"),
+            None,
+            text("last"),
+        ];
+
+        assert!(matches!(
+            storage.insert_row(table_id, &all_null),
+            Err(StorageError::NullViolation { column_name }) if column_name == "c10"
+        ));
+        assert!(matches!(
+            storage.insert_row(table_id, &interleaved[..9]),
+            Err(StorageError::ArityMismatch {
+                expected: 10,
+                actual: 9
+            })
+        ));
+        storage.insert_row(table_id, &interleaved).unwrap();
+        storage.insert_tuple(&file_path, &all_null).unwrap();
+
+        assert_eq!(
+            storage.read_tuples(&file_path).unwrap(),
+            vec![interleaved, all_null]
+        );
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_insert_row_checks_schema() {
+        let base_dir = temp_base_dir("insert_row");
+        let storage = Storage::new(&base_dir);
+        let definition = |name: &str, data_type: &str, is_nullable: bool| ColumnDefinition {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable,
+            is_primary_key: false,
+            default: None,
+        };
+        let table_id = storage
+            .create_table(
+                "accounts",
+                &[
+                    definition("id", "INTEGER", false),
+                    definition("name", "VARCHAR", true),
+                    definition("balance", "DOUBLE PRECISION", false),
+                    definition("active", "BOOLEAN", true),
+                ],
+            )
+            .unwrap();
+        let row = |id: i32, name: Option<&str>| {
+            vec![
+                Some(DataType::Integer32(id)),
+                name.map(|name| DataType::String(name.to_string())),
+                Some(DataType::Float64(id as f64 * 1.5)),
+                Some(DataType::Boolean(id % 2 == 0)),
+            ]
+        };
+
+        assert!(matches!(
+            storage.insert_row(table_id, &row(1, None)[..3]),
+            Err(StorageError::ArityMismatch {
+                expected: 4,
+                actual: 3
+            })
+        ));
+        let mut wrong_type = row(1, None);
+        wrong_type[0] = Some(DataType::Float32(1.0));
+        assert!(matches!(
+            storage.insert_row(table_id, &wrong_type),
+            Err(StorageError::TypeMismatch { column_name, expected: "INTEGER", actual: "REAL" })
+                if column_name == "id"
+        ));
+        let mut null_balance = row(1, None);
+        null_balance[2] = None;
+        assert!(matches!(
+            storage.insert_row(table_id, &null_balance),
+            Err(StorageError::NullViolation { column_name }) if column_name == "balance"
+        ));
+        assert!(matches!(
+            storage.insert_row(99, &row(1, None)),
+            Err(StorageError::NotFound(_))
+        ));
+        // Nothing was written for the rejected rows.
+        assert!(!Path::new(&storage.heap_path(table_id)).exists());
+
+        // A string longer than a u16 length prefix goes to overflow pages.
+        let long_name = "n".repeat(100 * 1024);
+        let rows = vec![
+            row(1, Some("alice")),
+            row(2, None),
+            row(3, Some(&long_name)),
+        ];
+        for values in &rows {
+            storage.insert_row(table_id, values).unwrap();
+        }
+        let columns = storage.get_columns_for_table(table_id).unwrap();
+        assert_eq!(
+            storage
+                .read_rows(&storage.heap_path(table_id), &columns)
+                .unwrap(),
+            rows
+        );
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_primary_key_is_unique() {
+        let base_dir = temp_base_dir("primary_key");
+        let storage = Storage::new(&base_dir);
+        let definition = |name: &str, data_type: &str, is_primary_key: bool| ColumnDefinition {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable: true,
+            is_primary_key,
+            default: None,
+        };
+        let table_id = storage
+            .create_table(
+                "memberships",
+                &[
+                    definition("user_id", "INTEGER", true),
+                    definition("group", "VARCHAR", true),
+                    definition("note", "VARCHAR", false),
+                ],
+            )
+            .unwrap();
+        let columns = storage.get_columns_for_table(table_id).unwrap();
+        assert_eq!(
+            columns
+                .iter()
+                .map(|column| column.is_primary_key)
+                .collect::<Vec<_>>(),
+            [true, true, false]
+        );
+        let row = |user_id: i32, group: &str, note: &str| {
+            vec![
+                Some(DataType::Integer32(user_id)),
+                Some(DataType::String(group.to_string())),
+                Some(DataType::String(note.to_string())),
+            ]
+        };
+
+        storage
+            .insert_row(table_id, &row(7, "admins", "first"))
+            .unwrap();
+        // Only the whole key has to be unique; the long group is stored in an
+        // overflow chain and still compared.
+        storage.insert_row(table_id, &row(7, "users", "")).unwrap();
+        storage.insert_row(table_id, &row(8, "admins", "")).unwrap();
+        let long_group = "g".repeat(5000);
+        storage
+            .insert_row(table_id, &row(7, &long_group, ""))
+            .unwrap();
+        match storage.insert_row(table_id, &row(7, "admins", "second")) {
+            Err(StorageError::UniqueViolation {
+                column_names,
+                values,
+            }) => {
+                assert_eq!(column_names, ["user_id", "group"]);
+                assert_eq!(
+                    values,
+                    [
+                        DataType::Integer32(7),
+                        DataType::String("admins".to_string())
+                    ]
+                );
+            }
+            other => panic!("expected a unique violation, got {:?}", other),
+        }
+        assert!(matches!(
+            storage.insert_row(table_id, &row(7, &long_group, "again")),
+            Err(StorageError::UniqueViolation { .. })
+        ));
+        let mut null_key = row(9, "admins", "");
+        null_key[0] = None;
+        assert!(matches!(
+            storage.insert_row(table_id, &null_key),
+            Err(StorageError::NullViolation { column_name }) if column_name == "user_id"
+        ));
+        assert_eq!(
+            storage.scan(table_id, ScanOptions::new()).unwrap().count(),
+            4
+        );
+        assert!(matches!(
+            storage.add_column(table_id, definition("region", "VARCHAR", true)),
+            Err(StorageError::NullViolation { .. })
+        ));
+
+        // Entries written before primary keys end in a plain nullable byte and
+        // read as columns outside the key; unknown flag bits are rejected.
+        let file_path = temp_path("primary_key_old_attributes");
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+        let old_entry = |column_id: u32, name: &str, nullable: u8| {
+            let mut entry = vec![];
+            entry.extend_from_slice(&column_id.to_le_bytes());
+            entry.extend_from_slice(&1u32.to_le_bytes());
+            for string in [name, "INTEGER"] {
+                entry.extend_from_slice(&(string.len() as u16).to_le_bytes());
+                entry.extend_from_slice(string.as_bytes());
+            }
+            entry.push(nullable);
+            entry
+        };
+        storage
+            .write_metadata(
+                &file_path,
+                vec![old_entry(1, "id", 0), old_entry(2, "age", 1)],
+                |entry| entry.len(),
+            )
+            .unwrap();
+        let old_columns = storage.read_postgres_attribute_in(&file_path).unwrap();
+        assert_eq!(
+            old_columns
+                .iter()
+                .map(|column| (column.is_nullable, column.is_primary_key))
+                .collect::<Vec<_>>(),
+            [(false, false), (true, false)]
+        );
+        storage
+            .write_metadata(&file_path, vec![old_entry(3, "bad", 0x80)], |entry| {
+                entry.len()
+            })
+            .unwrap();
+        assert!(matches!(
+            storage.read_postgres_attribute_in(&file_path),
+            Err(StorageError::CorruptPage { slot: Some(2), .. })
+        ));
+        std::fs::remove_file(&file_path).unwrap();
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_inserts_keep_primary_key_unique() {
+        let base_dir = temp_base_dir("primary_key_threads");
+        let storage = std::sync::Arc::new(Storage::new(&base_dir));
+        let table_id = storage
+            .create_table("accounts", &[tests::column("id", "INTEGER", false)])
+            .unwrap();
+        // Each round, half the threads insert the round's key and half load
+        // it from CSV; exactly one of them may store it.
+        let rounds = 20;
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    (0..rounds)
+                        .filter(|round| {
+                            if thread % 2 == 0 {
+                                let row = [Some(DataType::Integer32(*round))];
+                                storage.insert_row(table_id, &row).is_ok()
+                            } else {
+                                let input = format!("{}\n", round);
+                                storage
+                                    .copy_from_csv(table_id, input.as_bytes(), CsvOptions::new())
+                                    .is_ok()
+                            }
+                        })
+                        .count()
+                })
+            })
+            .collect();
+        let stored: usize = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .sum();
+        assert_eq!(stored, rounds as usize);
+        let mut ids: Vec<Vec<Option<DataType>>> = storage
+            .scan(table_id, ScanOptions::new())
+            .unwrap()
+            .map(std::result::Result::unwrap)
+            .collect();
+        ids.sort_by_key(|row| match row[0] {
+            Some(DataType::Integer32(id)) => id,
+            _ => unreachable!(),
+        });
+        let expected: Vec<Vec<Option<DataType>>> = (0..rounds)
+            .map(|round| vec![Some(DataType::Integer32(round))])
+            .collect();
+        assert_eq!(ids, expected);
+        drop(storage);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_column_defaults() {
+        let base_dir = temp_base_dir("column_defaults");
+        let storage = Storage::new(&base_dir);
+
+        // A default of each type survives the attribute entry.
+        let defaults = [
+            DataType::String("n/a".to_string()),
+            DataType::Integer32(-7),
+            DataType::Float32(2.5),
+            DataType::Integer64(i64::MAX),
+            DataType::Float64(-0.125),
+            DataType::Boolean(true),
+        ];
+        let definitions: Vec<ColumnDefinition> = defaults
+            .iter()
+            .enumerate()
+            .map(|(index, default)| ColumnDefinition {
+                name: format!("column_{}", index),
+                data_type: default.type_name().to_string(),
+                is_nullable: false,
+                is_primary_key: false,
+                default: Some(default.clone()),
+            })
+            .collect();
+        let table_id = storage.create_table("defaults", &definitions).unwrap();
+        let columns = storage.get_columns_for_table(table_id).unwrap();
+        assert_eq!(
+            columns
+                .iter()
+                .map(|column| column.default.clone().unwrap())
+                .collect::<Vec<_>>(),
+            defaults
+        );
+
+        // Omitted and NULL values take the defaults.
+        let heap_path = storage.heap_path(table_id);
+        storage
+            .insert_row(table_id, &[None, Some(DataType::Integer32(1))])
+            .unwrap();
+        let mut expected = defaults.map(Some).to_vec();
+        expected[1] = Some(DataType::Integer32(1));
+        assert_eq!(storage.read_rows(&heap_path, &columns).unwrap(), [expected]);
+
+        // A default of the wrong type is rejected when the column is defined.
+        let mismatched = ColumnDefinition {
+            name: "count".to_string(),
+            data_type: "INTEGER".to_string(),
+            is_nullable: true,
+            is_primary_key: false,
+            default: Some(DataType::Integer64(0)),
+        };
+        assert!(matches!(
+            storage.create_table("mismatched", std::slice::from_ref(&mismatched)),
+            Err(StorageError::TypeMismatch { column_name, expected: "INTEGER", actual: "BIGINT" })
+                if column_name == "count"
+        ));
+        assert_eq!(storage.get_table_by_name("mismatched").unwrap(), None);
+        assert!(matches!(
+            storage.add_column(table_id, mismatched),
+            Err(StorageError::TypeMismatch { .. })
+        ));
+
+        // A column with a default can be added to a table with rows, which
+        // then read as the default.
+        let column_id = storage
+            .add_column(
+                table_id,
+                ColumnDefinition {
+                    name: "status".to_string(),
+                    data_type: "VARCHAR".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: Some(DataType::String("active".to_string())),
+                },
+            )
+            .unwrap();
+        let columns = storage.get_columns_for_table(table_id).unwrap();
+        assert_eq!(columns.last().unwrap().column_id, column_id);
+        let rows = storage.read_rows(&heap_path, &columns).unwrap();
+        assert_eq!(rows[0][6], Some(DataType::String("active".to_string())));
+        let scanned: Vec<_> = storage
+            .scan(table_id, ScanOptions::new().with_projection(vec![6, 1]))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(
+            scanned,
+            [vec![
+                Some(DataType::String("active".to_string())),
+                Some(DataType::Integer32(1))
+            ]]
+        );
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_buffer_pool_matches_direct_writes() {
+        let direct_path = temp_path("pool_direct");
+        let pooled_path = temp_path("pool_cached");
+        for file_path in [&direct_path, &pooled_path] {
+            let _ = std::fs::remove_file(file_path);
+        }
+        let direct = Storage::new(std::env::temp_dir());
+        let pooled = Storage::new(std::env::temp_dir()).with_buffer_pool_capacity(2);
+
+        let entry = |n: u32| {
+            let mut data = n.to_le_bytes().to_vec();
+            data.resize(300 + (n as usize % 7) * 40, n as u8);
+            data
+        };
+        let parse = |page: &[u8], pointer: usize| {
+            Ok((
+                u32::from_le_bytes(page[pointer..pointer + 4].try_into().unwrap()),
+                0,
+            ))
+        };
+        for (storage, file_path) in [(&direct, &direct_path), (&pooled, &pooled_path)] {
+            storage.create_postgres_file(file_path).unwrap();
+            storage
+                .write_metadata(file_path, (0..40).map(entry).collect(), |e| e.len())
+                .unwrap();
+            let mut ids = storage.read_metadata(file_path, parse).unwrap();
+            ids.sort();
+            assert_eq!(ids, (0..40).collect::<Vec<_>>());
+            let tuple_id = |page_no, slot| TupleId { page_no, slot };
+            storage
+                .update_entry(file_path, tuple_id(0, 3), &entry(100))
+                .unwrap();
+            storage.delete_entry(file_path, tuple_id(1, 0)).unwrap();
+            storage
+                .write_metadata(file_path, (40..60).map(entry).collect(), |e| e.len())
+                .unwrap();
+            storage.delete_entry(file_path, tuple_id(0, 5)).unwrap();
+            storage
+                .update_entry(file_path, tuple_id(2, 1), &[7; 4])
+                .unwrap();
+        }
+
+        assert_ne!(
+            std::fs::read(&pooled_path).unwrap(),
+            std::fs::read(&direct_path).unwrap()
+        );
+        let pooled_entries = pooled.read_metadata(&pooled_path, parse).unwrap();
+        assert_eq!(
+            pooled_entries,
+            direct.read_metadata(&direct_path, parse).unwrap()
+        );
+
+        pooled.flush_all().unwrap();
+        assert_eq!(
+            std::fs::read(&pooled_path).unwrap(),
+            std::fs::read(&direct_path).unwrap()
+        );
+
+        // Cached pages are served without touching the file.
+        let cached = Storage::new(std::env::temp_dir()).with_buffer_pool_capacity(16);
+        assert_eq!(
+            cached.read_metadata(&pooled_path, parse).unwrap(),
+            pooled_entries
+        );
+        std::fs::write(&pooled_path, b"").unwrap();
+        assert_eq!(
+            cached.read_metadata(&pooled_path, parse).unwrap(),
+            pooled_entries
+        );
+        for file_path in [&direct_path, &pooled_path] {
+            std::fs::remove_file(file_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_ahead_batches_page_reads() {
+        let file_path = temp_path("read_ahead");
+        let other_path = temp_path("read_ahead_other");
+        for path in [&file_path, &other_path] {
+            let _ = std::fs::remove_file(path);
+        }
+        let writer = Storage::new(std::env::temp_dir());
+        let entries = |count: u32| -> Vec<Vec<u8>> {
+            (0..count)
+                .map(|n| {
+                    let mut data = n.to_le_bytes().to_vec();
+                    data.resize(2000, n as u8);
+                    data
+                })
+                .collect()
+        };
+        writer.create_postgres_file(&file_path).unwrap();
+        writer
+            .write_metadata(&file_path, entries(100), |e| e.len())
+            .unwrap();
+        let page_count = (std::fs::metadata(&file_path).unwrap().len() / PAGE_SIZE as u64) as u32;
+        assert!(page_count >= 25);
+        let parse = |page: &[u8], pointer: usize| {
+            Ok((
+                u32::from_le_bytes(page[pointer..pointer + 4].try_into().unwrap()),
+                0,
+            ))
+        };
+        let scan = |capacity: usize, read_ahead: u32| {
+            let storage = Storage::new(std::env::temp_dir())
+                .with_buffer_pool_capacity(capacity)
+                .with_read_ahead(read_ahead);
+            let ids = storage.read_metadata(&file_path, parse).unwrap();
+            assert_eq!(ids, (0..100).collect::<Vec<_>>());
+            storage.page_reads()
+        };
+        assert_eq!(scan(64, 0), page_count as u64);
+        assert_eq!(scan(0, 8), page_count as u64);
+        assert_eq!(scan(64, 8), page_count.div_ceil(9) as u64);
+        // The window never reaches past the end of the file, and is capped
+        // by the pool.
+        assert_eq!(scan(64, 1000), 1);
+        assert_eq!(scan(5, 1000), page_count.div_ceil(5) as u64);
+
+        // Read-ahead stops at a cached page and reads it from the pool.
+        let storage = Storage::new(std::env::temp_dir())
+            .with_buffer_pool_capacity(64)
+            .with_read_ahead(8);
+        let mut file = storage.open_data_file(file_path.as_str()).unwrap();
+        storage.read_page(&mut file, 4).unwrap();
+        storage.unlock_data_file(&mut file).unwrap();
+        drop(file);
+        storage.read_metadata(&file_path, parse).unwrap();
+        let after_four = 1 + (page_count - 5).div_ceil(9) as u64;
+        assert_eq!(storage.page_reads(), 1 + after_four);
+
+        // Dirty pages are not evicted to make room for pages read ahead.
+        let storage = Storage::new(std::env::temp_dir())
+            .with_buffer_pool_capacity(4)
+            .with_read_ahead(8);
+        storage.create_postgres_file(&other_path).unwrap();
+        storage
+            .write_metadata(&other_path, entries(12), |e| e.len())
+            .unwrap();
+        assert_eq!(storage.buffer_pool().dirty_pages(None).len(), 3);
+        let reads = storage.page_reads();
+        let mut ids = storage.iter_metadata(file_path.as_str(), parse).unwrap();
+        assert_eq!(ids.next().unwrap().unwrap(), 0);
+        assert_eq!(storage.page_reads(), reads + 1);
+        assert!(!storage.buffer_pool().contains(&file_path, 1));
+        assert_eq!(storage.buffer_pool().dirty_pages(None).len(), 3);
+        drop(ids);
+        for path in [&file_path, &other_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wal_lsns_match_page_headers() {
+        let table_file_path = temp_path("wal_tables");
+        let wal_path = temp_path("wal_tables_log");
+        for file_path in [&table_file_path, &wal_path] {
+            let _ = std::fs::remove_file(file_path);
+        }
+        let storage = Storage::new(std::env::temp_dir())
+            .with_wal(&wal_path)
+            .unwrap();
+        storage.create_postgres_file(&table_file_path).unwrap();
+
+        for batch in 0..4u32 {
+            let tables_metadata: Vec<TableMetadata> = (0..150)
+                .map(|i| TableMetadata {
+                    table_id: batch * 150 + i,
+                    table_name: format!("table_{}", batch * 150 + i),
+                })
+                .collect();
+            storage
+                .write_postgres_class_in(&table_file_path, &tables_metadata)
+                .unwrap();
+        }
+        storage
+            .delete_postgres_class_in(&table_file_path, 7)
+            .unwrap();
+
+        let wal = Wal::open(&wal_path).unwrap();
+        let records: Vec<(Lsn, WalRecord)> = wal.records().unwrap().map(Result::unwrap).collect();
+        assert!(records.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(records
+            .iter()
+            .all(|(_, record)| record.file_path == table_file_path));
+
+        let mut last_lsns = std::collections::BTreeMap::new();
+        for (lsn, record) in &records {
+            assert_eq!(PageHeader::decode(&record.page).lsn, *lsn);
+            last_lsns.insert(record.page_no, *lsn);
+        }
+        let data = std::fs::read(&table_file_path).unwrap();
+        assert_eq!(last_lsns.len(), data.len() / PAGE_SIZE);
+        assert!(last_lsns.len() >= 2);
+        for (page_no, lsn) in last_lsns {
+            let page: [u8; PAGE_SIZE] = data[page_no as usize * PAGE_SIZE..][..PAGE_SIZE]
+                .try_into()
+                .unwrap();
+            assert_eq!(PageHeader::parse(&page).unwrap().lsn, lsn);
+        }
+        for file_path in [&table_file_path, &wal_path] {
+            std::fs::remove_file(file_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_recover_replays_lost_page_writes() {
+        let table_file_path = temp_path("recover_tables");
+        let wal_path = temp_path("recover_tables_log");
+        for file_path in [&table_file_path, &wal_path] {
+            let _ = std::fs::remove_file(file_path);
+        }
+        let tables_metadata: Vec<TableMetadata> = (0..500)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("table_{}", table_id),
+            })
+            .collect();
+
+        // The cached pages are logged but never flushed: forgetting the
+        // Storage skips the flush in Drop, as a crash would.
+        let storage = Storage::new(std::env::temp_dir())
+            .with_buffer_pool_capacity(16)
+            .with_wal(&wal_path)
+            .unwrap();
         storage.create_postgres_file(&table_file_path).unwrap();
         storage
-            .write_postgres_class(&table_file_path, &tables_metadata)
+            .write_postgres_class_in(&table_file_path, &tables_metadata[..300])
             .unwrap();
-        let read_tables_metadata = storage.read_postgres_class(&table_file_path).unwrap();
-        assert_eq!(read_tables_metadata, tables_metadata);
-        let column_file_path = "src/base/column".to_string();
+        storage
+            .write_postgres_class_in(&table_file_path, &tables_metadata[300..])
+            .unwrap();
+        std::mem::forget(storage);
+        assert_eq!(
+            std::fs::metadata(&table_file_path).unwrap().len(),
+            PAGE_SIZE as u64
+        );
+
+        // A record torn part way through its body.
+        let wal_len = std::fs::metadata(&wal_path).unwrap().len();
+        let mut wal_file = OpenOptions::new().append(true).open(&wal_path).unwrap();
+        wal_file
+            .write_all(&[0x40, 0x20, 0, 0, 1, 2, 3, 4, 5])
+            .unwrap();
+        drop(wal_file);
+
+        let storage = Storage::new(std::env::temp_dir());
+        assert!(storage.recover(&wal_path).unwrap() >= 3);
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), wal_len);
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            tables_metadata
+        );
+        assert_eq!(storage.recover(&wal_path).unwrap(), 0);
+
+        // A page torn by the crash is restored even though its LSN is current.
+        let mut data = std::fs::read(&table_file_path).unwrap();
+        data[2 * PAGE_SIZE - 100..2 * PAGE_SIZE].fill(0xAA);
+        std::fs::write(&table_file_path, &data).unwrap();
+        assert!(storage.recover(&wal_path).unwrap() >= 1);
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            tables_metadata
+        );
+        for file_path in [&table_file_path, &wal_path] {
+            std::fs::remove_file(file_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_bounds_recovery() {
+        let table_file_path = temp_path("checkpoint_tables");
+        let wal_path = temp_path("checkpoint_tables_log");
+        for file_path in [&table_file_path, &wal_path] {
+            let _ = std::fs::remove_file(file_path);
+        }
+        let tables_metadata: Vec<TableMetadata> = (0..1000)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("table_{}", table_id),
+            })
+            .collect();
+        let storage = Storage::new(std::env::temp_dir())
+            .with_buffer_pool_capacity(4)
+            .with_wal(&wal_path)
+            .unwrap();
+        storage.create_postgres_file(&table_file_path).unwrap();
+        storage
+            .write_postgres_class_in(&table_file_path, &tables_metadata[..600])
+            .unwrap();
+        storage.flush_all().unwrap();
+        let original = std::fs::read(&table_file_path).unwrap();
+
+        // A write cut short part way through page 1 is rebuilt from its image.
+        let file = OpenOptions::new()
+            .write(true)
+            .open(&table_file_path)
+            .unwrap();
+        file.set_len(PAGE_SIZE as u64 + 1000).unwrap();
+        drop(file);
+        assert!(
+            Storage::new(std::env::temp_dir())
+                .recover(&wal_path)
+                .unwrap()
+                >= 1
+        );
+        assert_eq!(std::fs::read(&table_file_path).unwrap(), original);
+
+        storage.checkpoint().unwrap();
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 16);
+        let last_lsn = PageHeader::parse(original[PAGE_SIZE..].try_into().unwrap())
+            .unwrap()
+            .lsn;
+        assert_eq!(
+            Storage::new(std::env::temp_dir())
+                .recover(&wal_path)
+                .unwrap(),
+            0
+        );
+
+        // Only writes after the checkpoint are replayed, and their LSNs carry
+        // on from before it.
+        storage
+            .write_postgres_class_in(&table_file_path, &tables_metadata[600..])
+            .unwrap();
+        let records: Vec<(Lsn, WalRecord)> = Wal::open(&wal_path)
+            .unwrap()
+            .records()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|(lsn, _)| *lsn > last_lsn));
+        std::mem::forget(storage);
+        let mut data = std::fs::read(&table_file_path).unwrap();
+        data[PAGE_SIZE + 500..PAGE_SIZE + 600].fill(0xAA);
+        std::fs::write(&table_file_path, &data).unwrap();
+        assert_eq!(
+            Storage::new(std::env::temp_dir())
+                .recover(&wal_path)
+                .unwrap(),
+            records.len()
+        );
+        assert_eq!(
+            Storage::new(std::env::temp_dir())
+                .read_postgres_class_in(&table_file_path)
+                .unwrap(),
+            tables_metadata
+        );
+        for file_path in [&table_file_path, &wal_path] {
+            std::fs::remove_file(file_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sync_modes() {
+        for (index, sync_mode) in [SyncMode::None, SyncMode::Flush, SyncMode::Fsync]
+            .into_iter()
+            .enumerate()
+        {
+            let dir = temp_path(&format!("sync_mode_{}", index));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let table_file_path = format!("{}/table", dir);
+            let wal_path = format!("{}/wal", dir);
+
+            let tables_metadata: Vec<TableMetadata> = (0..600)
+                .map(|table_id| TableMetadata {
+                    table_id,
+                    table_name: format!("t{}", table_id),
+                })
+                .collect();
+            for capacity in [0, 1] {
+                let _ = std::fs::remove_file(&table_file_path);
+                let storage = Storage::new(std::env::temp_dir())
+                    .with_sync_mode(sync_mode)
+                    .with_buffer_pool_capacity(capacity)
+                    .with_wal(&wal_path)
+                    .unwrap();
+                storage.create_postgres_file(&table_file_path).unwrap();
+                storage
+                    .write_postgres_class_in(&table_file_path, &tables_metadata)
+                    .unwrap();
+                storage.flush_all().unwrap();
+                assert_eq!(
+                    Storage::new(std::env::temp_dir())
+                        .read_postgres_class_in(&table_file_path)
+                        .unwrap(),
+                    tables_metadata
+                );
+            }
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fsync_errors_are_propagated() {
+        let file_path = temp_path("fsync_error");
+        let _ = std::fs::remove_file(&file_path);
+        Storage::new(std::env::temp_dir())
+            .create_postgres_file(&file_path)
+            .unwrap();
+
+        // Writes to /dev/null succeed but syncing it fails with EINVAL.
+        let entries = || vec![vec![1u8; 16]];
+        let storage = Storage::new(std::env::temp_dir())
+            .with_sync_mode(SyncMode::Fsync)
+            .with_wal("/dev/null")
+            .unwrap();
+        assert!(matches!(
+            storage.write_metadata(&file_path, entries(), |entry| entry.len()),
+            Err(StorageError::Io(err)) if err.kind() == std::io::ErrorKind::InvalidInput
+        ));
+        let storage = Storage::new(std::env::temp_dir())
+            .with_sync_mode(SyncMode::Flush)
+            .with_wal("/dev/null")
+            .unwrap();
+        storage
+            .write_metadata(&file_path, entries(), |entry| entry.len())
+            .unwrap();
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_free_space_map_routes_writes() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("fsm_routing");
+        let _ = std::fs::remove_file(&file_path);
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        storage.create_postgres_file(&file_path).unwrap();
+
+        // 39 entries of 200 bytes and their slots leave 214 bytes on page 0, which
+        // the map rounds down to 192.
+        let pointer_offsets = storage
+            .write_metadata(&file_path, vec![vec![1u8; 200]; 39], |entry| entry.len())
+            .unwrap();
+        assert!(pointer_offsets.iter().all(|(page_no, _)| *page_no == 0));
+        assert_eq!(storage.free_space(&file_path, 0).unwrap(), 192);
+
+        let pointer_offsets = storage
+            .write_metadata(&file_path, vec![vec![2u8; 250]], |entry| entry.len())
+            .unwrap();
+        assert_eq!(pointer_offsets[0].0, 1);
+        assert!(storage.free_space(&file_path, 1).unwrap() > 7900);
+        assert!(std::path::Path::new(&FreeSpaceMap::path_for(&file_path)).exists());
+
+        // Deleting the lowest entry of page 0 frees its bytes.
+        storage
+            .delete_entry(
+                &file_path,
+                TupleId {
+                    page_no: 0,
+                    slot: 38,
+                },
+            )
+            .unwrap();
+        assert_eq!(storage.free_space(&file_path, 0).unwrap(), 384);
+        let pointer_offsets = storage
+            .write_metadata(&file_path, vec![vec![3u8; 250]], |entry| entry.len())
+            .unwrap();
+        assert_eq!(pointer_offsets[0].0, 0);
+
+        let mut entries = storage
+            .read_metadata(&file_path, |page, pointer| Ok((page[pointer], 0)))
+            .unwrap();
+        entries.sort();
+        assert_eq!(entries, [vec![1; 38], vec![2, 3]].concat());
+        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(FreeSpaceMap::path_for(&file_path)).unwrap();
+    }
+
+    #[test]
+    fn test_delete_entries() {
+        let base_dir = temp_base_dir("delete_entries");
+        let storage = Storage::new(&base_dir);
+        let table_file_path = temp_path("delete_tables");
+        let _ = std::fs::remove_file(&table_file_path);
+        storage.create_postgres_file(&table_file_path).unwrap();
+        let tables_metadata: Vec<TableMetadata> = (1..=5)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("table_{}", table_id),
+            })
+            .collect();
+        storage
+            .write_postgres_class_in(&table_file_path, &tables_metadata)
+            .unwrap();
+
+        storage
+            .delete_postgres_class_in(&table_file_path, 3)
+            .unwrap();
+        let mut expected: Vec<TableMetadata> = tables_metadata
+            .iter()
+            .filter(|table| table.table_id != 3)
+            .cloned()
+            .collect();
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            expected
+        );
+        assert!(matches!(
+            storage.delete_postgres_class_in(&table_file_path, 3),
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.delete_entry(
+                &table_file_path,
+                TupleId {
+                    page_no: 0,
+                    slot: 2
+                }
+            ),
+            Err(StorageError::NotFound(_))
+        ));
+
+        let new_table = TableMetadata {
+            table_id: 6,
+            table_name: "table_6".to_string(),
+        };
+        storage
+            .write_postgres_class_in(&table_file_path, std::slice::from_ref(&new_table))
+            .unwrap();
+        expected.push(new_table);
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            expected
+        );
+
+        storage
+            .delete_entry(
+                &table_file_path,
+                TupleId {
+                    page_no: 0,
+                    slot: 0,
+                },
+            )
+            .unwrap();
+        expected.remove(0);
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            expected
+        );
+        std::fs::remove_file(&table_file_path).unwrap();
+
+        let column_file_path = temp_path("delete_columns");
+        let _ = std::fs::remove_file(&column_file_path);
         storage.create_postgres_file(&column_file_path).unwrap();
-        storage.write_postgres_attribute(&column_file_path, &columns_metadata);
-        let read_columns_metadata = storage.read_postgres_attribute(&column_file_path).unwrap();
-        assert_eq!(read_columns_metadata, columns_metadata);
+        let columns_metadata: Vec<ColumnMetadata> = (1..=3)
+            .flat_map(|table_id| {
+                (1..=2).map(move |column_id| ColumnMetadata {
+                    column_id,
+                    table_id,
+                    column_name: format!("column_{}", column_id),
+                    data_type: "INTEGER".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: None,
+                })
+            })
+            .collect();
+        storage
+            .write_postgres_attribute_in(&column_file_path, &columns_metadata)
+            .unwrap();
+        storage
+            .delete_postgres_attribute_in(&column_file_path, 2, 1)
+            .unwrap();
+        let expected: Vec<ColumnMetadata> = columns_metadata
+            .into_iter()
+            .filter(|column| !(column.table_id == 2 && column.column_id == 1))
+            .collect();
+        assert_eq!(
+            storage
+                .read_postgres_attribute_in(&column_file_path)
+                .unwrap(),
+            expected
+        );
+        std::fs::remove_file(&column_file_path).unwrap();
+        let _ = std::fs::remove_dir_all(&base_dir);
+    }
+
+    #[test]
+    fn test_update_entries() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("update_tables");
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+        let mut tables_metadata: Vec<TableMetadata> = (1..=3)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("table_{}", table_id),
+            })
+            .collect();
+        storage
+            .write_postgres_class_in(&file_path, &tables_metadata)
+            .unwrap();
+        let read_page = || {
+            let mut page = [0u8; PAGE_SIZE];
+            File::open(&file_path)
+                .unwrap()
+                .read_exact(&mut page)
+                .unwrap();
+            page
+        };
+
+        // Shorter: rewritten in place, with the old trailing bytes cleared.
+        let page = read_page();
+        let pointer = read_item_id(&page, 1).offset as usize;
+        tables_metadata[1].table_name = "t2".to_string();
+        storage
+            .update_postgres_class_in(&file_path, 2, &tables_metadata[1])
+            .unwrap();
+        let page = read_page();
+        assert_eq!(
+            read_item_id(&page, 1),
+            ItemId::normal(pointer as u16, 4 + 2 + 2)
+        );
+        assert!(page[pointer + 4 + 2 + 2..pointer + 4 + 2 + 7]
+            .iter()
+            .all(|byte| *byte == 0));
+        assert_eq!(
+            storage.read_postgres_class_in(&file_path).unwrap(),
+            tables_metadata
+        );
+
+        // Longer and in the last slot: relocated into free space.
+        tables_metadata[2].table_name = "a_much_longer_table_name".to_string();
+        storage
+            .update_postgres_class_in(&file_path, 3, &tables_metadata[2])
+            .unwrap();
+        let page = read_page();
+        let header = PageHeader::parse(&page).unwrap();
+        assert_eq!(
+            read_item_id(&page, 2),
+            ItemId::normal(header.higher, 4 + 2 + 24)
+        );
+        assert_eq!(
+            storage.read_postgres_class_in(&file_path).unwrap(),
+            tables_metadata
+        );
+
+        // Same length as the original: still fits where it was.
+        tables_metadata[0].table_name = "table_9".to_string();
+        storage
+            .update_postgres_class_in(&file_path, 1, &tables_metadata[0])
+            .unwrap();
+        assert_eq!(
+            storage.read_postgres_class_in(&file_path).unwrap(),
+            tables_metadata
+        );
+
+        // Nearly full page: leave exactly enough room for a 10-byte name.
+        let page = read_page();
+        let free_space = PageHeader::parse(&page).unwrap().free_space();
+        storage
+            .write_metadata(
+                &file_path,
+                vec![vec![7u8; free_space - ITEM_ID_SIZE - (4 + 2 + 10)]],
+                |entry| entry.len(),
+            )
+            .unwrap();
+        let full_page = read_page();
+        let mut too_long = tables_metadata[0].clone();
+        too_long.table_name = "x".repeat(20);
+        assert!(matches!(
+            storage.update_postgres_class_in(&file_path, 1, &too_long),
+            Err(StorageError::PageFull {
+                needed: 26,
+                available: 16
+            })
+        ));
+        assert_eq!(read_page(), full_page);
+        tables_metadata[0].table_name = "x".repeat(10);
+        storage
+            .update_postgres_class_in(&file_path, 1, &tables_metadata[0])
+            .unwrap();
+        let page = read_page();
+        assert_eq!(PageHeader::parse(&page).unwrap().free_space(), 0);
+        let read_tables_metadata = storage
+            .read_metadata(&file_path, |page, pointer| {
+                Ok((
+                    u32::from_le_bytes(page[pointer..pointer + 4].try_into().unwrap()),
+                    0,
+                ))
+            })
+            .unwrap();
+        assert_eq!(read_tables_metadata[..3], [1, 2, 3]);
+
+        // Another entry's name or table_id is rejected and nothing changes.
+        let mut taken_name = tables_metadata[0].clone();
+        taken_name.table_name = tables_metadata[1].table_name.clone();
+        let mut taken_id = tables_metadata[0].clone();
+        taken_id.table_id = 3;
+        for duplicate in [taken_name, taken_id] {
+            assert!(matches!(
+                storage.update_postgres_class_in(&file_path, 1, &duplicate),
+                Err(StorageError::DuplicateCatalogEntry { index: 0, .. })
+            ));
+        }
+        assert_eq!(read_page(), page);
+
+        assert!(matches!(
+            storage.update_entry(
+                &file_path,
+                TupleId {
+                    page_no: 0,
+                    slot: 9
+                },
+                &[]
+            ),
+            Err(StorageError::NotFound(_))
+        ));
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_vacuum_reclaims_deleted_entries() {
+        let table_file_path = temp_path("vacuum_tables");
+        let wal_path = temp_path("vacuum_tables_log");
+        for file_path in [&table_file_path, &wal_path] {
+            let _ = std::fs::remove_file(file_path);
+        }
+        let storage = Storage::new(std::env::temp_dir())
+            .with_wal(&wal_path)
+            .unwrap();
+        storage.create_postgres_file(&table_file_path).unwrap();
+        // Each entry is 15 bytes: the id, the name length and a 9-byte name.
+        let tables_metadata: Vec<TableMetadata> = (100..200)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("table_{}", table_id),
+            })
+            .collect();
+        storage
+            .write_postgres_class_in(&table_file_path, &tables_metadata)
+            .unwrap();
+        for table_id in (100..200).step_by(2) {
+            storage
+                .delete_postgres_class_in(&table_file_path, table_id)
+                .unwrap();
+        }
+        let entries_before = storage
+            .read_postgres_class_entries(&table_file_path)
+            .unwrap();
+        let free_before = storage.free_space(&table_file_path, 0).unwrap();
+
+        // The last entry written, at the low end of the data area, is still
+        // live, so delete_entry could not give back any of the deleted bytes.
+        assert_eq!(storage.vacuum(&table_file_path).unwrap(), 50 * 15);
+        let free_after = storage.free_space(&table_file_path, 0).unwrap();
+        assert!(free_after.abs_diff(free_before + 50 * 15) < FSM_BUCKET_SIZE);
+        assert_eq!(
+            storage
+                .read_postgres_class_entries(&table_file_path)
+                .unwrap(),
+            entries_before
+        );
+        assert_eq!(storage.vacuum_page(&table_file_path, 0).unwrap(), 0);
+        assert!(matches!(
+            storage.vacuum_page(&table_file_path, 1),
+            Err(StorageError::NotFound(_))
+        ));
+
+        // Slot numbers survive, so entries can still be found and changed.
+        storage
+            .update_postgres_class_in(
+                &table_file_path,
+                101,
+                &TableMetadata {
+                    table_id: 101,
+                    table_name: "renamed".to_string(),
+                },
+            )
+            .unwrap();
+        storage
+            .delete_postgres_class_in(&table_file_path, 103)
+            .unwrap();
+        let mut expected: Vec<TableMetadata> = tables_metadata
+            .iter()
+            .filter(|table| table.table_id % 2 == 1 && table.table_id != 103)
+            .cloned()
+            .collect();
+        expected[0].table_name = "renamed".to_string();
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            expected
+        );
+
+        // The compacted page is logged, so a torn write of it is repaired.
+        storage.vacuum(&table_file_path).unwrap();
+        let mut data = std::fs::read(&table_file_path).unwrap();
+        data[PAGE_SIZE - 500..PAGE_SIZE].fill(0xAA);
+        std::fs::write(&table_file_path, &data).unwrap();
+        assert!(storage.recover(&wal_path).unwrap() >= 1);
+        assert_eq!(
+            storage.read_postgres_class_in(&table_file_path).unwrap(),
+            expected
+        );
+        for file_path in [&table_file_path, &wal_path] {
+            std::fs::remove_file(file_path).unwrap();
+        }
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&table_file_path));
+    }
+
+    #[test]
+    fn test_concurrent_writers_keep_every_entry() {
+        let file_path = temp_path("concurrent_writers");
+        let _ = std::fs::remove_file(&file_path);
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        Storage::new(std::env::temp_dir())
+            .create_postgres_file(&file_path)
+            .unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let file_path = file_path.clone();
+                std::thread::spawn(move || {
+                    let storage = Storage::new(std::env::temp_dir());
+                    for n in 0..50 {
+                        let table_id = thread * 1000 + n;
+                        storage
+                            .write_postgres_class_in(
+                                &file_path,
+                                &[TableMetadata {
+                                    table_id,
+                                    table_name: format!("table_{}_{}", thread, n),
+                                }],
+                            )
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut table_ids: Vec<u32> = Storage::new(std::env::temp_dir())
+            .read_postgres_class_in(&file_path)
+            .unwrap()
+            .into_iter()
+            .map(|table| table.table_id)
+            .collect();
+        table_ids.sort();
+        let expected: Vec<u32> = (0..4)
+            .flat_map(|thread| (0..50).map(move |n| thread * 1000 + n))
+            .collect();
+        assert_eq!(table_ids, expected);
+        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(FreeSpaceMap::path_for(&file_path)).unwrap();
+    }
+
+    #[test]
+    fn test_shared_storage_serializes_writers() {
+        let file_path = temp_path("shared_storage");
+        let _ = std::fs::remove_file(&file_path);
+        let _ = std::fs::remove_file(FreeSpaceMap::path_for(&file_path));
+        let storage =
+            std::sync::Arc::new(Storage::new(std::env::temp_dir()).with_buffer_pool_capacity(4));
+        storage.create_postgres_file(&file_path).unwrap();
+
+        let (thread_count, entry_count) = (8, 40);
+        let threads: Vec<_> = (0..thread_count)
+            .map(|thread| {
+                let storage = storage.clone();
+                let file_path = file_path.clone();
+                std::thread::spawn(move || {
+                    for n in 0..entry_count {
+                        let entry = format!("{}:{}", thread, n).into_bytes();
+                        storage
+                            .write_metadata(&file_path, vec![entry], |entry| entry.len())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut entries = storage
+            .read_metadata(&file_path, |page, pointer| {
+                Ok((String::from_utf8(page[pointer..].to_vec())?, 0))
+            })
+            .unwrap();
+        entries.sort();
+        let mut expected: Vec<String> = (0..thread_count)
+            .flat_map(|thread| (0..entry_count).map(move |n| format!("{}:{}", thread, n)))
+            .collect();
+        expected.sort();
+        assert_eq!(entries, expected);
+        drop(storage);
+        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(FreeSpaceMap::path_for(&file_path)).unwrap();
+    }
+
+    #[test]
+    fn test_lock_wait_fail() {
+        let file_path = temp_path("lock_wait_fail");
+        let _ = std::fs::remove_file(&file_path);
+        let storage = Storage::new(std::env::temp_dir()).with_lock_wait(LockWait::Fail);
+        storage.create_postgres_file(&file_path).unwrap();
+        let tables = vec![TableMetadata {
+            table_id: 1,
+            table_name: "locked".to_string(),
+        }];
+
+        let other = File::open(&file_path).unwrap();
+        other.lock_shared().unwrap();
+        assert!(storage
+            .read_postgres_class_in(&file_path)
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            storage.write_postgres_class_in(&file_path, &tables),
+            Err(StorageError::FileLocked(path)) if path == file_path
+        ));
+
+        other.unlock().unwrap();
+        other.lock().unwrap();
+        assert!(matches!(
+            storage.read_postgres_class_in(&file_path),
+            Err(StorageError::FileLocked(_))
+        ));
+
+        drop(other);
+        storage
+            .write_postgres_class_in(&file_path, &tables)
+            .unwrap();
+        assert_eq!(storage.read_postgres_class_in(&file_path).unwrap(), tables);
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_storage() {
+        let base_dir = temp_base_dir("read_only");
+        let writer = Storage::new(&base_dir);
+        let table_file_path = writer.pg_class_path().to_string();
+        let column_file_path = writer.pg_attribute_path().to_string();
+        let table_id = writer
+            .create_table(
+                "users",
+                &[ColumnDefinition {
+                    name: "id".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: None,
+                }],
+            )
+            .unwrap();
+        std::fs::remove_file(attribute_index_path(&column_file_path)).unwrap();
+        let mut permissions = std::fs::metadata(&table_file_path).unwrap().permissions();
+        permissions.set_readonly(true);
+        for file_path in [&table_file_path, &column_file_path] {
+            std::fs::set_permissions(file_path, permissions.clone()).unwrap();
+        }
+        let bytes_before = std::fs::read(&table_file_path).unwrap();
+
+        let storage = Storage::new(&base_dir).with_access_mode(AccessMode::ReadOnly);
+        let (table, columns) = storage.get_table_and_columns("users").unwrap().unwrap();
+        assert_eq!(table.table_id, table_id);
+        assert_eq!(columns[0].column_name, "id");
+        assert!(storage.free_space(&table_file_path, 0).unwrap() > 0);
+        assert!(!Path::new(&attribute_index_path(&column_file_path)).exists());
+
+        let tuple_id = TupleId {
+            page_no: 0,
+            slot: 0,
+        };
+        let results = [
+            storage.create_table("orders", &[]).map(|_| ()),
+            storage.write_postgres_class_in(&table_file_path, std::slice::from_ref(&table)),
+            storage.write_postgres_attribute_in(&column_file_path, &columns),
+            storage.update_postgres_class_in(&table_file_path, table_id, &table),
+            storage.delete_postgres_class_in(&table_file_path, table_id),
+            storage.delete_entry(&table_file_path, tuple_id),
+            storage.update_entry(&table_file_path, tuple_id, b"x"),
+            storage.vacuum(&table_file_path).map(|_| ()),
+            storage.insert_tuple(&table_file_path, &[None]).map(|_| ()),
+            storage.create_postgres_file(&temp_path("read_only_new")),
+            storage.recover(temp_path("read_only_wal")).map(|_| ()),
+        ];
+        for result in results {
+            assert!(
+                matches!(result, Err(StorageError::ReadOnly)),
+                "{:?}",
+                result
+            );
+        }
+        assert!(matches!(
+            Storage::new(std::env::temp_dir())
+                .with_access_mode(AccessMode::ReadOnly)
+                .with_wal(temp_path("read_only_wal")),
+            Err(StorageError::ReadOnly)
+        ));
+        assert!(!Path::new(&temp_path("read_only_new")).exists());
+        assert!(!Path::new(&temp_path("read_only_wal")).exists());
+        assert_eq!(std::fs::read(&table_file_path).unwrap(), bytes_before);
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_rejects_foreign_and_other_version_files() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("format_check");
+        let parse = |page: &[u8], pointer: usize| Ok((page[pointer], pointer + 1));
+
+        std::fs::write(&file_path, [0u8; PAGE_SIZE]).unwrap();
+        assert!(matches!(
+            storage.read_metadata(&file_path, parse),
+            Err(StorageError::InvalidFormat { magic: 0 })
+        ));
+
+        let mut page = PageInit::default().build(PAGE_SIZE);
+        page[PAGE_HEADER_SIZE - 4..PAGE_HEADER_SIZE].copy_from_slice(b"PGSQ");
+        std::fs::write(&file_path, page).unwrap();
+        assert!(matches!(
+            storage.read_metadata(&file_path, parse),
+            Err(StorageError::InvalidFormat { .. })
+        ));
+
+        // Version 2 pages have 2-byte slot pointers, which this version would
+        // misread as line pointers.
+        for version in [PAGE_LAYOUT_VERSION - 1, PAGE_LAYOUT_VERSION + 1] {
+            let page = PageInit {
+                version,
+                ..PageInit::default()
+            }
+            .build(PAGE_SIZE);
+            std::fs::write(&file_path, page).unwrap();
+            match storage.read_metadata(&file_path, parse) {
+                Err(StorageError::UnsupportedVersion { found, supported }) => {
+                    assert_eq!((found, supported), (version, PAGE_LAYOUT_VERSION))
+                }
+                other => panic!("expected UnsupportedVersion, got {:?}", other),
+            }
+        }
+
+        std::fs::remove_file(&file_path).unwrap();
+        storage.create_postgres_file(&file_path).unwrap();
+        let page: [u8; PAGE_SIZE] = std::fs::read(&file_path).unwrap()[..].try_into().unwrap();
+        let header = PageHeader::parse(&page).unwrap();
+        assert_eq!(header.magic, PAGE_MAGIC);
+        assert_eq!(header.version(), PAGE_LAYOUT_VERSION);
+        assert!(storage.read_metadata(&file_path, parse).unwrap().is_empty());
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_bounds_entries_by_item_length() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("item_length");
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+        let entries: Vec<Vec<u8>> = (1..=4).map(|n| vec![n; n as usize * 3]).collect();
+        storage
+            .write_metadata(&file_path, entries.clone(), |entry| entry.len())
+            .unwrap();
+        storage
+            .delete_entry(
+                &file_path,
+                TupleId {
+                    page_no: 0,
+                    slot: 1,
+                },
+            )
+            .unwrap();
+
+        let page: [u8; PAGE_SIZE] = std::fs::read(&file_path).unwrap()[..].try_into().unwrap();
+        assert_eq!(read_item_id(&page, 0).length, 3);
+        assert_eq!(read_item_id(&page, 1).status, ItemStatus::Dead);
+        assert_eq!(read_item_id(&page, 3).length, 12);
+
+        // The parser sees the page only up to the end of its entry.
+        let read = storage
+            .read_metadata(&file_path, |page, pointer| {
+                Ok((page[pointer..].to_vec(), page.len()))
+            })
+            .unwrap();
+        assert_eq!(read, [&entries[0][..], &entries[2], &entries[3]]);
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("checksum");
+        let _ = std::fs::remove_file(&file_path);
+        storage.create_postgres_file(&file_path).unwrap();
+        let tables_metadata: Vec<TableMetadata> = (0..600)
+            .map(|table_id| TableMetadata {
+                table_id,
+                table_name: format!("table_{}", table_id),
+            })
+            .collect();
+        storage
+            .write_postgres_class_in(&file_path, &tables_metadata)
+            .unwrap();
+        assert_eq!(
+            storage.read_postgres_class_in(&file_path).unwrap(),
+            tables_metadata
+        );
+
+        let mut bytes = std::fs::read(&file_path).unwrap();
+        assert!(bytes.len() >= 2 * PAGE_SIZE);
+        bytes[PAGE_SIZE + PAGE_SIZE / 2] ^= 0x01;
+        std::fs::write(&file_path, &bytes).unwrap();
+        match storage.read_postgres_class_in(&file_path) {
+            Err(StorageError::ChecksumMismatch { page_no, .. }) => assert_eq!(page_no, 1),
+            other => panic!("expected ChecksumMismatch, got {:?}", other),
+        }
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_pages_without_checksum_flag_are_not_verified() {
+        let storage = Storage::new(std::env::temp_dir());
+        let file_path = temp_path("no_checksum");
+        let _ = std::fs::remove_file(&file_path);
+        let page = PageInit {
+            flags: 0,
+            ..PageInit::default()
+        }
+        .build(PAGE_SIZE);
+        std::fs::write(&file_path, page).unwrap();
+        let tables_metadata = vec![TableMetadata {
+            table_id: 1,
+            table_name: "accounts".to_string(),
+        }];
+        storage
+            .write_postgres_class_in(&file_path, &tables_metadata)
+            .unwrap();
+
+        let mut bytes = std::fs::read(&file_path).unwrap();
+        assert_eq!(PageHeader::parse(&bytes).unwrap().checksum, 0);
+        bytes[PAGE_SIZE / 2] ^= 0x01;
+        std::fs::write(&file_path, &bytes).unwrap();
+        assert_eq!(
+            storage.read_postgres_class_in(&file_path).unwrap(),
+            tables_metadata
+        );
+        std::fs::remove_file(&file_path).unwrap();
     }
 }
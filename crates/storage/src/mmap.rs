@@ -0,0 +1,336 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::os::raw::{c_int, c_void};
+use std::sync::{Mutex, PoisonError};
+
+use crate::page::item_ids;
+use crate::{parse_caught, verify_page, EntryParser, PageHeader, Result, Storage, TupleId};
+
+const PROT_READ: c_int = 1;
+const MAP_SHARED: c_int = 1;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// A read-only shared mapping of the start of a file.
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// The mapping is plain memory owned by this value; nothing about it is tied
+// to the thread that created it.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn empty() -> Mapping {
+        Mapping {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn new(file: &File, len: usize) -> Result<Mapping> {
+        if len == 0 {
+            return Ok(Mapping::empty());
+        }
+        // SAFETY: a fresh mapping chosen by the kernel aliases no Rust memory.
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Mapping { ptr, len })
+    }
+
+    fn page(&self, page_no: u32, page_size: usize) -> Option<&[u8]> {
+        let start = page_no as usize * page_size;
+        if start + page_size > self.len {
+            return None;
+        }
+        // SAFETY: the range lies inside the mapping, which lives as long as
+        // `self`. Callers hold a shared lock on the file while they use the
+        // page, so writers of this crate cannot change or truncate it.
+        Some(unsafe { std::slice::from_raw_parts(self.ptr.cast::<u8>().add(start), page_size) })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: `ptr` and `len` came from a successful mmap, and no
+            // borrow of the mapping outlives `self`.
+            unsafe { munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+/// A data file mapped into memory, so reads parse entries straight from the
+/// mapping instead of copying each page. See `Storage::open_mapped`.
+///
+/// The mapping follows the file as it grows or shrinks: it is checked against
+/// the file length before each page is read and remapped when they differ.
+/// Pages the Storage's buffer pool holds are read from the pool, since the
+/// mapping only shows what was written back. Writes still go through the
+/// `Storage`.
+///
+/// The file lock taken for each page keeps this crate's writers out while the
+/// page is parsed. A process that truncates the file without taking the lock
+/// can make reading the mapping crash with `SIGBUS`.
+pub struct MappedFile<'a> {
+    storage: &'a Storage,
+    path: String,
+    file: File,
+    mapping: Mutex<Mapping>,
+}
+
+impl<'a> MappedFile<'a> {
+    pub(crate) fn open(storage: &'a Storage, file_path: &str) -> Result<MappedFile<'a>> {
+        let file = File::open(file_path)?;
+        let _guard = storage.lock_file(file_path, &file, false)?;
+        let mapping = storage
+            .check_page_size(&file)
+            .and_then(|()| Mapping::new(&file, file.metadata()?.len() as usize));
+        file.unlock()?;
+        Ok(MappedFile {
+            storage,
+            path: file_path.to_string(),
+            file,
+            mapping: Mutex::new(mapping?),
+        })
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.path
+    }
+
+    /// Like `Storage::read_metadata`. The parser is handed the mapped page,
+    /// and since it cannot return anything borrowed from it, no entry
+    /// outlives the mapping.
+    pub fn read_metadata<F, T>(&self, parse_entry: F) -> Result<Vec<T>>
+    where
+        F: Fn(&[u8], usize) -> Result<(T, usize)>,
+    {
+        self.iter_metadata(parse_entry)?.collect()
+    }
+
+    /// Like `Storage::iter_metadata`: each page is parsed under its own
+    /// shared lock once the iteration reaches it.
+    pub fn iter_metadata<'m, F, T>(&'m self, parse_entry: F) -> Result<MappedMetadataIter<'m, T>>
+    where
+        F: Fn(&[u8], usize) -> Result<(T, usize)> + 'm,
+    {
+        let _guard = self.storage.lock_file(&self.path, &self.file, false)?;
+        let on_disk = (self.file.metadata()?.len() / self.storage.page_size() as u64) as u32;
+        let page_count = on_disk.max(self.storage.buffer_pool().page_count(&self.path));
+        self.file.unlock()?;
+        Ok(MappedMetadataIter {
+            mapped: self,
+            parse_entry: Box::new(parse_entry),
+            page_count,
+            next_page_no: 0,
+            entries: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Parses the live entries of a page, stopping after the first error.
+    fn parse_page<T>(
+        &self,
+        page_no: u32,
+        parse_entry: &EntryParser<'_, T>,
+    ) -> Result<VecDeque<Result<T>>> {
+        let _guard = self.storage.lock_file(&self.path, &self.file, false)?;
+        let result = self.parse_locked_page(page_no, parse_entry);
+        self.file.unlock()?;
+        result
+    }
+
+    fn parse_locked_page<T>(
+        &self,
+        page_no: u32,
+        parse_entry: &EntryParser<'_, T>,
+    ) -> Result<VecDeque<Result<T>>> {
+        let cached = self.storage.buffer_pool().get(&self.path, page_no);
+        if let Some(page) = cached {
+            return Ok(parse_entries(
+                &page,
+                &PageHeader::decode(&page),
+                page_no,
+                parse_entry,
+            ));
+        }
+
+        let mut mapping = self.mapping.lock().unwrap_or_else(PoisonError::into_inner);
+        let len = self.file.metadata()?.len() as usize;
+        if mapping.len != len {
+            *mapping = Mapping::empty();
+            *mapping = Mapping::new(&self.file, len)?;
+        }
+        let page = mapping
+            .page(page_no, self.storage.page_size())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("page {} is past the end of the file", page_no),
+                )
+            })?;
+        let header = verify_page(page, page_no)?;
+        Ok(parse_entries(page, &header, page_no, parse_entry))
+    }
+}
+
+fn parse_entries<T>(
+    page: &[u8],
+    header: &PageHeader,
+    page_no: u32,
+    parse_entry: &EntryParser<'_, T>,
+) -> VecDeque<Result<T>> {
+    let mut entries = VecDeque::new();
+    if header.is_overflow() {
+        return entries;
+    }
+    for (slot, item_id) in (0..).zip(item_ids(page, header)) {
+        if !item_id.is_normal() {
+            continue;
+        }
+        let result = parse_caught(parse_entry, page, TupleId { page_no, slot }, item_id);
+        let failed = result.is_err();
+        entries.push_back(result);
+        if failed {
+            break;
+        }
+    }
+    entries
+}
+
+/// Live entries of a mapped file, parsed a page at a time. See
+/// `MappedFile::iter_metadata`.
+pub struct MappedMetadataIter<'a, T> {
+    mapped: &'a MappedFile<'a>,
+    parse_entry: EntryParser<'a, T>,
+    page_count: u32,
+    next_page_no: u32,
+    /// The parsed entries of the current page not yet returned.
+    entries: VecDeque<Result<T>>,
+    done: bool,
+}
+
+impl<T> Iterator for MappedMetadataIter<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Some(result) = self.entries.pop_front() {
+                self.done = result.is_err();
+                return Some(result);
+            }
+            if self.next_page_no == self.page_count {
+                self.done = true;
+                break;
+            }
+            match self.mapped.parse_page(self.next_page_no, &self.parse_entry) {
+                Ok(entries) => {
+                    self.entries = entries;
+                    self.next_page_no += 1;
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<T> std::iter::FusedIterator for MappedMetadataIter<'_, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rsrdms_{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    fn parse(page: &[u8], pointer: usize) -> Result<(Vec<u8>, usize)> {
+        Ok((page[pointer..].to_vec(), page.len()))
+    }
+
+    #[test]
+    fn test_mapped_reads_match_file_reads() {
+        let file_path = temp_path("mapped_reads");
+        let _ = std::fs::remove_file(&file_path);
+        let storage = Storage::new(std::env::temp_dir());
+        storage.create_postgres_file(&file_path).unwrap();
+        let entries: Vec<Vec<u8>> = (0..30u8).map(|n| vec![n; 700 + n as usize]).collect();
+        storage
+            .write_metadata(&file_path, entries, |entry| entry.len())
+            .unwrap();
+        storage
+            .delete_entry(
+                &file_path,
+                TupleId {
+                    page_no: 1,
+                    slot: 2,
+                },
+            )
+            .unwrap();
+
+        let mapped = storage.open_mapped(&file_path).unwrap();
+        let expected = storage.read_metadata(&file_path, parse).unwrap();
+        assert_eq!(expected.len(), 29);
+        assert_eq!(mapped.read_metadata(parse).unwrap(), expected);
+
+        // Growing the file remaps it.
+        storage
+            .write_metadata(&file_path, vec![vec![99; 8000]], |entry| entry.len())
+            .unwrap();
+        let expected = storage.read_metadata(&file_path, parse).unwrap();
+        assert_eq!(expected.last().unwrap(), &vec![99; 8000]);
+        assert_eq!(mapped.read_metadata(parse).unwrap(), expected);
+        let first: Vec<Vec<u8>> = mapped
+            .iter_metadata(parse)
+            .unwrap()
+            .take(2)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(first, expected[..2]);
+        drop(mapped);
+
+        // Pages still in the buffer pool are read from there.
+        let cached = Storage::new(std::env::temp_dir()).with_buffer_pool_capacity(8);
+        cached
+            .write_metadata(&file_path, vec![vec![100; 10]], |entry| entry.len())
+            .unwrap();
+        let mapped = cached.open_mapped(&file_path).unwrap();
+        let expected = cached.read_metadata(&file_path, parse).unwrap();
+        assert!(expected.contains(&vec![100; 10]));
+        assert_eq!(mapped.read_metadata(parse).unwrap(), expected);
+        drop(mapped);
+        drop(cached);
+        std::fs::remove_file(&file_path).unwrap();
+        let _ = std::fs::remove_file(crate::FreeSpaceMap::path_for(&file_path));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::data_type::take;
+use crate::Result;
+
+/// The transaction id of rows stored without a tuple header, by `insert_row`
+/// or before tuple headers existed. Every snapshot sees them, so no
+/// transaction may use it.
+pub const FROZEN_XID: u32 = 0;
+
+/// Set in a tuple's field count when the tuple header follows it, which
+/// leaves 15 bits for the count.
+pub(crate) const TUPLE_HAS_HEADER: u16 = 0x8000;
+
+/// Most fields a stored tuple can have, the largest count below
+/// `TUPLE_HAS_HEADER`.
+pub const MAX_TUPLE_FIELDS: usize = TUPLE_HAS_HEADER as usize - 1;
+pub(crate) const TUPLE_HEADER_SIZE: usize = 8;
+
+/// What a read should see: the work of transaction `xid` itself and of the
+/// transactions before it, except those in `active`, which were still running
+/// when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub xid: u32,
+    pub active: Vec<u32>,
+}
+
+impl Snapshot {
+    pub fn new(xid: u32, active: Vec<u32>) -> Snapshot {
+        Snapshot { xid, active }
+    }
+
+    /// Whether the snapshot sees what transaction `xid` did.
+    pub fn sees(&self, xid: u32) -> bool {
+        xid == FROZEN_XID || xid == self.xid || (xid < self.xid && !self.active.contains(&xid))
+    }
+}
+
+/// The transactions that inserted and deleted a row. `xmax` is 0 while the
+/// row is live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TupleHeader {
+    pub(crate) xmin: u32,
+    pub(crate) xmax: u32,
+}
+
+impl TupleHeader {
+    pub(crate) const FROZEN: TupleHeader = TupleHeader {
+        xmin: FROZEN_XID,
+        xmax: 0,
+    };
+
+    /// Whether a read under `snapshot` sees the row. A read without one sees
+    /// the latest state: every row not deleted.
+    pub(crate) fn visible_to(&self, snapshot: Option<&Snapshot>) -> bool {
+        match snapshot {
+            None => self.xmax == 0,
+            Some(snapshot) => {
+                snapshot.sees(self.xmin) && (self.xmax == 0 || !snapshot.sees(self.xmax))
+            }
+        }
+    }
+}
+
+/// Decodes the field count and tuple header at the start of a stored tuple,
+/// returning them with the offset of the null bitmap. A tuple without a
+/// header is frozen.
+pub(crate) fn parse_tuple_prefix(
+    page: &[u8],
+    pointer: usize,
+) -> Result<(TupleHeader, usize, usize)> {
+    let count = u16::from_le_bytes(take(page, pointer, "field count")?);
+    let field_count = (count & !TUPLE_HAS_HEADER) as usize;
+    if count & TUPLE_HAS_HEADER == 0 {
+        return Ok((TupleHeader::FROZEN, field_count, pointer + 2));
+    }
+    let xmin = u32::from_le_bytes(take(page, pointer + 2, "xmin")?);
+    let xmax = u32::from_le_bytes(take(page, pointer + 6, "xmax")?);
+    Ok((
+        TupleHeader { xmin, xmax },
+        field_count,
+        pointer + 2 + TUPLE_HEADER_SIZE,
+    ))
+}
+
+/// Entry parser for just the header of a stored tuple.
+pub(crate) fn parse_tuple_header(page: &[u8], pointer: usize) -> Result<(TupleHeader, usize)> {
+    let (header, _, offset) = parse_tuple_prefix(page, pointer)?;
+    Ok((header, offset))
+}
+
+/// The tuple's bytes with `xmax` stamped into its header, adding a frozen
+/// header to a tuple that has none.
+pub(crate) fn stamp_xmax(tuple: &[u8], xmax: u32) -> Result<Vec<u8>> {
+    let count = u16::from_le_bytes(take(tuple, 0, "field count")?);
+    let mut stamped = Vec::with_capacity(tuple.len() + TUPLE_HEADER_SIZE);
+    stamped.extend_from_slice(&(count | TUPLE_HAS_HEADER).to_le_bytes());
+    if count & TUPLE_HAS_HEADER == 0 {
+        stamped.extend_from_slice(&FROZEN_XID.to_le_bytes());
+        stamped.extend_from_slice(&xmax.to_le_bytes());
+        stamped.extend_from_slice(&tuple[2..]);
+    } else {
+        let (header, _, _) = parse_tuple_prefix(tuple, 0)?;
+        stamped.extend_from_slice(&header.xmin.to_le_bytes());
+        stamped.extend_from_slice(&xmax.to_le_bytes());
+        stamped.extend_from_slice(&tuple[2 + TUPLE_HEADER_SIZE..]);
+    }
+    Ok(stamped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColumnDefinition, DataType, ScanOptions, Storage, StorageError};
+
+    #[test]
+    fn test_snapshots_see_committed_rows() {
+        let base_dir = crate::tests::temp_base_dir("mvcc");
+        let storage = Storage::new(&base_dir);
+        let table_id = storage
+            .create_table(
+                "events",
+                &[ColumnDefinition {
+                    name: "id".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    is_nullable: false,
+                    is_primary_key: false,
+                    default: None,
+                }],
+            )
+            .unwrap();
+        let ids = |snapshot: Option<Snapshot>| -> Vec<i32> {
+            let options = match snapshot {
+                Some(snapshot) => ScanOptions::new().with_snapshot(snapshot),
+                None => ScanOptions::new(),
+            };
+            storage
+                .scan(table_id, options)
+                .unwrap()
+                .map(|row| match row.unwrap()[0] {
+                    Some(DataType::Integer32(id)) => id,
+                    ref other => panic!("unexpected value {:?}", other),
+                })
+                .collect()
+        };
+
+        let row = |id| [Some(DataType::Integer32(id))];
+        let frozen = storage.insert_row(table_id, &row(1)).unwrap();
+        let tuple_id = storage.insert_row_as(table_id, &row(2), 10).unwrap();
+        storage.delete_row_as(table_id, tuple_id, 20).unwrap();
+        storage.insert_row_as(table_id, &row(3), 30).unwrap();
+
+        assert_eq!(ids(Some(Snapshot::new(5, vec![]))), [1]);
+        assert_eq!(ids(Some(Snapshot::new(15, vec![]))), [1, 2]);
+        assert_eq!(ids(Some(Snapshot::new(25, vec![]))), [1]);
+        // A deleter still running when the snapshot was taken does not count,
+        // and a transaction sees its own work.
+        assert_eq!(ids(Some(Snapshot::new(25, vec![20]))), [1, 2]);
+        assert_eq!(ids(Some(Snapshot::new(20, vec![]))), [1]);
+        assert_eq!(ids(Some(Snapshot::new(31, vec![30]))), [1]);
+        assert_eq!(ids(None), [1, 3]);
+        let heap_path = storage.heap_path(table_id);
+        assert_eq!(storage.read_tuples(&heap_path).unwrap().len(), 2);
+        let stats = storage.analyze(table_id).unwrap();
+        assert_eq!((stats.live_tuples, stats.dead_tuples), (2, 1));
+
+        // Deleting a row without a header gives it one; a row is deleted once.
+        storage.delete_row_as(table_id, frozen, 40).unwrap();
+        assert_eq!(ids(Some(Snapshot::new(35, vec![]))), [1, 3]);
+        assert_eq!(ids(Some(Snapshot::new(45, vec![]))), [3]);
+        assert!(matches!(
+            storage.delete_row_as(table_id, frozen, 41),
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.insert_row_as(table_id, &row(4), FROZEN_XID),
+            Err(StorageError::InvalidXid(FROZEN_XID))
+        ));
+
+        // Vacuum removes the rows deleted before the horizon, and only those.
+        assert_eq!(storage.vacuum_table(table_id, 30).unwrap(), 1);
+        assert_eq!(ids(Some(Snapshot::new(15, vec![]))), [1]);
+        assert_eq!(ids(Some(Snapshot::new(35, vec![]))), [1, 3]);
+        assert_eq!(storage.vacuum_table(table_id, 50).unwrap(), 1);
+        assert_eq!(ids(Some(Snapshot::new(35, vec![]))), [3]);
+        assert_eq!(storage.analyze(table_id).unwrap().live_tuples, 1);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_deleters_delete_a_row_once() {
+        let base_dir = crate::tests::temp_base_dir("mvcc_deleters");
+        let storage = std::sync::Arc::new(Storage::new(&base_dir));
+        let table_id = storage
+            .create_table("events", &[crate::tests::column("id", "INTEGER", true)])
+            .unwrap();
+        for round in 0..100 {
+            let tuple_id = storage
+                .insert_row_as(table_id, &[Some(DataType::Integer32(round))], 10)
+                .unwrap();
+            let threads: Vec<_> = (0..4)
+                .map(|thread| {
+                    let storage = storage.clone();
+                    std::thread::spawn(move || {
+                        storage
+                            .delete_row_as(table_id, tuple_id, 20 + thread)
+                            .is_ok()
+                    })
+                })
+                .collect();
+            let deleted = threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .filter(|deleted| *deleted)
+                .count();
+            assert_eq!(deleted, 1, "round {}", round);
+        }
+        drop(storage);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_field_count_stays_clear_of_header_flag() {
+        let base_dir = crate::tests::temp_base_dir("max_tuple_fields");
+        let storage = Storage::new(&base_dir);
+        let heap_path = crate::tests::temp_path("max_tuple_fields_heap");
+        let _ = std::fs::remove_file(&heap_path);
+        storage.create_postgres_file(&heap_path).unwrap();
+
+        let widest = vec![None; MAX_TUPLE_FIELDS];
+        storage.insert_tuple(&heap_path, &widest).unwrap();
+        let too_wide = vec![None; MAX_TUPLE_FIELDS + 1];
+        assert!(matches!(
+            storage.insert_tuple(&heap_path, &too_wide),
+            Err(StorageError::TooManyFields { count, max })
+                if count == 0x8000 && max == MAX_TUPLE_FIELDS
+        ));
+        let rows = storage.read_tuples(&heap_path).unwrap();
+        assert_eq!(rows, [widest]);
+        std::fs::remove_file(&heap_path).unwrap();
+        let _ = std::fs::remove_dir_all(&base_dir);
+    }
+}
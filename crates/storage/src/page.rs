@@ -0,0 +1,719 @@
+use crate::{Result, StorageError};
+
+/// Page size of a Storage not given another with `Storage::with_page_size`.
+pub const PAGE_SIZE: usize = 8192;
+
+/// Smallest page size a Storage accepts.
+pub const MIN_PAGE_SIZE: usize = 1024;
+
+/// Largest page size a Storage accepts. `ItemId` offsets have 15 bits, so no
+/// entry can start further into a page than this.
+pub const MAX_PAGE_SIZE: usize = 32768;
+
+pub const PAGE_HEADER_SIZE: usize = 24;
+
+/// Layout version written into new pages. Version 1 pages predate the magic
+/// number and cannot be told apart from foreign files; version 2 pages have
+/// 2-byte slot pointers instead of `ItemId`s; version 3 pages are always 8192
+/// bytes and do not record their size.
+pub const PAGE_LAYOUT_VERSION: u8 = 4;
+
+/// Size of an `ItemId` in the slot directory.
+pub const ITEM_ID_SIZE: usize = 4;
+
+/// Identifies a page as written by this storage engine.
+pub const PAGE_MAGIC: u32 = u32::from_le_bytes(*b"RSRD");
+
+/// Set when the header checksum field holds a checksum of the page.
+pub const PAGE_FLAG_HAS_CHECKSUM: u8 = 0x01;
+
+/// Set on overflow pages, which hold one chunk of a value too large to keep
+/// in its tuple and take no entries. Their special space holds the number of
+/// the chain's next page, or `NO_NEXT_PAGE` on the last one.
+pub const PAGE_FLAG_OVERFLOW: u8 = 0x02;
+
+/// Set only on the copy of a page stored compressed in its file, with the
+/// `compression` feature. Pages are expanded as they are read, so the pages
+/// the rest of the library sees never carry it.
+pub const PAGE_FLAG_COMPRESSED: u8 = 0x04;
+
+pub(crate) const OVERFLOW_SPECIAL_SIZE: usize = 4;
+
+pub(crate) const NO_NEXT_PAGE: u32 = u32::MAX;
+
+/// Bytes of a value one overflow page holds, as the single entry of the page.
+pub(crate) fn overflow_chunk_size(page_size: usize) -> usize {
+    page_size - PAGE_HEADER_SIZE - ITEM_ID_SIZE - OVERFLOW_SPECIAL_SIZE
+}
+
+/// Whether `page_size` is a power of two between `MIN_PAGE_SIZE` and
+/// `MAX_PAGE_SIZE`.
+pub(crate) fn is_valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
+const LSN_OFFSET: usize = 0;
+const CHECKSUM_OFFSET: usize = 8;
+const FLAGS_OFFSET: usize = 10;
+const LOWER_OFFSET: usize = 12;
+const HIGHER_OFFSET: usize = 14;
+const SPECIAL_SPACE_OFFSET: usize = 16;
+const MAGIC_OFFSET: usize = 18;
+const PAGE_SIZE_OFFSET: usize = 22;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32c_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// CRC-32C of the page with the checksum field skipped, folded to 16 bits.
+pub(crate) fn page_checksum(page: &[u8]) -> u16 {
+    let crc = crc32c_update(!0, &page[..CHECKSUM_OFFSET]);
+    let crc = !crc32c_update(crc, &page[CHECKSUM_OFFSET + 2..]);
+    (crc ^ (crc >> 16)) as u16
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The fixed-size header at the start of every page.
+///
+/// The flags field stores the flag bits in its low byte and the page layout
+/// version in its high byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHeader {
+    pub lsn: u64,
+    pub checksum: u16,
+    pub flags: u16,
+    pub lower: u16,
+    pub higher: u16,
+    pub special_space: u16,
+    pub magic: u32,
+    /// Size of every page of the file, so a file is not read with pages of
+    /// another size.
+    pub page_size: u16,
+}
+
+impl PageHeader {
+    /// Decodes the header, checks that the page is in a format this library
+    /// reads, and that the slot directory, free space and special space are
+    /// laid out in order within the page.
+    pub fn parse(page: &[u8]) -> Result<PageHeader> {
+        let header = Self::decode(page);
+        header.check_format()?;
+        header
+            .check_invariants()
+            .map_err(|reason| StorageError::InvalidPageHeader { reason })?;
+        Ok(header)
+    }
+
+    /// Fails with `InvalidFormat` if the page was not written by this engine,
+    /// or `UnsupportedVersion` if it was written in a layout other than the
+    /// current one.
+    ///
+    /// Only the current layout is read. A later layout change adds the older
+    /// versions it can still read, and how to migrate them, here.
+    pub fn check_format(&self) -> Result<()> {
+        if self.magic != PAGE_MAGIC {
+            return Err(StorageError::InvalidFormat { magic: self.magic });
+        }
+        if self.version() != PAGE_LAYOUT_VERSION {
+            return Err(StorageError::UnsupportedVersion {
+                found: self.version(),
+                supported: PAGE_LAYOUT_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decodes the header fields without validating them. Panics if `page` is
+    /// shorter than a header.
+    pub(crate) fn decode(page: &[u8]) -> PageHeader {
+        PageHeader {
+            lsn: u64::from_le_bytes(page[LSN_OFFSET..LSN_OFFSET + 8].try_into().unwrap()),
+            checksum: read_u16(page, CHECKSUM_OFFSET),
+            flags: read_u16(page, FLAGS_OFFSET),
+            lower: read_u16(page, LOWER_OFFSET),
+            higher: read_u16(page, HIGHER_OFFSET),
+            special_space: read_u16(page, SPECIAL_SPACE_OFFSET),
+            magic: u32::from_le_bytes(page[MAGIC_OFFSET..MAGIC_OFFSET + 4].try_into().unwrap()),
+            page_size: read_u16(page, PAGE_SIZE_OFFSET),
+        }
+    }
+
+    pub(crate) fn check_invariants(&self) -> std::result::Result<(), String> {
+        if !is_valid_page_size(self.page_size as usize) {
+            return Err(format!(
+                "page size {} is not a power of two between {} and {}",
+                self.page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+            ));
+        }
+        if (self.lower as usize) < PAGE_HEADER_SIZE {
+            return Err(format!(
+                "lower {} is inside the {}-byte page header",
+                self.lower, PAGE_HEADER_SIZE
+            ));
+        }
+        if self.lower > self.higher {
+            return Err(format!(
+                "lower {} is past higher {}",
+                self.lower, self.higher
+            ));
+        }
+        if self.higher > self.special_space {
+            return Err(format!(
+                "higher {} is past special space {}",
+                self.higher, self.special_space
+            ));
+        }
+        if self.special_space > self.page_size {
+            return Err(format!(
+                "special space {} is past the end of the {}-byte page",
+                self.special_space, self.page_size
+            ));
+        }
+        if !(self.lower as usize - PAGE_HEADER_SIZE).is_multiple_of(ITEM_ID_SIZE) {
+            return Err(format!(
+                "lower {} does not end a slot directory of {}-byte item ids",
+                self.lower, ITEM_ID_SIZE
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that an item with storage lies in the data area, between
+    /// `higher` and the special space. Only call on a header that passed
+    /// `check_invariants`.
+    pub(crate) fn check_item_id(&self, item_id: &ItemId) -> std::result::Result<(), String> {
+        if !item_id.has_storage() {
+            return Ok(());
+        }
+        let offset = item_id.offset as usize;
+        if offset < PAGE_HEADER_SIZE {
+            return Err(format!("offset {} points into the page header", offset));
+        }
+        if offset < self.lower as usize {
+            return Err(format!("offset {} points into the slot directory", offset));
+        }
+        if offset < self.higher as usize {
+            return Err(format!(
+                "offset {} is in the free space below higher {}",
+                offset, self.higher
+            ));
+        }
+        if offset + item_id.length as usize > self.special_space as usize {
+            return Err(format!(
+                "entry of {} bytes at offset {} runs past special space {}",
+                item_id.length, offset, self.special_space
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn write_to(&self, page: &mut [u8]) {
+        page[LSN_OFFSET..LSN_OFFSET + 8].copy_from_slice(&self.lsn.to_le_bytes());
+        write_u16(page, CHECKSUM_OFFSET, self.checksum);
+        write_u16(page, FLAGS_OFFSET, self.flags);
+        write_u16(page, LOWER_OFFSET, self.lower);
+        write_u16(page, HIGHER_OFFSET, self.higher);
+        write_u16(page, SPECIAL_SPACE_OFFSET, self.special_space);
+        page[MAGIC_OFFSET..MAGIC_OFFSET + 4].copy_from_slice(&self.magic.to_le_bytes());
+        write_u16(page, PAGE_SIZE_OFFSET, self.page_size);
+    }
+
+    /// Bytes between the end of the slot directory and the start of the data
+    /// area. Always 0 on overflow pages, since no entry may go there.
+    pub fn free_space(&self) -> usize {
+        if self.is_overflow() {
+            return 0;
+        }
+        self.higher.saturating_sub(self.lower) as usize
+    }
+
+    pub fn slot_count(&self) -> usize {
+        (self.lower as usize).saturating_sub(PAGE_HEADER_SIZE) / ITEM_ID_SIZE
+    }
+
+    /// Pages written before checksums existed leave `PAGE_FLAG_HAS_CHECKSUM`
+    /// clear and are neither stamped nor verified.
+    pub fn has_checksum(&self) -> bool {
+        self.flags.to_le_bytes()[0] & PAGE_FLAG_HAS_CHECKSUM != 0
+    }
+
+    pub fn is_overflow(&self) -> bool {
+        self.flags.to_le_bytes()[0] & PAGE_FLAG_OVERFLOW != 0
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags.to_le_bytes()[0] & PAGE_FLAG_COMPRESSED != 0
+    }
+
+    pub fn version(&self) -> u8 {
+        self.flags.to_le_bytes()[1]
+    }
+
+    /// Whether an entry of `entry_size` bytes plus its `ItemId` fits between
+    /// the end of the slot directory and the start of the data area.
+    pub(crate) fn entry_fits(&self, entry_size: usize) -> bool {
+        !self.is_overflow()
+            && (self.higher as usize)
+                .checked_sub(entry_size)
+                .is_some_and(|new_higher| new_higher >= self.lower as usize + ITEM_ID_SIZE)
+    }
+}
+
+/// State of a slot, stored in the two status bits of its `ItemId`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemStatus {
+    /// The slot holds nothing; its entry was deleted and its bytes reclaimed.
+    Unused = 0,
+    /// The slot points at a live entry.
+    Normal = 1,
+    /// Reserved for an entry that moved to the slot named by the offset.
+    /// Nothing writes it yet.
+    Redirect = 2,
+    /// The entry was deleted but its bytes are still on the page.
+    Dead = 3,
+}
+
+/// A slot directory entry: where the slot's entry starts, its state and its
+/// length. Stored as 4 little-endian bytes holding 15 bits of offset, 2 bits
+/// of status and 15 bits of length, like PostgreSQL's `ItemIdData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItemId {
+    pub offset: u16,
+    pub status: ItemStatus,
+    pub length: u16,
+}
+
+impl ItemId {
+    pub const UNUSED: ItemId = ItemId {
+        offset: 0,
+        status: ItemStatus::Unused,
+        length: 0,
+    };
+
+    pub fn normal(offset: u16, length: u16) -> ItemId {
+        ItemId {
+            offset,
+            status: ItemStatus::Normal,
+            length,
+        }
+    }
+
+    pub fn is_normal(&self) -> bool {
+        self.status == ItemStatus::Normal
+    }
+
+    /// Whether the item's bytes occupy the data area.
+    pub fn has_storage(&self) -> bool {
+        matches!(self.status, ItemStatus::Normal | ItemStatus::Dead)
+    }
+
+    fn pack(&self) -> u32 {
+        (self.offset as u32 & 0x7FFF)
+            | (self.status as u32) << 15
+            | (self.length as u32 & 0x7FFF) << 17
+    }
+
+    fn unpack(packed: u32) -> ItemId {
+        ItemId {
+            offset: (packed & 0x7FFF) as u16,
+            status: match (packed >> 15) & 0b11 {
+                0 => ItemStatus::Unused,
+                1 => ItemStatus::Normal,
+                2 => ItemStatus::Redirect,
+                _ => ItemStatus::Dead,
+            },
+            length: (packed >> 17) as u16,
+        }
+    }
+}
+
+/// Offset of the given slot's `ItemId` in the slot directory.
+pub(crate) fn slot_offset(slot: u16) -> usize {
+    PAGE_HEADER_SIZE + slot as usize * ITEM_ID_SIZE
+}
+
+pub(crate) fn read_item_id(page: &[u8], slot: u16) -> ItemId {
+    let offset = slot_offset(slot);
+    ItemId::unpack(u32::from_le_bytes(
+        page[offset..offset + ITEM_ID_SIZE].try_into().unwrap(),
+    ))
+}
+
+pub(crate) fn write_item_id(page: &mut [u8], slot: u16, item_id: ItemId) {
+    let offset = slot_offset(slot);
+    page[offset..offset + ITEM_ID_SIZE].copy_from_slice(&item_id.pack().to_le_bytes());
+}
+
+/// The `ItemId`s of the page's slot directory, in slot order.
+pub(crate) fn item_ids(page: &[u8], header: &PageHeader) -> Vec<ItemId> {
+    (0..header.slot_count() as u16)
+        .map(|slot| read_item_id(page, slot))
+        .collect()
+}
+
+/// The page's special space, from `special_space` to the end of the page.
+/// Empty on pages without one.
+pub fn read_special(page: &[u8]) -> &[u8] {
+    let start = PageHeader::decode(page).special_space as usize;
+    page.get(start..).unwrap_or(&[])
+}
+
+/// Copies `bytes` to the start of the page's special space, failing with
+/// `SpecialSpaceOverflow` if they do not fit. The checksum is left for the
+/// page write to stamp.
+pub fn write_special(page: &mut [u8], bytes: &[u8]) -> Result<()> {
+    let start = PageHeader::decode(page).special_space as usize;
+    let available = page.len().saturating_sub(start);
+    if bytes.len() > available {
+        return Err(StorageError::SpecialSpaceOverflow {
+            size: bytes.len(),
+            available,
+        });
+    }
+    page[start..start + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Describes a freshly initialized page. Every path that creates pages goes
+/// through `build` so header defaults live in one place. The LSN starts at 0
+/// until the page is first written.
+#[derive(Debug, Clone, Copy)]
+pub struct PageInit {
+    pub special_size: u16,
+    pub flags: u8,
+    pub version: u8,
+}
+
+impl Default for PageInit {
+    fn default() -> Self {
+        Self {
+            special_size: 0,
+            flags: PAGE_FLAG_HAS_CHECKSUM,
+            version: PAGE_LAYOUT_VERSION,
+        }
+    }
+}
+
+impl PageInit {
+    pub(crate) fn overflow() -> PageInit {
+        PageInit {
+            special_size: OVERFLOW_SPECIAL_SIZE as u16,
+            flags: PAGE_FLAG_HAS_CHECKSUM | PAGE_FLAG_OVERFLOW,
+            ..PageInit::default()
+        }
+    }
+
+    /// Builds a page of `page_size` bytes. Panics if the page size is not
+    /// one a Storage accepts or the special area does not leave room for the
+    /// header.
+    pub fn build(&self, page_size: usize) -> Box<[u8]> {
+        assert!(
+            is_valid_page_size(page_size),
+            "invalid page size {}",
+            page_size
+        );
+        assert!(
+            self.special_size as usize <= page_size - PAGE_HEADER_SIZE,
+            "special area of {} bytes does not fit in a page",
+            self.special_size
+        );
+        let mut page = vec![0u8; page_size].into_boxed_slice();
+        let special_space = (page_size - self.special_size as usize) as u16;
+
+        let mut header = PageHeader {
+            lsn: 0,
+            checksum: 0,
+            flags: u16::from_le_bytes([self.flags, self.version]),
+            lower: PAGE_HEADER_SIZE as u16,
+            higher: special_space,
+            special_space,
+            magic: PAGE_MAGIC,
+            page_size: page_size as u16,
+        };
+        header.write_to(&mut page);
+        if header.has_checksum() {
+            header.checksum = page_checksum(&page);
+            header.write_to(&mut page);
+        }
+        page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> PageHeader {
+        PageHeader {
+            lsn: 0x0102_0304_0506_0708,
+            checksum: 0xBEEF,
+            flags: u16::from_le_bytes([PAGE_FLAG_HAS_CHECKSUM, PAGE_LAYOUT_VERSION]),
+            lower: 32,
+            higher: 4000,
+            special_space: 8000,
+            magic: PAGE_MAGIC,
+            page_size: PAGE_SIZE as u16,
+        }
+    }
+
+    #[test]
+    fn test_page_header_round_trip() {
+        let mut page = [0u8; PAGE_SIZE];
+        header().write_to(&mut page);
+        let parsed = PageHeader::parse(&page).unwrap();
+        assert_eq!(parsed, header());
+        assert_eq!(parsed.free_space(), 3968);
+        assert_eq!(parsed.slot_count(), 2);
+        assert!(parsed.has_checksum());
+        assert_eq!(parsed.version(), PAGE_LAYOUT_VERSION);
+        assert_eq!(
+            page[PAGE_HEADER_SIZE..],
+            [0u8; PAGE_SIZE - PAGE_HEADER_SIZE]
+        );
+    }
+
+    #[test]
+    fn test_entry_fits_leaves_room_for_its_item_id() {
+        let header = header();
+        let room = header.free_space() - ITEM_ID_SIZE;
+        assert!(header.entry_fits(room));
+        assert!(!header.entry_fits(room + 1));
+        assert!(!header.entry_fits(header.free_space()));
+        assert!(!header.entry_fits(header.higher as usize + 1));
+
+        let full = PageHeader {
+            higher: header.lower + 2,
+            ..header
+        };
+        assert!(!full.entry_fits(0));
+    }
+
+    #[test]
+    fn test_page_header_rejects_invalid_layouts() {
+        let cases = [
+            (
+                PageHeader {
+                    lower: 17,
+                    ..header()
+                },
+                "inside",
+            ),
+            (
+                PageHeader {
+                    lower: 4001,
+                    ..header()
+                },
+                "past higher",
+            ),
+            (
+                PageHeader {
+                    higher: 8001,
+                    ..header()
+                },
+                "past special space",
+            ),
+            (
+                PageHeader {
+                    higher: 8193,
+                    special_space: 8193,
+                    ..header()
+                },
+                "past the end",
+            ),
+            (
+                PageHeader {
+                    lower: 34,
+                    ..header()
+                },
+                "does not end a slot directory",
+            ),
+            (
+                PageHeader {
+                    page_size: 8000,
+                    ..header()
+                },
+                "not a power of two",
+            ),
+            (
+                PageHeader {
+                    page_size: 512,
+                    ..header()
+                },
+                "not a power of two between",
+            ),
+        ];
+        for (invalid, expected) in cases {
+            let mut page = [0u8; PAGE_SIZE];
+            invalid.write_to(&mut page);
+            match PageHeader::parse(&page) {
+                Err(StorageError::InvalidPageHeader { reason }) => {
+                    assert!(reason.contains(expected), "{}", reason)
+                }
+                other => panic!("expected InvalidPageHeader, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_page_header_rejects_foreign_and_other_formats() {
+        let mut page = [0u8; PAGE_SIZE];
+        header().write_to(&mut page);
+        page[MAGIC_OFFSET] ^= 0xFF;
+        assert!(matches!(
+            PageHeader::parse(&page),
+            Err(StorageError::InvalidFormat { .. })
+        ));
+
+        PageHeader {
+            flags: u16::from_le_bytes([PAGE_FLAG_HAS_CHECKSUM, PAGE_LAYOUT_VERSION + 1]),
+            ..header()
+        }
+        .write_to(&mut page);
+        assert!(matches!(
+            PageHeader::parse(&page),
+            Err(StorageError::UnsupportedVersion { found, supported })
+                if found == PAGE_LAYOUT_VERSION + 1 && supported == PAGE_LAYOUT_VERSION
+        ));
+
+        // Pages of the old layout have 2-byte slot pointers.
+        PageHeader {
+            flags: u16::from_le_bytes([PAGE_FLAG_HAS_CHECKSUM, 2]),
+            ..header()
+        }
+        .write_to(&mut page);
+        assert!(matches!(
+            PageHeader::parse(&page),
+            Err(StorageError::UnsupportedVersion { found: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_item_id_round_trip() {
+        let expected = [
+            ItemId::UNUSED,
+            ItemId::normal(PAGE_HEADER_SIZE as u16, 1),
+            ItemId::normal(PAGE_SIZE as u16 - 1, 0x7FFF),
+            ItemId {
+                offset: 4000,
+                status: ItemStatus::Dead,
+                length: 12,
+            },
+            ItemId {
+                offset: 3,
+                status: ItemStatus::Redirect,
+                length: 0,
+            },
+        ];
+        let mut page = [0u8; PAGE_SIZE];
+        assert_eq!(read_item_id(&page, 0), ItemId::UNUSED);
+        for (slot, item_id) in expected.iter().enumerate() {
+            write_item_id(&mut page, slot as u16, *item_id);
+        }
+        let header = PageHeader {
+            lower: slot_offset(expected.len() as u16) as u16,
+            ..header()
+        };
+        assert_eq!(item_ids(&page, &header), expected);
+    }
+
+    #[test]
+    fn test_page_init_builds_valid_headers() {
+        for (init, page_size) in [
+            (PageInit::default(), PAGE_SIZE),
+            (PageInit::default(), MIN_PAGE_SIZE),
+            (PageInit::default(), MAX_PAGE_SIZE),
+            (
+                PageInit {
+                    special_size: 16,
+                    ..PageInit::default()
+                },
+                4096,
+            ),
+            (
+                PageInit {
+                    flags: 0,
+                    ..PageInit::default()
+                },
+                PAGE_SIZE,
+            ),
+        ] {
+            let page = init.build(page_size);
+            assert_eq!(page.len(), page_size);
+            let header = PageHeader::parse(&page).unwrap();
+            assert_eq!(header.page_size as usize, page_size);
+            assert_eq!(
+                header.special_space as usize,
+                page_size - init.special_size as usize
+            );
+            assert_eq!(
+                header.free_space(),
+                header.special_space as usize - PAGE_HEADER_SIZE
+            );
+            assert_eq!(header.slot_count(), 0);
+        }
+
+        let header = PageHeader::parse(&PageInit::overflow().build(PAGE_SIZE)).unwrap();
+        assert!(header.is_overflow());
+        assert_eq!(
+            header.special_space as usize,
+            PAGE_SIZE - OVERFLOW_SPECIAL_SIZE
+        );
+        assert_eq!(header.free_space(), 0);
+        assert!(!header.entry_fits(1));
+    }
+
+    #[test]
+    fn test_special_space_accessors() {
+        let mut page = PageInit::default().build(PAGE_SIZE);
+        assert!(read_special(&page).is_empty());
+        write_special(&mut page, &[]).unwrap();
+        assert!(matches!(
+            write_special(&mut page, &[1]),
+            Err(StorageError::SpecialSpaceOverflow {
+                size: 1,
+                available: 0
+            })
+        ));
+
+        let mut page = PageInit {
+            special_size: 16,
+            ..PageInit::default()
+        }
+        .build(PAGE_SIZE);
+        assert_eq!(read_special(&page), [0u8; 16]);
+        write_special(&mut page, &[7; 4]).unwrap();
+        assert_eq!(read_special(&page)[..5], [7, 7, 7, 7, 0]);
+        assert!(write_special(&mut page, &[9; 17]).is_err());
+        assert_eq!(&page[PAGE_SIZE - 16..PAGE_SIZE - 12], [7; 4]);
+    }
+}
@@ -0,0 +1,387 @@
+use std::io::{Read, Write};
+
+use crate::{check_row, with_defaults, ColumnMetadata, DataType, Result, StorageError};
+
+/// The 11 bytes PostgreSQL's binary COPY format starts with.
+const SIGNATURE: &[u8; 11] = b"PGCOPY\n\xff\r\n\0";
+/// Set in the header flags when every tuple carries an OID, which this
+/// engine's rows do not have.
+const FLAG_HAS_OIDS: u32 = 1 << 16;
+/// Flags a reader must understand: bit 16 and the reserved bits above it.
+const CRITICAL_FLAGS: u32 = 0xffff_0000;
+/// The field count that ends the data in place of a tuple.
+const TRAILER: i16 = -1;
+
+/// Writes the signature, flags and an empty header extension.
+pub(crate) fn write_header(writer: &mut impl Write) -> Result<()> {
+    writer.write_all(SIGNATURE)?;
+    writer.write_all(&0u32.to_be_bytes())?;
+    writer.write_all(&0u32.to_be_bytes())?;
+    Ok(())
+}
+
+/// Writes the row as a tuple: its field count, then each field's length and
+/// bytes in the binary form of the PostgreSQL type, with -1 for NULL.
+/// Integer32, Float32, Integer64 and Float64 are int4, float4, int8 and
+/// float8, Boolean is bool and String is text.
+pub(crate) fn write_tuple(writer: &mut impl Write, row: &[Option<DataType>]) -> Result<()> {
+    writer.write_all(&(row.len() as i16).to_be_bytes())?;
+    for value in row {
+        let bytes = match value {
+            None => {
+                writer.write_all(&(-1i32).to_be_bytes())?;
+                continue;
+            }
+            Some(DataType::String(value)) => value.as_bytes().to_vec(),
+            Some(DataType::Integer32(value)) => value.to_be_bytes().to_vec(),
+            Some(DataType::Float32(value)) => value.to_be_bytes().to_vec(),
+            Some(DataType::Integer64(value)) => value.to_be_bytes().to_vec(),
+            Some(DataType::Float64(value)) => value.to_be_bytes().to_vec(),
+            Some(DataType::Boolean(value)) => vec![*value as u8],
+        };
+        writer.write_all(&(bytes.len() as i32).to_be_bytes())?;
+        writer.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_trailer(writer: &mut impl Write) -> Result<()> {
+    writer.write_all(&TRAILER.to_be_bytes())?;
+    Ok(())
+}
+
+fn invalid(tuple: Option<usize>, column_name: Option<&str>, reason: &str) -> StorageError {
+    StorageError::InvalidPgCopy {
+        tuple,
+        column_name: column_name.map(str::to_string),
+        reason: reason.to_string(),
+    }
+}
+
+/// Fills `buf`, failing with `InvalidPgCopy` if the data ends first.
+fn read_into(reader: &mut impl Read, buf: &mut [u8], tuple: Option<usize>) -> Result<()> {
+    reader.read_exact(buf).map_err(|err| {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            invalid(tuple, None, "the data ends before its trailer")
+        } else {
+            err.into()
+        }
+    })
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read, tuple: Option<usize>) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    read_into(reader, &mut bytes, tuple)?;
+    Ok(bytes)
+}
+
+/// Reads the header, skipping its extension area. Data with OIDs or with
+/// flags this reader does not know fails with `InvalidPgCopy`.
+pub(crate) fn read_header(reader: &mut impl Read) -> Result<()> {
+    if &read_array::<11>(reader, None)? != SIGNATURE {
+        return Err(invalid(None, None, "missing the PGCOPY signature"));
+    }
+    let flags = u32::from_be_bytes(read_array(reader, None)?);
+    if flags & FLAG_HAS_OIDS != 0 {
+        return Err(invalid(None, None, "tuples with OIDs are not supported"));
+    }
+    if flags & CRITICAL_FLAGS != 0 {
+        return Err(invalid(None, None, "unknown critical flags are set"));
+    }
+    let extension_length = u32::from_be_bytes(read_array(reader, None)?);
+    let skipped = std::io::copy(
+        &mut reader.take(extension_length as u64),
+        &mut std::io::sink(),
+    )?;
+    if skipped < extension_length as u64 {
+        return Err(invalid(None, None, "the data ends before its trailer"));
+    }
+    Ok(())
+}
+
+/// Reads tuple number `tuple`, counting from 1, as a row of `columns` with
+/// their defaults filled in and checked like a row for `insert_row`, or
+/// `None` at the trailer. Every problem fails with `InvalidPgCopy`.
+pub(crate) fn read_tuple(
+    reader: &mut impl Read,
+    columns: &[ColumnMetadata],
+    tuple: usize,
+) -> Result<Option<Vec<Option<DataType>>>> {
+    let field_count = i16::from_be_bytes(read_array(reader, Some(tuple))?);
+    if field_count == TRAILER {
+        return Ok(None);
+    }
+    if field_count < 0 || field_count as usize != columns.len() {
+        return Err(invalid(
+            Some(tuple),
+            None,
+            &format!("expected {} fields, found {}", columns.len(), field_count),
+        ));
+    }
+    let mut values = Vec::with_capacity(columns.len());
+    for column in columns {
+        let length = i32::from_be_bytes(read_array(reader, Some(tuple))?);
+        if length == -1 {
+            values.push(None);
+            continue;
+        }
+        let column_invalid = |reason: &str| invalid(Some(tuple), Some(&column.column_name), reason);
+        if length < 0 {
+            return Err(column_invalid(&format!("invalid field length {}", length)));
+        }
+        let mut bytes = vec![0; length as usize];
+        read_into(reader, &mut bytes, Some(tuple))?;
+        let value =
+            decode_field(&column.data_type, bytes).map_err(|reason| column_invalid(&reason))?;
+        values.push(Some(value));
+    }
+    let row = with_defaults(columns, &values)?;
+    check_row(columns, &row).map_err(|err| match err {
+        StorageError::NullViolation { column_name } => invalid(
+            Some(tuple),
+            Some(&column_name),
+            "NULL in a column that does not allow it",
+        ),
+        StorageError::ValueTooLong {
+            ref column_name, ..
+        } => invalid(Some(tuple), Some(column_name), &err.to_string()),
+        err => invalid(Some(tuple), None, &err.to_string()),
+    })?;
+    Ok(Some(row))
+}
+
+/// Decodes a field from the binary form `write_tuple` gives the type.
+fn decode_field(data_type: &str, bytes: Vec<u8>) -> std::result::Result<DataType, String> {
+    let length = bytes.len();
+    let wrong_length = |expected: usize| {
+        format!(
+            "expected {} bytes for {}, found {}",
+            expected, data_type, length
+        )
+    };
+    let value = match DataType::from_type_name(data_type).map_err(|err| err.to_string())? {
+        DataType::String(_) => {
+            DataType::String(String::from_utf8(bytes).map_err(|_| "invalid UTF-8".to_string())?)
+        }
+        DataType::Integer32(_) => DataType::Integer32(i32::from_be_bytes(
+            bytes.as_slice().try_into().map_err(|_| wrong_length(4))?,
+        )),
+        DataType::Float32(_) => DataType::Float32(f32::from_be_bytes(
+            bytes.as_slice().try_into().map_err(|_| wrong_length(4))?,
+        )),
+        DataType::Integer64(_) => DataType::Integer64(i64::from_be_bytes(
+            bytes.as_slice().try_into().map_err(|_| wrong_length(8))?,
+        )),
+        DataType::Float64(_) => DataType::Float64(f64::from_be_bytes(
+            bytes.as_slice().try_into().map_err(|_| wrong_length(8))?,
+        )),
+        DataType::Boolean(_) => match bytes.as_slice() {
+            [0] => DataType::Boolean(false),
+            [1] => DataType::Boolean(true),
+            [_] => return Err("expected a bool of 0 or 1".to_string()),
+            _ => return Err(wrong_length(1)),
+        },
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::column;
+    use crate::{Storage, StorageError};
+
+    use super::*;
+
+    /// The stream PostgreSQL writes for `COPY readings TO STDOUT (FORMAT
+    /// binary)` from a table `readings (id int4, name text, score float4)`
+    /// holding (1, 'one', 1.5) and (2, NULL, -0.25).
+    const READINGS: &[u8] = b"PGCOPY\n\xff\r\n\0\
+        \0\0\0\0\
+        \0\0\0\0\
+        \0\x03\
+        \0\0\0\x04\0\0\0\x01\
+        \0\0\0\x03one\
+        \0\0\0\x04\x3f\xc0\0\0\
+        \0\x03\
+        \0\0\0\x04\0\0\0\x02\
+        \xff\xff\xff\xff\
+        \0\0\0\x04\xbe\x80\0\0\
+        \xff\xff";
+
+    #[test]
+    fn test_pg_binary_matches_postgres() {
+        let base_dir = crate::tests::temp_base_dir("pg_binary");
+        let storage = Storage::new(&base_dir);
+        let columns = [
+            column("id", "INTEGER", false),
+            column("name", "VARCHAR", true),
+            column("score", "REAL", true),
+        ];
+        let readings = storage.create_table("readings", &columns).unwrap();
+        let rows = [
+            [
+                Some(DataType::Integer32(1)),
+                Some(DataType::String("one".to_string())),
+                Some(DataType::Float32(1.5)),
+            ],
+            [
+                Some(DataType::Integer32(2)),
+                None,
+                Some(DataType::Float32(-0.25)),
+            ],
+        ];
+        for row in &rows {
+            storage.insert_row(readings, row).unwrap();
+        }
+        let mut output = Vec::new();
+        assert_eq!(storage.copy_to_pg_binary(readings, &mut output).unwrap(), 2);
+        assert_eq!(output, READINGS);
+
+        let copy = storage.create_table("copy", &columns).unwrap();
+        assert_eq!(storage.copy_from_pg_binary(copy, READINGS).unwrap(), 2);
+        let copied: Vec<_> = storage
+            .scan(copy, crate::ScanOptions::new())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(copied, rows);
+
+        // Loading the same keys again fails without storing anything.
+        let err = storage.copy_from_pg_binary(copy, READINGS).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                StorageError::InvalidPgCopy {
+                    tuple: Some(1),
+                    column_name: None,
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+        assert_eq!(storage.analyze(copy).unwrap().live_tuples, 2);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_pg_binary_round_trip_and_bad_data() {
+        let base_dir = crate::tests::temp_base_dir("pg_binary_round_trip");
+        let storage = Storage::new(&base_dir);
+        let columns = [
+            column("id", "BIGINT", false),
+            column("name", "VARCHAR", true),
+            column("ratio", "DOUBLE PRECISION", true),
+            column("done", "BOOLEAN", true),
+        ];
+        let source = storage.create_table("source", &columns).unwrap();
+        let mut rows = Vec::new();
+        for id in 0..300i64 {
+            let name = match id % 3 {
+                0 => None,
+                1 => Some(format!("héllo {}", id)),
+                _ => Some("x".repeat(id as usize * 40)),
+            };
+            rows.push(vec![
+                Some(DataType::Integer64(id - 150)),
+                name.map(DataType::String),
+                Some(DataType::Float64(id as f64 / 7.0)),
+                (id % 5 != 0).then_some(DataType::Boolean(id % 2 == 0)),
+            ]);
+        }
+        for row in &rows {
+            storage.insert_row(source, row).unwrap();
+        }
+        let mut output = Vec::new();
+        assert_eq!(storage.copy_to_pg_binary(source, &mut output).unwrap(), 300);
+        let copy = storage.create_table("copy", &columns).unwrap();
+        assert_eq!(storage.copy_from_pg_binary(copy, &output[..]).unwrap(), 300);
+        // Rows go where there is room, so they come back in another order.
+        let mut copied: Vec<_> = storage
+            .scan(copy, crate::ScanOptions::new())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        copied.sort_by_key(|row| match row[0] {
+            Some(DataType::Integer64(id)) => id,
+            ref other => panic!("unexpected value {:?}", other),
+        });
+        assert_eq!(copied, rows);
+
+        // A header extension is skipped.
+        let mut extended = output[..15].to_vec();
+        extended.extend_from_slice(&3u32.to_be_bytes());
+        extended.extend_from_slice(b"abc");
+        extended.extend_from_slice(&output[19..]);
+        let other = storage.create_table("other", &columns).unwrap();
+        assert_eq!(
+            storage.copy_from_pg_binary(other, &extended[..]).unwrap(),
+            300
+        );
+
+        let empty = storage.create_table("empty", &columns).unwrap();
+        let load = |data: &[u8]| match storage.copy_from_pg_binary(empty, data) {
+            Err(StorageError::InvalidPgCopy {
+                tuple,
+                column_name,
+                reason,
+            }) => (tuple, column_name, reason),
+            other => panic!("unexpected result {:?}", other),
+        };
+        let (tuple, _, reason) = load(b"PGCOPY\n\xff\r\n\x01\0\0\0\0\0\0\0\0\xff\xff");
+        assert_eq!(
+            (tuple, reason.as_str()),
+            (None, "missing the PGCOPY signature")
+        );
+        let mut with_oids = output.clone();
+        with_oids[12] = 1;
+        assert_eq!(load(&with_oids).0, None);
+        // The data is cut off in the middle of the first tuple's id.
+        assert_eq!(
+            load(&output[..30]),
+            (
+                Some(1),
+                None,
+                "the data ends before its trailer".to_string()
+            )
+        );
+        let without_trailer = &output[..output.len() - 2];
+        assert_eq!(load(without_trailer).0, Some(301));
+
+        let tuple = |fields: &[&[u8]]| {
+            let mut data = SIGNATURE.to_vec();
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&(fields.len() as i16).to_be_bytes());
+            for field in fields {
+                data.extend_from_slice(&(field.len() as i32).to_be_bytes());
+                data.extend_from_slice(field);
+            }
+            data.extend_from_slice(&TRAILER.to_be_bytes());
+            data
+        };
+        let id = 7i64.to_be_bytes();
+        let ratio = 0.5f64.to_be_bytes();
+        assert_eq!(
+            load(&tuple(&[&id, b"a", &ratio])),
+            (Some(1), None, "expected 4 fields, found 3".to_string())
+        );
+        assert_eq!(
+            load(&tuple(&[&7i32.to_be_bytes(), b"a", &ratio, &[1]])),
+            (
+                Some(1),
+                Some("id".to_string()),
+                "expected 8 bytes for BIGINT, found 4".to_string()
+            )
+        );
+        assert_eq!(
+            load(&tuple(&[&id, b"\xff", &ratio, &[1]])).1,
+            Some("name".to_string())
+        );
+        assert_eq!(
+            load(&tuple(&[&id, b"a", &ratio, &[2]])).2,
+            "expected a bool of 0 or 1"
+        );
+        assert_eq!(storage.analyze(empty).unwrap().live_tuples, 0);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
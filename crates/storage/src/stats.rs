@@ -0,0 +1,161 @@
+use crate::page::item_ids;
+use crate::{parse_tuple_header, ItemStatus, PageHeader};
+
+/// Counts for a table's heap file from `Storage::analyze`, taken from page
+/// headers, slot directories and tuple headers without decoding any fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableStats {
+    /// Pages in the heap file, overflow pages included.
+    pub page_count: u32,
+    /// Pages holding chunks of strings moved out of their rows.
+    pub overflow_page_count: u32,
+    pub live_tuples: u64,
+    /// Deleted rows whose bytes wait for a vacuum, including rows deleted
+    /// under a transaction. A row deleted with `delete_tuple` that lies at the
+    /// low end of its page's data area is freed at once and not counted.
+    pub dead_tuples: u64,
+    /// Free bytes between the slot directory and the data area, summed over
+    /// the pages rows can go on. Overflow pages are left out.
+    pub free_bytes: u64,
+    /// Bytes the live rows take on their pages, not counting their slots or
+    /// the overflow chunks of strings moved out.
+    pub live_bytes: u64,
+}
+
+impl TableStats {
+    /// Mean size of a live row on its page, or `None` for a table without
+    /// rows.
+    pub fn average_tuple_size(&self) -> Option<f64> {
+        (self.live_tuples > 0).then(|| self.live_bytes as f64 / self.live_tuples as f64)
+    }
+
+    pub(crate) fn add_page(&mut self, page: &[u8], header: &PageHeader) {
+        self.page_count += 1;
+        if header.is_overflow() {
+            self.overflow_page_count += 1;
+            return;
+        }
+        self.free_bytes += header.free_space() as u64;
+        for item_id in item_ids(page, header) {
+            match item_id.status {
+                ItemStatus::Normal => {
+                    let start = item_id.offset as usize;
+                    let entry = &page[..start + item_id.length as usize];
+                    let deleted =
+                        parse_tuple_header(entry, start).is_ok_and(|(header, _)| header.xmax != 0);
+                    if deleted {
+                        self.dead_tuples += 1;
+                        continue;
+                    }
+                    self.live_tuples += 1;
+                    self.live_bytes += item_id.length as u64;
+                }
+                ItemStatus::Dead => self.dead_tuples += 1,
+                ItemStatus::Unused | ItemStatus::Redirect => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColumnDefinition, DataType, Storage, StorageError, TupleId};
+
+    #[test]
+    fn test_analyze_counts_rows_and_free_space() {
+        let base_dir = crate::tests::temp_base_dir("analyze");
+        let storage = Storage::new(&base_dir);
+        let column = |name: &str, data_type: &str| ColumnDefinition {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable: true,
+            is_primary_key: false,
+            default: None,
+        };
+        let table_id = storage
+            .create_table(
+                "readings",
+                &[column("id", "INTEGER"), column("label", "VARCHAR")],
+            )
+            .unwrap();
+        assert_eq!(storage.analyze(table_id).unwrap(), TableStats::default());
+        assert_eq!(TableStats::default().average_tuple_size(), None);
+
+        let tuple_ids: Vec<TupleId> = (0..200)
+            .map(|id| {
+                storage
+                    .insert_row(
+                        table_id,
+                        &[
+                            Some(DataType::Integer32(id)),
+                            Some(DataType::String(format!("{:0100}", id))),
+                        ],
+                    )
+                    .unwrap()
+            })
+            .collect();
+        let row_pages = tuple_ids.last().unwrap().page_no + 1;
+        assert!(row_pages >= 3);
+        // The first row of each page lies at the top of its data area, so
+        // deleting it leaves a dead slot.
+        let firsts: Vec<TupleId> = tuple_ids
+            .iter()
+            .copied()
+            .filter(|tuple_id| tuple_id.slot == 0)
+            .collect();
+        let heap_path = storage.heap_path(table_id);
+        for tuple_id in &firsts {
+            storage.delete_tuple(&heap_path, *tuple_id).unwrap();
+        }
+        storage
+            .insert_row(
+                table_id,
+                &[
+                    Some(DataType::Integer32(200)),
+                    Some(DataType::String("y".repeat(10_000))),
+                ],
+            )
+            .unwrap();
+
+        let stats = storage.analyze(table_id).unwrap();
+        // A tuple is a 2-byte field count, a null bitmap byte, a tagged
+        // i32 and a tagged string: 3 + 5 + 3 + 100 bytes.
+        let tuple_size = 111;
+        let external_size = 3 + 5 + 9;
+        assert_eq!(stats.page_count, row_pages + 2);
+        assert_eq!(stats.overflow_page_count, 2);
+        let live_tuples = 201 - firsts.len() as u64;
+        let live_bytes = (live_tuples - 1) * tuple_size + external_size;
+        assert_eq!(stats.live_tuples, live_tuples);
+        assert_eq!(stats.dead_tuples, firsts.len() as u64);
+        assert_eq!(stats.live_bytes, live_bytes);
+        assert_eq!(
+            stats.average_tuple_size(),
+            Some(live_bytes as f64 / live_tuples as f64)
+        );
+
+        // The free space is what the pages' headers say is left.
+        let expected_free: u64 = (0..stats.page_count)
+            .map(|page_no| storage.dump_page(&heap_path, page_no).unwrap())
+            .filter(|info| !info.header.is_overflow())
+            .map(|info| info.free_space as u64)
+            .sum();
+        assert_eq!(stats.free_bytes, expected_free);
+
+        storage.vacuum(&heap_path).unwrap();
+        let vacuumed = storage.analyze(table_id).unwrap();
+        assert_eq!(vacuumed.dead_tuples, 0);
+        assert_eq!(vacuumed.live_tuples, stats.live_tuples);
+        assert_eq!(
+            vacuumed.free_bytes,
+            stats.free_bytes + firsts.len() as u64 * tuple_size
+        );
+
+        assert!(matches!(
+            storage.analyze(table_id + 100),
+            Err(StorageError::NotFound(_))
+        ));
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
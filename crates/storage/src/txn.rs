@@ -0,0 +1,581 @@
+use std::collections::BTreeMap;
+use std::sync::PoisonError;
+use std::thread::{self, ThreadId};
+
+use crate::fsm::FreeSpaceMap;
+use crate::wal::{WalEntry, WalRecord};
+use crate::{
+    remove_if_exists, ColumnDefinition, ColumnMetadata, DataFile, DataType, PageHeader, Result,
+    Storage, TableMetadata, TupleId,
+};
+
+/// Page images by file path and page number.
+type Pages = BTreeMap<String, BTreeMap<u32, Box<[u8]>>>;
+
+/// The writes of the open transaction, kept from the files until it commits.
+pub(crate) struct TxnState {
+    pages: Pages,
+    /// Files the transaction created, removed again if it rolls back.
+    pub(crate) created_files: Vec<String>,
+    /// The thread running a call made through the transaction, whose page
+    /// reads and writes go to `pages`.
+    scope: Option<ThreadId>,
+}
+
+impl TxnState {
+    pub(crate) fn new() -> TxnState {
+        TxnState {
+            pages: Pages::new(),
+            created_files: Vec::new(),
+            scope: None,
+        }
+    }
+
+    pub(crate) fn in_scope(&self) -> bool {
+        self.scope == Some(thread::current().id())
+    }
+
+    pub(crate) fn page(&self, file_path: &str, page_no: u32) -> Option<Box<[u8]>> {
+        self.pages.get(file_path)?.get(&page_no).cloned()
+    }
+
+    pub(crate) fn put_page(&mut self, file_path: &str, page_no: u32, page: &[u8]) {
+        self.pages
+            .entry(file_path.to_string())
+            .or_default()
+            .insert(page_no, page.into());
+    }
+
+    /// Number of pages the file has counting those the transaction added.
+    pub(crate) fn page_count(&self, file_path: &str) -> u32 {
+        self.pages
+            .get(file_path)
+            .and_then(|pages| pages.keys().next_back())
+            .map_or(0, |page_no| page_no + 1)
+    }
+}
+
+/// Makes the calling thread's calls go through the open transaction until
+/// dropped.
+struct Scope<'a>(&'a Storage);
+
+impl<'a> Scope<'a> {
+    fn enter(storage: &'a Storage) -> Scope<'a> {
+        if let Some(txn) = storage.txn_state().as_mut() {
+            txn.scope = Some(thread::current().id());
+        }
+        Scope(storage)
+    }
+}
+
+impl Drop for Scope<'_> {
+    fn drop(&mut self) {
+        if let Some(txn) = self.0.txn_state().as_mut() {
+            txn.scope = None;
+        }
+    }
+}
+
+/// A write transaction, from `Storage::begin`. Its methods work like the
+/// Storage methods of the same names, but what they write is seen only by
+/// this transaction's own reads until `commit`. A call that fails part way
+/// may leave some of its writes in the transaction; `rollback` discards them
+/// with the rest.
+///
+/// Files a call creates, such as a table's heap file, are created on disk at
+/// once, empty, and removed again on rollback. Ids handed out by
+/// `next_table_id` and `next_column_id` stay used.
+pub struct Txn<'a> {
+    storage: &'a Storage,
+    id: u64,
+    done: bool,
+}
+
+impl<'a> Txn<'a> {
+    pub(crate) fn new(storage: &'a Storage, id: u64) -> Txn<'a> {
+        Txn {
+            storage,
+            id,
+            done: false,
+        }
+    }
+
+    /// The id the transaction's records carry in the WAL. Ids are unique
+    /// within a Storage and start over with the next one.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn run<T>(&mut self, call: impl FnOnce(&Storage) -> Result<T>) -> Result<T> {
+        let _scope = Scope::enter(self.storage);
+        call(self.storage)
+    }
+
+    pub fn create_table(&mut self, name: &str, columns: &[ColumnDefinition]) -> Result<u32> {
+        self.run(|storage| storage.create_table(name, columns))
+    }
+
+    pub fn write_postgres_class(&mut self, tables_metadata: &[TableMetadata]) -> Result<()> {
+        self.run(|storage| storage.write_postgres_class(tables_metadata))
+    }
+
+    pub fn write_postgres_attribute(&mut self, columns_metadata: &[ColumnMetadata]) -> Result<()> {
+        self.run(|storage| storage.write_postgres_attribute(columns_metadata))
+    }
+
+    pub fn insert_row(&mut self, table_id: u32, values: &[Option<DataType>]) -> Result<TupleId> {
+        self.run(|storage| storage.insert_row(table_id, values))
+    }
+
+    pub fn delete_tuple(&mut self, file_path: &str, tuple_id: TupleId) -> Result<()> {
+        self.run(|storage| storage.delete_tuple(file_path, tuple_id))
+    }
+
+    pub fn read_postgres_class(&mut self) -> Result<Vec<TableMetadata>> {
+        self.run(|storage| storage.read_postgres_class())
+    }
+
+    pub fn read_postgres_attribute(&mut self) -> Result<Vec<ColumnMetadata>> {
+        self.run(|storage| storage.read_postgres_attribute())
+    }
+
+    pub fn get_table_by_name(&mut self, name: &str) -> Result<Option<TableMetadata>> {
+        self.run(|storage| storage.get_table_by_name(name))
+    }
+
+    pub fn get_columns_for_table(&mut self, table_id: u32) -> Result<Vec<ColumnMetadata>> {
+        self.run(|storage| storage.get_columns_for_table(table_id))
+    }
+
+    pub fn read_tuples(&mut self, file_path: &str) -> Result<Vec<Vec<Option<DataType>>>> {
+        self.run(|storage| storage.read_tuples(file_path))
+    }
+
+    /// Makes the transaction's writes durable and visible to every read.
+    /// With a WAL, the pages are logged with the transaction's id, then a
+    /// commit record, and the log is synced whatever the sync mode; only then
+    /// are the pages written into the buffer pool or the files. If the
+    /// commit fails before the log is synced, its records are dropped from
+    /// the log and the transaction is rolled back.
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        let storage = self.storage;
+        let scope = Scope::enter(storage);
+        let mut pages = storage
+            .txn_state()
+            .as_mut()
+            .map(|txn| std::mem::take(&mut txn.pages))
+            .unwrap_or_default();
+        // The files are locked before the pool, as every other write does.
+        let paths: Vec<String> = pages.keys().cloned().collect();
+        let files = paths
+            .iter()
+            .map(|path| storage.open_writable_data_file(path))
+            .collect::<Result<Vec<DataFile>>>();
+        let mut pool = storage.buffer_pool();
+        let logged = files.and_then(|files| {
+            self.log_commit(&mut pages)?;
+            Ok(files)
+        });
+        let mut files = match logged {
+            Ok(files) => files,
+            Err(err) => {
+                drop(pool);
+                drop(scope);
+                self.discard()?;
+                return Err(err);
+            }
+        };
+        let written = (|| {
+            for (file, file_pages) in files.iter_mut().zip(pages.into_values()) {
+                for (page_no, mut page) in file_pages {
+                    storage.store_page(&mut pool, file, page_no, &mut page)?;
+                }
+            }
+            Ok(())
+        })();
+        drop(pool);
+        drop(files);
+        drop(scope);
+        storage.txn_state().take();
+        written
+    }
+
+    /// Stamps the pages with the LSNs of their records and logs them with
+    /// the commit record. A failure truncates the log back to where the
+    /// transaction's records began.
+    fn log_commit(&self, pages: &mut Pages) -> Result<()> {
+        let Some(wal) = &self.storage.wal else {
+            return Ok(());
+        };
+        let mut wal = wal.lock().unwrap_or_else(PoisonError::into_inner);
+        let start = wal.next_lsn();
+        let result = (|| {
+            for (file_path, file_pages) in pages.iter_mut() {
+                for (page_no, page) in file_pages.iter_mut() {
+                    let mut header = PageHeader::decode(page);
+                    header.lsn = wal.next_lsn();
+                    header.write_to(page);
+                    wal.append_entry(&WalEntry::TxnPage {
+                        txn_id: self.id,
+                        record: WalRecord {
+                            file_path: file_path.clone(),
+                            page_no: *page_no,
+                            page: page.clone(),
+                        },
+                    })?;
+                }
+            }
+            wal.append_entry(&WalEntry::Commit { txn_id: self.id })?;
+            wal.sync()
+        })();
+        if result.is_err() {
+            wal.truncate(start)?;
+        }
+        result
+    }
+
+    /// Discards the transaction's writes and removes the files it created.
+    pub fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        self.discard()
+    }
+
+    fn discard(&self) -> Result<()> {
+        let Some(txn) = self.storage.txn_state().take() else {
+            return Ok(());
+        };
+        for file_path in txn.created_files.iter().rev() {
+            self.storage.buffer_pool().discard_file(file_path);
+            remove_if_exists(file_path)?;
+            remove_if_exists(&FreeSpaceMap::path_for(file_path))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Txn<'_> {
+    /// Rolls back a transaction that was neither committed nor rolled back.
+    /// Errors are ignored here; call `rollback` to see them.
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.discard();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PageInit, StorageError, PAGE_SIZE};
+    use std::path::Path;
+
+    fn column() -> ColumnDefinition {
+        ColumnDefinition {
+            name: "id".to_string(),
+            data_type: "INTEGER".to_string(),
+            is_nullable: false,
+            is_primary_key: false,
+            default: None,
+        }
+    }
+
+    fn row(id: i32) -> Vec<Option<DataType>> {
+        vec![Some(DataType::Integer32(id))]
+    }
+
+    #[test]
+    fn test_rollback_discards_writes() {
+        let base_dir = crate::tests::temp_base_dir("txn_rollback");
+        let storage = Storage::new(&base_dir);
+
+        // A transaction on an empty base directory leaves nothing behind.
+        let mut txn = storage.begin().unwrap();
+        let table_id = txn.create_table("draft", &[column()]).unwrap();
+        txn.insert_row(table_id, &row(1)).unwrap();
+        assert_eq!(
+            txn.get_table_by_name("draft").unwrap().unwrap().table_id,
+            table_id
+        );
+        assert_eq!(txn.get_columns_for_table(table_id).unwrap().len(), 1);
+        assert_eq!(storage.get_table_by_name("draft").unwrap(), None);
+        assert!(matches!(
+            storage.begin(),
+            Err(StorageError::TransactionInProgress)
+        ));
+        assert!(matches!(
+            storage.create_table("other", &[column()]),
+            Err(StorageError::TransactionInProgress)
+        ));
+        txn.rollback().unwrap();
+        assert!(!Path::new(storage.pg_class_path()).exists());
+        assert!(!Path::new(&storage.heap_path(table_id)).exists());
+        assert_eq!(storage.get_table_by_name("draft").unwrap(), None);
+
+        // Over committed data, a rollback leaves exactly that, and so does
+        // dropping the transaction.
+        let kept = storage.create_table("kept", &[column()]).unwrap();
+        storage.insert_row(kept, &row(1)).unwrap();
+        let heap_path = storage.heap_path(kept);
+        let mut txn = storage.begin().unwrap();
+        txn.insert_row(kept, &row(2)).unwrap();
+        txn.write_postgres_class(&[TableMetadata {
+            table_id: 99,
+            table_name: "manual".to_string(),
+        }])
+        .unwrap();
+        assert_eq!(txn.read_tuples(&heap_path).unwrap(), [row(1), row(2)]);
+        assert_eq!(txn.read_postgres_class().unwrap().len(), 2);
+        assert_eq!(storage.read_tuples(&heap_path).unwrap(), [row(1)]);
+        drop(txn);
+        assert_eq!(storage.read_tuples(&heap_path).unwrap(), [row(1)]);
+        assert_eq!(storage.read_postgres_class().unwrap().len(), 1);
+        assert_eq!(storage.get_columns_for_table(kept).unwrap().len(), 1);
+        storage.insert_row(kept, &row(3)).unwrap();
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_commit_survives_restart() {
+        let base_dir = crate::tests::temp_base_dir("txn_commit");
+        let wal_path = crate::tests::temp_path("txn_commit_wal");
+        let _ = std::fs::remove_file(&wal_path);
+        let open = || {
+            Storage::new(&base_dir)
+                .with_buffer_pool_capacity(64)
+                .with_wal(&wal_path)
+                .unwrap()
+        };
+
+        // The committed pages are only logged and cached: forgetting the
+        // Storage skips the flush in Drop, as a crash would.
+        let storage = open();
+        let mut txn = storage.begin().unwrap();
+        let table_id = txn.create_table("events", &[column()]).unwrap();
+        for id in 0..100 {
+            txn.insert_row(table_id, &row(id)).unwrap();
+        }
+        txn.commit().unwrap();
+        let heap_path = storage.heap_path(table_id);
+        assert_eq!(storage.read_tuples(&heap_path).unwrap().len(), 100);
+        std::mem::forget(storage);
+
+        // After recovery, a transaction still open at the next crash logged
+        // nothing.
+        let storage = open();
+        assert!(storage.recover(&wal_path).unwrap() >= 3);
+        let mut txn = storage.begin().unwrap();
+        txn.insert_row(table_id, &row(100)).unwrap();
+        std::mem::forget(txn);
+        std::mem::forget(storage);
+
+        // A commit cut off before its commit record is not applied, and is
+        // dropped from the log.
+        let wal_len = std::fs::metadata(&wal_path).unwrap().len();
+        let mut wal = crate::Wal::open(&wal_path).unwrap();
+        wal.append_entry(&WalEntry::TxnPage {
+            txn_id: 7,
+            record: WalRecord {
+                file_path: heap_path.clone(),
+                page_no: 0,
+                page: PageInit::default().build(PAGE_SIZE),
+            },
+        })
+        .unwrap();
+        drop(wal);
+
+        let storage = Storage::new(&base_dir);
+        assert_eq!(storage.recover(&wal_path).unwrap(), 0);
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), wal_len);
+        assert_eq!(
+            storage
+                .get_table_by_name("events")
+                .unwrap()
+                .unwrap()
+                .table_id,
+            table_id
+        );
+        assert_eq!(storage.get_columns_for_table(table_id).unwrap().len(), 1);
+        let rows: Vec<Vec<Option<DataType>>> = (0..100).map(row).collect();
+        assert_eq!(storage.read_tuples(&heap_path).unwrap(), rows);
+        drop(storage);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_drop_table_is_logged_as_one_transaction() {
+        let base_dir = crate::tests::temp_base_dir("txn_drop");
+        let wal_path = crate::tests::temp_path("txn_drop_wal");
+        let _ = std::fs::remove_file(&wal_path);
+        let storage = Storage::new(&base_dir).with_wal(&wal_path).unwrap();
+        let kept_id = storage.create_table("kept", &[column()]).unwrap();
+        let table_id = storage.create_table("events", &[column()]).unwrap();
+        let logged_before = crate::Wal::open(&wal_path)
+            .unwrap()
+            .entries()
+            .unwrap()
+            .count();
+        storage.drop_table(table_id).unwrap();
+
+        // Both catalog files' pages are logged under one transaction, which
+        // ends in its commit record.
+        let entries: Vec<WalEntry> = crate::Wal::open(&wal_path)
+            .unwrap()
+            .entries()
+            .unwrap()
+            .skip(logged_before)
+            .map(|entry| entry.unwrap().1)
+            .filter(|entry| {
+                entry.record().is_none_or(|record| {
+                    record.file_path == storage.pg_class_path()
+                        || record.file_path == storage.pg_attribute_path()
+                })
+            })
+            .collect();
+        let Some((WalEntry::Commit { txn_id }, pages)) = entries.split_last() else {
+            panic!("the drop does not commit: {:?}", entries.last());
+        };
+        for file_path in [storage.pg_class_path(), storage.pg_attribute_path()] {
+            assert!(pages.iter().any(|entry| matches!(
+                entry,
+                WalEntry::TxnPage { txn_id: id, record } if id == txn_id && record.file_path == file_path
+            )));
+        }
+        assert!(pages
+            .iter()
+            .all(|entry| matches!(entry, WalEntry::TxnPage { txn_id: id, .. } if id == txn_id)));
+        assert!(storage.get_table_by_id(table_id).unwrap().is_none());
+        assert_eq!(storage.get_columns_for_table(table_id).unwrap(), []);
+        assert_eq!(storage.get_columns_for_table(kept_id).unwrap().len(), 1);
+        drop(storage);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_is_logged_as_one_transaction() {
+        let base_dir = crate::tests::temp_base_dir("txn_rewrite");
+        let wal_path = crate::tests::temp_path("txn_rewrite_wal");
+        let _ = std::fs::remove_file(&wal_path);
+        let storage = Storage::new(&base_dir)
+            .with_buffer_pool_capacity(64)
+            .with_wal(&wal_path)
+            .unwrap();
+        let table_id = storage.create_table("events", &[column()]).unwrap();
+        let mut columns = storage.get_columns_for_table(table_id).unwrap();
+        for column_id in 100..700 {
+            columns.push(ColumnMetadata {
+                column_id,
+                column_name: format!("column_{}", column_id),
+                ..columns[0].clone()
+            });
+        }
+        let logged_before = crate::Wal::open(&wal_path)
+            .unwrap()
+            .entries()
+            .unwrap()
+            .count();
+        storage.replace_postgres_attribute(&columns).unwrap();
+
+        // Every page of the rewrite is logged under one transaction, ending
+        // in its commit record.
+        let entries: Vec<WalEntry> = crate::Wal::open(&wal_path)
+            .unwrap()
+            .entries()
+            .unwrap()
+            .skip(logged_before)
+            .map(|entry| entry.unwrap().1)
+            .filter(|entry| {
+                entry
+                    .record()
+                    .is_none_or(|record| record.file_path == storage.pg_attribute_path())
+            })
+            .collect();
+        let Some((WalEntry::Commit { txn_id }, pages)) = entries.split_last() else {
+            panic!("the rewrite does not commit: {:?}", entries.last());
+        };
+        assert!(pages.len() >= 2);
+        assert!(pages
+            .iter()
+            .all(|entry| matches!(entry, WalEntry::TxnPage { txn_id: id, .. } if id == txn_id)));
+        assert_eq!(storage.get_columns_for_table(table_id).unwrap(), columns);
+        drop(storage);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_moved_catalog_entry_is_logged_as_one_transaction() {
+        let base_dir = crate::tests::temp_base_dir("txn_moved_entry");
+        let wal_path = crate::tests::temp_path("txn_moved_entry_wal");
+        let _ = std::fs::remove_file(&wal_path);
+        let open = || {
+            Storage::new(&base_dir)
+                .with_buffer_pool_capacity(64)
+                .with_wal(&wal_path)
+                .unwrap()
+        };
+        let storage = open();
+        let note = ColumnDefinition {
+            name: "note".to_string(),
+            ..column()
+        };
+        let table_id = storage.create_table("events", &[column(), note]).unwrap();
+        let column_ids: Vec<u32> = storage
+            .get_columns_for_table(table_id)
+            .unwrap()
+            .iter()
+            .map(|column| column.column_id)
+            .collect();
+        // The first name still fits in place; the second leaves the page
+        // without room, so its entry moves to a new page.
+        let names = ["a".repeat(3000), "b".repeat(6000)];
+        storage
+            .rename_column(table_id, column_ids[0], &names[0])
+            .unwrap();
+        storage
+            .rename_column(table_id, column_ids[1], &names[1])
+            .unwrap();
+
+        let entries: Vec<WalEntry> = crate::Wal::open(&wal_path)
+            .unwrap()
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().1)
+            .collect();
+        // The index insert after the move is logged on its own.
+        let txn_id = entries
+            .iter()
+            .find_map(|entry| match entry {
+                WalEntry::Commit { txn_id } => Some(txn_id),
+                _ => None,
+            })
+            .expect("the move commits");
+        let moved_pages: Vec<u32> = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                WalEntry::TxnPage { txn_id: id, record }
+                    if id == txn_id && record.file_path == storage.pg_attribute_path() =>
+                {
+                    Some(record.page_no)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(moved_pages, [0, 1]);
+
+        std::mem::forget(storage);
+        let storage = Storage::new(&base_dir);
+        storage.recover(&wal_path).unwrap();
+        let columns = storage.get_columns_for_table(table_id).unwrap();
+        let stored: Vec<&str> = columns
+            .iter()
+            .map(|column| column.column_name.as_str())
+            .collect();
+        assert_eq!(stored, [names[0].as_str(), names[1].as_str()]);
+        drop(storage);
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+}
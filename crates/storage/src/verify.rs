@@ -0,0 +1,517 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::File;
+
+use crate::page::{item_ids, page_checksum, read_item_id, slot_offset, NO_NEXT_PAGE};
+use crate::{
+    expand_stored_page, parse_caught, parse_postgres_attribute, parse_postgres_class,
+    parse_stored_tuple, read_page_slot, ItemId, PageHeader, Result, StorageError, StoredField,
+    TupleId,
+};
+
+/// What `Storage::verify_file` found wrong with a file. No problems means
+/// every check passed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    /// Complete pages in the file.
+    pub page_count: u32,
+    pub problems: Vec<VerifyProblem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyProblem {
+    /// The page at fault, or `None` for a problem with the file as a whole.
+    pub page_no: Option<u32>,
+    /// Byte offset into the file of what is wrong: the start of the page for
+    /// a bad header, the slot's item id for a bad slot, or the entry for an
+    /// entry that does not decode.
+    pub offset: u64,
+    pub reason: String,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.page_no {
+            Some(page_no) => write!(
+                f,
+                "page {} offset {}: {}",
+                page_no, self.offset, self.reason
+            ),
+            None => write!(f, "offset {}: {}", self.offset, self.reason),
+        }
+    }
+}
+
+/// How the entries of a file are laid out, so they can be decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum EntryFormat {
+    PgClass,
+    PgAttribute,
+    /// Tuples of a heap file, which may refer to overflow chains.
+    Heap,
+    /// Entries whose layout is not known; only the pages are checked.
+    Opaque,
+}
+
+pub(crate) fn verify(
+    file: &mut File,
+    page_size: usize,
+    format: EntryFormat,
+) -> Result<VerifyReport> {
+    let file_len = file.metadata()?.len();
+    let page_count = (file_len / page_size as u64) as u32;
+    let mut verifier = Verifier {
+        file,
+        page_size,
+        page_count,
+        format,
+        problems: Vec::new(),
+    };
+    for page_no in 0..page_count {
+        let mut page = read_page_slot(verifier.file, page_no, page_size)?;
+        match expand_stored_page(&mut page, page_no) {
+            Ok(()) => verifier.check_page(page_no, &page)?,
+            Err(StorageError::CorruptPage { reason, .. }) => {
+                let start = page_no as u64 * page_size as u64;
+                verifier.report(page_no, start, reason);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let partial = file_len % page_size as u64;
+    if partial != 0 {
+        verifier.problems.push(VerifyProblem {
+            page_no: None,
+            offset: file_len - partial,
+            reason: format!(
+                "file ends in a partial page of {} bytes; pages are {} bytes",
+                partial, page_size
+            ),
+        });
+    }
+    Ok(VerifyReport {
+        page_count,
+        problems: verifier.problems,
+    })
+}
+
+struct Verifier<'f> {
+    file: &'f mut File,
+    page_size: usize,
+    page_count: u32,
+    format: EntryFormat,
+    problems: Vec<VerifyProblem>,
+}
+
+impl Verifier<'_> {
+    fn report(&mut self, page_no: u32, offset: u64, reason: String) {
+        self.problems.push(VerifyProblem {
+            page_no: Some(page_no),
+            offset,
+            reason,
+        });
+    }
+
+    /// Checks the page and its entries. A header that does not hold up ends
+    /// the checks of the page, since its slots cannot be trusted.
+    fn check_page(&mut self, page_no: u32, page: &[u8]) -> Result<()> {
+        let start = page_no as u64 * self.page_size as u64;
+        let header = PageHeader::decode(page);
+        if let Err(err) = header.check_format() {
+            self.report(page_no, start, err.to_string());
+            return Ok(());
+        }
+        if header.page_size as usize != page.len() {
+            self.report(
+                page_no,
+                start,
+                format!(
+                    "header gives a page size of {}, but the file has {}-byte pages",
+                    header.page_size,
+                    page.len()
+                ),
+            );
+            return Ok(());
+        }
+        let computed = page_checksum(page);
+        if header.has_checksum() && header.checksum != computed {
+            self.report(
+                page_no,
+                start,
+                format!(
+                    "checksum mismatch: stored {:#06x}, computed {:#06x}",
+                    header.checksum, computed
+                ),
+            );
+        }
+        if let Err(reason) = header.check_invariants() {
+            self.report(page_no, start, reason);
+            return Ok(());
+        }
+
+        let mut stored: Vec<(u16, ItemId)> = Vec::new();
+        for (slot, item_id) in item_ids(page, &header).into_iter().enumerate() {
+            let slot = slot as u16;
+            match header.check_item_id(&item_id) {
+                Err(reason) => self.report(
+                    page_no,
+                    start + slot_offset(slot) as u64,
+                    format!("slot {}: {}", slot, reason),
+                ),
+                Ok(()) if item_id.has_storage() && item_id.length > 0 => {
+                    stored.push((slot, item_id))
+                }
+                Ok(()) => {}
+            }
+        }
+        if header.is_overflow() {
+            if header.slot_count() != 1 || !read_item_id(page, 0).is_normal() {
+                self.report(
+                    page_no,
+                    start,
+                    "overflow page does not hold exactly one chunk".to_string(),
+                );
+            }
+            return Ok(());
+        }
+
+        let mut by_offset = stored.clone();
+        by_offset.sort_by_key(|(_, item_id)| item_id.offset);
+        for pair in by_offset.windows(2) {
+            let ((first_slot, first), (second_slot, second)) = (pair[0], pair[1]);
+            if first.offset + first.length > second.offset {
+                self.report(
+                    page_no,
+                    start + second.offset as u64,
+                    format!("slots {} and {} overlap", first_slot, second_slot),
+                );
+            }
+        }
+
+        for (slot, item_id) in stored {
+            if !item_id.is_normal() {
+                continue;
+            }
+            let offset = start + item_id.offset as u64;
+            let tuple_id = TupleId { page_no, slot };
+            let decoded = match self.format {
+                EntryFormat::PgClass => {
+                    parse_caught(&parse_postgres_class, page, tuple_id, item_id).map(|_| ())
+                }
+                EntryFormat::PgAttribute => {
+                    parse_caught(&parse_postgres_attribute, page, tuple_id, item_id).map(|_| ())
+                }
+                EntryFormat::Heap => {
+                    match parse_caught(&parse_stored_tuple, page, tuple_id, item_id) {
+                        Ok(fields) => {
+                            for field in fields.into_iter().flatten() {
+                                if let StoredField::External {
+                                    length,
+                                    first_page_no,
+                                } = field
+                                {
+                                    self.check_chain(tuple_id, offset, length, first_page_no)?;
+                                }
+                            }
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+                EntryFormat::Opaque => Ok(()),
+            };
+            if let Err(err) = decoded {
+                let reason = match err {
+                    StorageError::CorruptPage { reason, .. } => reason,
+                    err => err.to_string(),
+                };
+                self.report(page_no, offset, format!("slot {}: {}", slot, reason));
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the overflow chain of a field, reporting at the entry that
+    /// refers to it if the chain leaves the file, runs through a page that is
+    /// not an overflow page, comes back to a page it has passed, or does not
+    /// hold `length` bytes.
+    fn check_chain(
+        &mut self,
+        tuple_id: TupleId,
+        offset: u64,
+        length: u32,
+        first_page_no: u32,
+    ) -> Result<()> {
+        let mut visited = BTreeSet::new();
+        let mut total = 0u64;
+        let mut page_no = first_page_no;
+        let problem = loop {
+            if page_no == NO_NEXT_PAGE {
+                break (total != length as u64).then(|| {
+                    format!(
+                        "overflow chain holds {} bytes for a {}-byte value",
+                        total, length
+                    )
+                });
+            }
+            if page_no >= self.page_count {
+                break Some(format!(
+                    "overflow chain runs past the end of the file to page {}",
+                    page_no
+                ));
+            }
+            if !visited.insert(page_no) {
+                break Some(format!("overflow chain loops back to page {}", page_no));
+            }
+            let mut page = read_page_slot(self.file, page_no, self.page_size)?;
+            match expand_stored_page(&mut page, page_no) {
+                Ok(()) => {}
+                Err(StorageError::CorruptPage { reason, .. }) => {
+                    break Some(format!(
+                        "overflow chain runs through page {}, which does not read: {}",
+                        page_no, reason
+                    ))
+                }
+                Err(err) => return Err(err),
+            }
+            let header = PageHeader::decode(&page);
+            let item_id = read_item_id(&page, 0);
+            let holds_chunk = header.check_format().is_ok()
+                && header.page_size as usize == page.len()
+                && header.check_invariants().is_ok()
+                && header.is_overflow()
+                && header.slot_count() == 1
+                && item_id.is_normal()
+                && header.check_item_id(&item_id).is_ok();
+            let next = page
+                .get(header.special_space as usize..)
+                .and_then(|special| special.get(..4));
+            match next {
+                Some(next) if holds_chunk => {
+                    total += item_id.length as u64;
+                    page_no = u32::from_le_bytes(next.try_into().unwrap());
+                }
+                _ => {
+                    break Some(format!(
+                        "overflow chain runs through page {}, which is not an overflow page",
+                        page_no
+                    ))
+                }
+            }
+        };
+        if let Some(reason) = problem {
+            self.report(
+                tuple_id.page_no,
+                offset,
+                format!("slot {}: {}", tuple_id.slot, reason),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::{write_item_id, PAGE_FLAG_COMPRESSED, PAGE_HEADER_SIZE};
+    use crate::{ColumnDefinition, DataType, Storage, PAGE_SIZE};
+
+    /// Rewrites page `page_no` of the file through `corrupt`, restamping the
+    /// checksum so that only the damage done is reported.
+    fn corrupt_page(file_path: &str, page_no: u32, corrupt: impl FnOnce(&mut [u8])) {
+        let mut bytes = std::fs::read(file_path).unwrap();
+        let page = &mut bytes[page_no as usize * PAGE_SIZE..][..PAGE_SIZE];
+        corrupt(page);
+        let mut header = PageHeader::decode(page);
+        header.checksum = page_checksum(page);
+        header.write_to(page);
+        std::fs::write(file_path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_verify_file_reports_corruption() {
+        let base_dir = crate::tests::temp_base_dir("verify_file");
+        let storage = Storage::new(&base_dir);
+        let column = |name: &str, data_type: &str| ColumnDefinition {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable: true,
+            is_primary_key: false,
+            default: None,
+        };
+        let table_id = storage
+            .create_table(
+                "notes",
+                &[column("id", "INTEGER"), column("body", "VARCHAR")],
+            )
+            .unwrap();
+        let row =
+            |id: i32, body: String| [Some(DataType::Integer32(id)), Some(DataType::String(body))];
+        let short = storage
+            .insert_row(table_id, &row(0, "short".to_string()))
+            .unwrap();
+        let long = storage
+            .insert_row(table_id, &row(1, "x".repeat(20_000)))
+            .unwrap();
+        storage
+            .insert_row(table_id, &row(2, "also short".to_string()))
+            .unwrap();
+        let heap_path = storage.heap_path(table_id);
+        let pg_class = storage.pg_class_path().to_string();
+        for file_path in [
+            &heap_path,
+            &pg_class,
+            &storage.pg_attribute_path().to_string(),
+        ] {
+            let report = storage.verify_file(file_path).unwrap();
+            assert!(report.is_ok(), "{}: {:?}", file_path, report.problems);
+        }
+        let heap_report = storage.verify_file(&heap_path).unwrap();
+        assert_eq!(heap_report.page_count, 4);
+        let original = std::fs::read(&heap_path).unwrap();
+        let restore = || std::fs::write(&heap_path, &original).unwrap();
+        let entry_offset = |tuple_id: TupleId| {
+            let page = &original[tuple_id.page_no as usize * PAGE_SIZE..][..PAGE_SIZE];
+            (tuple_id.page_no as usize * PAGE_SIZE
+                + read_item_id(page, tuple_id.slot).offset as usize) as u64
+        };
+
+        // A trailing partial page.
+        let mut bytes = original.clone();
+        bytes.extend_from_slice(&[0; 100]);
+        std::fs::write(&heap_path, bytes).unwrap();
+        let report = storage.verify_file(&heap_path).unwrap();
+        assert_eq!(report.page_count, 4);
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].page_no, None);
+        assert_eq!(report.problems[0].offset, 4 * PAGE_SIZE as u64);
+        restore();
+
+        // A flipped bit the checksum catches, reported at the page's start.
+        let mut bytes = original.clone();
+        bytes[2 * PAGE_SIZE + 5000] ^= 0x10;
+        std::fs::write(&heap_path, bytes).unwrap();
+        let report = storage.verify_file(&heap_path).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].page_no, Some(2));
+        assert_eq!(report.problems[0].offset, 2 * PAGE_SIZE as u64);
+        assert!(report.problems[0].reason.starts_with("checksum mismatch"));
+        restore();
+
+        // A slot pointing into the header and two slots sharing an entry are
+        // both reported, each where it lies.
+        corrupt_page(&heap_path, long.page_no, |page| {
+            write_item_id(page, short.slot, ItemId::normal(10, 4));
+            let item_id = read_item_id(page, 2);
+            write_item_id(page, long.slot, item_id);
+        });
+        let report = storage.verify_file(&heap_path).unwrap();
+        let problems: Vec<_> = report
+            .problems
+            .iter()
+            .map(|problem| (problem.page_no, problem.offset))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                (Some(0), slot_offset(short.slot) as u64),
+                (
+                    Some(0),
+                    entry_offset(TupleId {
+                        page_no: 0,
+                        slot: 2
+                    })
+                ),
+            ]
+        );
+        assert!(report.problems[1].reason.contains("overlap"));
+        restore();
+
+        // Header invariants that do not hold.
+        corrupt_page(&heap_path, 0, |page| {
+            let mut header = PageHeader::decode(page);
+            header.lower = header.higher + 1;
+            header.write_to(page);
+        });
+        let report = storage.verify_file(&heap_path).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].offset, 0);
+        restore();
+
+        // An overflow chain whose last page points back to its first.
+        let first_overflow_page = 1u32;
+        corrupt_page(&heap_path, 3, |page| {
+            let header = PageHeader::decode(page);
+            let special_space = header.special_space as usize;
+            page[special_space..special_space + 4]
+                .copy_from_slice(&first_overflow_page.to_le_bytes());
+        });
+        let report = storage.verify_file(&heap_path).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].offset, entry_offset(long));
+        assert!(report.problems[0]
+            .reason
+            .ends_with("overflow chain loops back to page 1"));
+        restore();
+
+        // A page flagged compressed that does not expand, in builds with or
+        // without the compression feature. The chain through it is reported
+        // too, and the checks carry on.
+        corrupt_page(&heap_path, 2, |page| {
+            let mut header = PageHeader::decode(page);
+            header.flags |= PAGE_FLAG_COMPRESSED as u16;
+            header.write_to(page);
+            page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        });
+        let report = storage.verify_file(&heap_path).unwrap();
+        let problems: Vec<_> = report
+            .problems
+            .iter()
+            .map(|problem| (problem.page_no, problem.offset))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                (Some(0), entry_offset(long)),
+                (Some(2), 2 * PAGE_SIZE as u64)
+            ]
+        );
+        assert!(report.problems[0]
+            .reason
+            .contains("overflow chain runs through page 2, which does not read"));
+        let reader = Storage::new(&base_dir);
+        let info = reader.dump_page(&heap_path, 2).unwrap();
+        assert_eq!(info.anomalies[0], report.problems[1].reason);
+        drop(reader);
+        restore();
+
+        // A string length prefix running past the end of its catalog entry.
+        let original_class = std::fs::read(&pg_class).unwrap();
+        corrupt_page(&pg_class, 0, |page| {
+            let item_id = read_item_id(page, 0);
+            let name_length = item_id.offset as usize + 4;
+            page[name_length..name_length + 2].copy_from_slice(&500u16.to_le_bytes());
+        });
+        let report = storage.verify_file(&pg_class).unwrap();
+        let class_entry = read_item_id(&original_class, 0).offset as u64;
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].offset, class_entry);
+        assert_eq!(
+            report.problems[0].to_string(),
+            format!(
+                "page 0 offset {}: slot 0: Corrupt value: truncated table name value",
+                class_entry
+            )
+        );
+        std::fs::write(&pg_class, original_class).unwrap();
+
+        assert!(storage.verify_file(&heap_path).unwrap().is_ok());
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,495 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::page::{crc32c_update, is_valid_page_size, MAX_PAGE_SIZE};
+use crate::{Result, StorageError};
+
+/// Position of a record in the log: the byte offset at which it starts.
+pub type Lsn = u64;
+
+/// Written at the start of every log, followed by the u64 LSN of the log's
+/// first record. A new log's first record gets LSN `WAL_HEADER_SIZE`, so no
+/// record has LSN 0 and a page LSN of 0 means the page has never been logged.
+/// After `reset` the first record continues from the LSNs already handed
+/// out, so page LSNs keep increasing.
+const WAL_MAGIC: &[u8; 8] = b"RSRDWAL2";
+
+const WAL_HEADER_SIZE: usize = 16;
+
+/// Each record is a u32 body length and the CRC-32C of the body, followed by
+/// the body itself. The top bit of the length marks a transaction record,
+/// whose body starts with its kind and the transaction's u64 id. Records
+/// without it are page images written outside any transaction.
+const RECORD_HEADER_SIZE: usize = 8;
+
+const TXN_RECORD_FLAG: u32 = 1 << 31;
+
+const TXN_HEADER_SIZE: usize = 1 + 8;
+
+const TXN_PAGE: u8 = 0;
+
+const TXN_COMMIT: u8 = 1;
+
+/// A full image of a page as it is about to be written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalRecord {
+    pub file_path: String,
+    pub page_no: u32,
+    pub page: Box<[u8]>,
+}
+
+impl WalRecord {
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(2 + self.file_path.len() + 4 + self.page.len());
+        body.extend_from_slice(&(self.file_path.len() as u16).to_le_bytes());
+        body.extend_from_slice(self.file_path.as_bytes());
+        body.extend_from_slice(&self.page_no.to_le_bytes());
+        body.extend_from_slice(&self.page[..]);
+        body
+    }
+
+    fn decode(body: &[u8]) -> std::result::Result<WalRecord, String> {
+        let path_length = u16::from_le_bytes(
+            body.get(0..2)
+                .ok_or("record body too short")?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let page_size = body.len().saturating_sub(2 + path_length + 4);
+        if !is_valid_page_size(page_size) {
+            return Err(format!(
+                "record body of {} bytes does not end in a page image",
+                body.len()
+            ));
+        }
+        let file_path =
+            String::from_utf8(body[2..2 + path_length].to_vec()).map_err(|err| err.to_string())?;
+        let offset = 2 + path_length;
+        let page_no = u32::from_le_bytes(body[offset..offset + 4].try_into().unwrap());
+        Ok(WalRecord {
+            file_path,
+            page_no,
+            page: body[offset + 4..].into(),
+        })
+    }
+}
+
+/// What a record of the log holds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalEntry {
+    /// A page image written outside any transaction.
+    Page(WalRecord),
+    /// A page image written by a transaction, to be applied only if the
+    /// transaction's commit record follows it.
+    TxnPage { txn_id: u64, record: WalRecord },
+    /// The end of a transaction's page images, which makes them durable.
+    Commit { txn_id: u64 },
+}
+
+impl WalEntry {
+    /// The page image the entry carries, if it carries one.
+    pub fn record(&self) -> Option<&WalRecord> {
+        match self {
+            WalEntry::Page(record) | WalEntry::TxnPage { record, .. } => Some(record),
+            WalEntry::Commit { .. } => None,
+        }
+    }
+
+    fn encode(&self) -> (bool, Vec<u8>) {
+        let txn_header = |kind: u8, txn_id: u64| {
+            let mut body = vec![kind];
+            body.extend_from_slice(&txn_id.to_le_bytes());
+            body
+        };
+        match self {
+            WalEntry::Page(record) => (false, record.encode()),
+            WalEntry::TxnPage { txn_id, record } => {
+                let mut body = txn_header(TXN_PAGE, *txn_id);
+                body.extend_from_slice(&record.encode());
+                (true, body)
+            }
+            WalEntry::Commit { txn_id } => (true, txn_header(TXN_COMMIT, *txn_id)),
+        }
+    }
+
+    fn decode(is_txn: bool, body: &[u8]) -> std::result::Result<WalEntry, String> {
+        if !is_txn {
+            return WalRecord::decode(body).map(WalEntry::Page);
+        }
+        if body.len() < TXN_HEADER_SIZE {
+            return Err("transaction record body too short".to_string());
+        }
+        let txn_id = u64::from_le_bytes(body[1..TXN_HEADER_SIZE].try_into().unwrap());
+        match body[0] {
+            TXN_PAGE => Ok(WalEntry::TxnPage {
+                txn_id,
+                record: WalRecord::decode(&body[TXN_HEADER_SIZE..])?,
+            }),
+            TXN_COMMIT if body.len() == TXN_HEADER_SIZE => Ok(WalEntry::Commit { txn_id }),
+            TXN_COMMIT => Err("commit record has trailing bytes".to_string()),
+            kind => Err(format!("unknown transaction record kind {}", kind)),
+        }
+    }
+}
+
+/// An append-only log of page images.
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    first_lsn: Lsn,
+    next_lsn: Lsn,
+}
+
+impl Wal {
+    /// Opens the log, creating it if it does not exist. New records are
+    /// appended after whatever the file already holds.
+    pub fn open(wal_path: impl AsRef<Path>) -> Result<Wal> {
+        let path = wal_path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            file.write_all(&header(WAL_HEADER_SIZE as Lsn))?;
+            return Ok(Wal {
+                path,
+                file,
+                first_lsn: WAL_HEADER_SIZE as Lsn,
+                next_lsn: WAL_HEADER_SIZE as Lsn,
+            });
+        }
+
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        let read = read_full(&mut file, &mut header)?;
+        if read < WAL_HEADER_SIZE || header[..8] != *WAL_MAGIC {
+            return Err(StorageError::CorruptWalRecord {
+                lsn: 0,
+                reason: "missing log header".to_string(),
+            });
+        }
+        let first_lsn = u64::from_le_bytes(header[8..].try_into().unwrap());
+        Ok(Wal {
+            path,
+            file,
+            first_lsn,
+            next_lsn: first_lsn + (len - WAL_HEADER_SIZE as u64),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Drops everything from `lsn` on, such as a record torn by a crash, so
+    /// the next record is appended there.
+    pub fn truncate(&mut self, lsn: Lsn) -> Result<()> {
+        self.file.set_len(self.offset_of(lsn))?;
+        self.next_lsn = lsn;
+        Ok(())
+    }
+
+    /// Drops every record. The next record gets the LSN it would have had
+    /// anyway. The emptied log replaces the old one through a rename, so a
+    /// crash leaves one or the other.
+    pub fn reset(&mut self) -> Result<()> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let mut temp_file = File::create(&temp_path)?;
+        temp_file.write_all(&header(self.next_lsn))?;
+        temp_file.sync_all()?;
+        drop(temp_file);
+        std::fs::rename(&temp_path, &self.path)?;
+        crate::sync_parent_dir(&self.path)?;
+
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.first_lsn = self.next_lsn;
+        Ok(())
+    }
+
+    fn offset_of(&self, lsn: Lsn) -> u64 {
+        WAL_HEADER_SIZE as u64 + (lsn - self.first_lsn)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Waits for the appended records to reach the disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// The LSN the next appended record will get.
+    pub fn next_lsn(&self) -> Lsn {
+        self.next_lsn
+    }
+
+    /// Appends a page image written outside any transaction.
+    pub fn append(&mut self, record: &WalRecord) -> Result<Lsn> {
+        self.append_body(false, &record.encode())
+    }
+
+    pub fn append_entry(&mut self, entry: &WalEntry) -> Result<Lsn> {
+        let (is_txn, body) = entry.encode();
+        self.append_body(is_txn, &body)
+    }
+
+    fn append_body(&mut self, is_txn: bool, body: &[u8]) -> Result<Lsn> {
+        let flag = if is_txn { TXN_RECORD_FLAG } else { 0 };
+        let mut data = Vec::with_capacity(RECORD_HEADER_SIZE + body.len());
+        data.extend_from_slice(&(body.len() as u32 | flag).to_le_bytes());
+        data.extend_from_slice(&(!crc32c_update(!0, body)).to_le_bytes());
+        data.extend_from_slice(body);
+
+        let lsn = self.next_lsn;
+        self.file.seek(SeekFrom::Start(self.offset_of(lsn)))?;
+        self.file.write_all(&data)?;
+        self.next_lsn += data.len() as Lsn;
+        Ok(lsn)
+    }
+
+    /// Reads the page images of the log from the start, those written by
+    /// transactions included. A record that fails its CRC or is cut short
+    /// yields an error and ends the iteration.
+    pub fn records(&self) -> Result<impl Iterator<Item = Result<(Lsn, WalRecord)>>> {
+        Ok(self.entries()?.filter_map(|entry| match entry {
+            Ok((lsn, entry)) => entry.record().cloned().map(|record| Ok((lsn, record))),
+            Err(err) => Some(Err(err)),
+        }))
+    }
+
+    /// Like `records`, but yields every record, commit records included.
+    pub fn entries(&self) -> Result<impl Iterator<Item = Result<(Lsn, WalEntry)>>> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(WAL_HEADER_SIZE as u64))?;
+        Ok(WalRecords {
+            reader,
+            lsn: self.first_lsn,
+            done: false,
+        })
+    }
+}
+
+fn header(first_lsn: Lsn) -> [u8; WAL_HEADER_SIZE] {
+    let mut header = [0u8; WAL_HEADER_SIZE];
+    header[..8].copy_from_slice(WAL_MAGIC);
+    header[8..].copy_from_slice(&first_lsn.to_le_bytes());
+    header
+}
+
+struct WalRecords {
+    reader: BufReader<File>,
+    lsn: Lsn,
+    done: bool,
+}
+
+impl WalRecords {
+    fn read_record(&mut self) -> Result<Option<(Lsn, WalEntry)>> {
+        let lsn = self.lsn;
+        let corrupt = |reason: String| StorageError::CorruptWalRecord { lsn, reason };
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        match read_full(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            RECORD_HEADER_SIZE => {}
+            read => {
+                return Err(corrupt(format!(
+                    "record header cut short after {} bytes",
+                    read
+                )))
+            }
+        }
+        let raw_length = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let is_txn = raw_length & TXN_RECORD_FLAG != 0;
+        let length = (raw_length & !TXN_RECORD_FLAG) as usize;
+        let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if length > TXN_HEADER_SIZE + 2 + u16::MAX as usize + 4 + MAX_PAGE_SIZE {
+            return Err(corrupt(format!("record length {} is too large", length)));
+        }
+
+        let mut body = vec![0u8; length];
+        let read = read_full(&mut self.reader, &mut body)?;
+        if read != length {
+            return Err(corrupt(format!(
+                "record body cut short: {} of {} bytes",
+                read, length
+            )));
+        }
+        let computed = !crc32c_update(!0, &body);
+        if computed != crc {
+            return Err(corrupt(format!(
+                "CRC mismatch: stored {:#010x}, computed {:#010x}",
+                crc, computed
+            )));
+        }
+        let entry = WalEntry::decode(is_txn, &body).map_err(corrupt)?;
+        self.lsn += (RECORD_HEADER_SIZE + length) as Lsn;
+        Ok(Some((lsn, entry)))
+    }
+}
+
+impl Iterator for WalRecords {
+    type Item = Result<(Lsn, WalEntry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_record().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning how many
+/// bytes were read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_path;
+    use crate::PAGE_SIZE;
+
+    fn record(page_no: u32, byte: u8) -> WalRecord {
+        WalRecord {
+            file_path: "base/table".to_string(),
+            page_no,
+            page: vec![byte; PAGE_SIZE].into_boxed_slice(),
+        }
+    }
+
+    #[test]
+    fn test_wal_append_and_read_back() {
+        let wal_path = temp_path("wal_append");
+        let _ = std::fs::remove_file(&wal_path);
+
+        let mut wal = Wal::open(&wal_path).unwrap();
+        let records = [record(0, 1), record(3, 2), record(0, 3)];
+        let lsns: Vec<Lsn> = records
+            .iter()
+            .map(|record| wal.append(record).unwrap())
+            .collect();
+        assert_eq!(lsns[0], WAL_HEADER_SIZE as Lsn);
+        assert!(lsns.windows(2).all(|pair| pair[0] < pair[1]));
+        drop(wal);
+
+        // Reopening continues after the existing records.
+        let mut wal = Wal::open(&wal_path).unwrap();
+        let last = wal.append(&record(1, 4)).unwrap();
+        assert!(last > lsns[2]);
+
+        let read: Vec<(Lsn, WalRecord)> = wal.records().unwrap().map(Result::unwrap).collect();
+        let mut expected: Vec<(Lsn, WalRecord)> = lsns.into_iter().zip(records).collect();
+        expected.push((last, record(1, 4)));
+        assert_eq!(read, expected);
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_transaction_entries() {
+        let wal_path = temp_path("wal_entries");
+        let _ = std::fs::remove_file(&wal_path);
+
+        let mut wal = Wal::open(&wal_path).unwrap();
+        let entries = [
+            WalEntry::Page(record(0, 1)),
+            WalEntry::TxnPage {
+                txn_id: 7,
+                record: record(1, 2),
+            },
+            WalEntry::Commit { txn_id: 7 },
+        ];
+        let lsns: Vec<Lsn> = entries
+            .iter()
+            .map(|entry| wal.append_entry(entry).unwrap())
+            .collect();
+        let read: Vec<(Lsn, WalEntry)> = wal.entries().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read, lsns.iter().copied().zip(entries).collect::<Vec<_>>());
+        // The page images of transactions are among the records, commit
+        // records are not.
+        let read: Vec<(Lsn, WalRecord)> = wal.records().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read, [(lsns[0], record(0, 1)), (lsns[1], record(1, 2))]);
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_stops_at_damaged_record() {
+        let wal_path = temp_path("wal_damaged");
+        let _ = std::fs::remove_file(&wal_path);
+
+        let mut wal = Wal::open(&wal_path).unwrap();
+        wal.append(&record(0, 1)).unwrap();
+        let second = wal.append(&record(1, 2)).unwrap();
+        drop(wal);
+
+        let mut bytes = std::fs::read(&wal_path).unwrap();
+        bytes[second as usize + 100] ^= 0xFF;
+        std::fs::write(&wal_path, &bytes).unwrap();
+        let wal = Wal::open(&wal_path).unwrap();
+        let read: Vec<Result<(Lsn, WalRecord)>> = wal.records().unwrap().collect();
+        assert_eq!(read.len(), 2);
+        assert!(read[0].is_ok());
+        assert!(matches!(
+            read[1],
+            Err(StorageError::CorruptWalRecord { lsn, .. }) if lsn == second
+        ));
+
+        bytes.truncate(second as usize + 20);
+        std::fs::write(&wal_path, &bytes).unwrap();
+        let wal = Wal::open(&wal_path).unwrap();
+        let read: Vec<Result<(Lsn, WalRecord)>> = wal.records().unwrap().collect();
+        assert!(matches!(
+            read[1],
+            Err(StorageError::CorruptWalRecord { lsn, .. }) if lsn == second
+        ));
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_reset_keeps_lsns_increasing() {
+        let wal_path = temp_path("wal_reset");
+        let _ = std::fs::remove_file(&wal_path);
+
+        let mut wal = Wal::open(&wal_path).unwrap();
+        wal.append(&record(0, 1)).unwrap();
+        let next_lsn = wal.next_lsn();
+        wal.reset().unwrap();
+        assert_eq!(wal.records().unwrap().count(), 0);
+        assert_eq!(
+            std::fs::metadata(&wal_path).unwrap().len(),
+            WAL_HEADER_SIZE as u64
+        );
+        assert_eq!(wal.append(&record(2, 2)).unwrap(), next_lsn);
+        let after = wal.append(&record(3, 3)).unwrap();
+        drop(wal);
+
+        let mut wal = Wal::open(&wal_path).unwrap();
+        let read: Vec<(Lsn, WalRecord)> = wal.records().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read, [(next_lsn, record(2, 2)), (after, record(3, 3))]);
+        wal.truncate(after).unwrap();
+        assert_eq!(wal.records().unwrap().count(), 1);
+        assert_eq!(wal.append(&record(4, 4)).unwrap(), after);
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+}